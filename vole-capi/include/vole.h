@@ -0,0 +1,173 @@
+#ifndef VOLE_H
+#define VOLE_H
+
+/* Generated with cbindgen from vole-capi/src/lib.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The reasons a machine stops running.
+ */
+typedef enum VoleStopReason {
+  /**
+   * A halt instruction was executed.
+   */
+  VOLE_STOP_REASON_HALTED = 0,
+  /**
+   * An illegal instruction was fetched.
+   */
+  VOLE_STOP_REASON_ILLEGAL_INSTRUCTION = 1,
+  /**
+   * A memory access raised a page fault.
+   */
+  VOLE_STOP_REASON_PAGE_FAULT = 2,
+  /**
+   * The cycle limit was reached before the machine halted.
+   */
+  VOLE_STOP_REASON_CYCLE_LIMIT = 3,
+  /**
+   * The simulator panicked, the machine is left in an unspecified state.
+   */
+  VOLE_STOP_REASON_PANICKED = 4,
+} VoleStopReason;
+
+/**
+ * A Vole machine, only used through pointers.
+ */
+typedef struct VoleCpu VoleCpu;
+
+/**
+ * Creates a new machine with empty memory and registers.
+ * It has to be destroyed with [vole_cpu_free].
+ */
+struct VoleCpu *vole_cpu_new(void);
+
+/**
+ * Destroys a machine created with [vole_cpu_new].
+ *
+ * # Safety
+ *
+ * `cpu` has to be null or a pointer returned by [vole_cpu_new] which was not destroyed yet.
+ */
+void vole_cpu_free(struct VoleCpu *cpu);
+
+/**
+ * Resets the machine and loads the program of `len` bytes into memory.
+ * Returns false if the program does not fit into memory, true otherwise.
+ *
+ * # Safety
+ *
+ * `cpu` has to be a valid machine and `program` has to point to `len` readable bytes.
+ */
+bool vole_cpu_load(struct VoleCpu *cpu, const uint8_t *program, size_t len);
+
+/**
+ * Does a full fetch-decode-execute cycle.
+ * Returns false if the instruction was illegal, raised a page fault or the simulator panicked,
+ * true otherwise.
+ *
+ * # Safety
+ *
+ * `cpu` has to be a valid machine.
+ */
+bool vole_cpu_step(struct VoleCpu *cpu);
+
+/**
+ * Runs till halt or till `max_cycles` cycles have been executed.
+ * A panic of the simulator doesn't unwind into C, it is reported as [VoleStopReason::Panicked].
+ *
+ * # Safety
+ *
+ * `cpu` has to be a valid machine.
+ */
+enum VoleStopReason vole_cpu_run(struct VoleCpu *cpu, uint32_t max_cycles);
+
+/**
+ * Returns true if the machine has halted.
+ *
+ * # Safety
+ *
+ * `cpu` has to be a valid machine.
+ */
+bool vole_cpu_halted(const struct VoleCpu *cpu);
+
+/**
+ * Returns the address of the next instruction.
+ *
+ * # Safety
+ *
+ * `cpu` has to be a valid machine.
+ */
+uint8_t vole_cpu_program_counter(const struct VoleCpu *cpu);
+
+/**
+ * Returns the number of executed cycles.
+ *
+ * # Safety
+ *
+ * `cpu` has to be a valid machine.
+ */
+uint32_t vole_cpu_cycles(const struct VoleCpu *cpu);
+
+/**
+ * Gets the value of register `reg`, only the lower four bits of `reg` are used.
+ *
+ * # Safety
+ *
+ * `cpu` has to be a valid machine.
+ */
+uint8_t vole_cpu_get_register(const struct VoleCpu *cpu, uint8_t reg);
+
+/**
+ * Sets register `reg` to `value`, only the lower four bits of `reg` are used.
+ *
+ * # Safety
+ *
+ * `cpu` has to be a valid machine.
+ */
+void vole_cpu_set_register(struct VoleCpu *cpu, uint8_t reg, uint8_t value);
+
+/**
+ * Gets the value of the memory cell at `addr`.
+ *
+ * # Safety
+ *
+ * `cpu` has to be a valid machine.
+ */
+uint8_t vole_cpu_get_memory(const struct VoleCpu *cpu, uint8_t addr);
+
+/**
+ * Sets the memory cell at `addr` to `value`.
+ *
+ * # Safety
+ *
+ * `cpu` has to be a valid machine.
+ */
+void vole_cpu_set_memory(struct VoleCpu *cpu, uint8_t addr, uint8_t value);
+
+/**
+ * Writes the text of the instruction, e.g. `STORE 0x04 0x17`, null terminated into `buf`.
+ * The text is truncated to fit into `len` bytes.
+ * Returns the length of the whole text without the terminator, 0 if the instruction is illegal.
+ *
+ * # Safety
+ *
+ * `buf` has to point to `len` writable bytes, it may be null if `len` is 0.
+ */
+size_t vole_decode_instruction(uint16_t instruction, char *buf, size_t len);
+
+/**
+ * Encodes a float into the eight bit floating point format.
+ */
+uint8_t vole_floating_encode(float value);
+
+/**
+ * Decodes a value in the eight bit floating point format.
+ */
+float vole_floating_decode(uint8_t value);
+
+#endif  /* VOLE_H */
@@ -0,0 +1,273 @@
+//! A C API for vole-rs.
+//!
+//! The header `include/vole.h` is generated from this file with
+//! `cbindgen --config cbindgen.toml --output include/vole.h`.
+
+use std::ffi::c_char;
+use std::panic::{self, AssertUnwindSafe};
+
+use vole_rs::floating::Floating;
+use vole_rs::vole::{Cpu, StopReason};
+
+/// A Vole machine, only used through pointers.
+pub struct VoleCpu {
+    cpu: Cpu,
+}
+
+/// The reasons a machine stops running.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoleStopReason {
+    /// A halt instruction was executed.
+    Halted = 0,
+    /// An illegal instruction was fetched.
+    IllegalInstruction = 1,
+    /// A memory access raised a page fault.
+    PageFault = 2,
+    /// The cycle limit was reached before the machine halted.
+    CycleLimit = 3,
+    /// The simulator panicked, the machine is left in an unspecified state.
+    Panicked = 4,
+}
+
+impl From<StopReason> for VoleStopReason {
+    fn from(reason: StopReason) -> Self {
+        match reason {
+            StopReason::Halted => VoleStopReason::Halted,
+            StopReason::IllegalInstruction => VoleStopReason::IllegalInstruction,
+            StopReason::PageFault => VoleStopReason::PageFault,
+            StopReason::CycleLimit => VoleStopReason::CycleLimit,
+        }
+    }
+}
+
+/// Creates a new machine with empty memory and registers.
+/// It has to be destroyed with [vole_cpu_free].
+#[unsafe(no_mangle)]
+pub extern "C" fn vole_cpu_new() -> *mut VoleCpu {
+    Box::into_raw(Box::new(VoleCpu { cpu: Cpu::new() }))
+}
+
+/// Destroys a machine created with [vole_cpu_new].
+///
+/// # Safety
+///
+/// `cpu` has to be null or a pointer returned by [vole_cpu_new] which was not destroyed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vole_cpu_free(cpu: *mut VoleCpu) {
+    if !cpu.is_null() {
+        drop(unsafe { Box::from_raw(cpu) });
+    }
+}
+
+/// Resets the machine and loads the program of `len` bytes into memory.
+/// Returns false if the program does not fit into memory, true otherwise.
+///
+/// # Safety
+///
+/// `cpu` has to be a valid machine and `program` has to point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vole_cpu_load(cpu: *mut VoleCpu, program: *const u8, len: usize) -> bool {
+    let cpu = unsafe { &mut *cpu };
+    if len > cpu.cpu.memory.len() {
+        return false;
+    }
+    let program = if len == 0 {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(program, len) }
+    };
+    cpu.cpu = Cpu::init(program);
+    true
+}
+
+/// Does a full fetch-decode-execute cycle.
+/// Returns false if the instruction was illegal, raised a page fault or the simulator panicked,
+/// true otherwise.
+///
+/// # Safety
+///
+/// `cpu` has to be a valid machine.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vole_cpu_step(cpu: *mut VoleCpu) -> bool {
+    let cpu = unsafe { &mut *cpu };
+    panic::catch_unwind(AssertUnwindSafe(|| cpu.cpu.cycle())).unwrap_or(false)
+}
+
+/// Runs till halt or till `max_cycles` cycles have been executed.
+/// A panic of the simulator doesn't unwind into C, it is reported as [VoleStopReason::Panicked].
+///
+/// # Safety
+///
+/// `cpu` has to be a valid machine.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vole_cpu_run(cpu: *mut VoleCpu, max_cycles: u32) -> VoleStopReason {
+    let cpu = unsafe { &mut *cpu };
+    panic::catch_unwind(AssertUnwindSafe(|| cpu.cpu.run_for(max_cycles).into()))
+        .unwrap_or(VoleStopReason::Panicked)
+}
+
+/// Returns true if the machine has halted.
+///
+/// # Safety
+///
+/// `cpu` has to be a valid machine.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vole_cpu_halted(cpu: *const VoleCpu) -> bool {
+    unsafe { &*cpu }.cpu.halted
+}
+
+/// Returns the address of the next instruction.
+///
+/// # Safety
+///
+/// `cpu` has to be a valid machine.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vole_cpu_program_counter(cpu: *const VoleCpu) -> u8 {
+    unsafe { &*cpu }.cpu.program_counter as u8
+}
+
+/// Returns the number of executed cycles.
+///
+/// # Safety
+///
+/// `cpu` has to be a valid machine.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vole_cpu_cycles(cpu: *const VoleCpu) -> u32 {
+    unsafe { &*cpu }.cpu.cycle
+}
+
+/// Gets the value of register `reg`, only the lower four bits of `reg` are used.
+///
+/// # Safety
+///
+/// `cpu` has to be a valid machine.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vole_cpu_get_register(cpu: *const VoleCpu, reg: u8) -> u8 {
+    unsafe { &*cpu }.cpu.registers[(reg & 0x0F) as usize]
+}
+
+/// Sets register `reg` to `value`, only the lower four bits of `reg` are used.
+///
+/// # Safety
+///
+/// `cpu` has to be a valid machine.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vole_cpu_set_register(cpu: *mut VoleCpu, reg: u8, value: u8) {
+    unsafe { &mut *cpu }.cpu.registers[(reg & 0x0F) as usize] = value;
+}
+
+/// Gets the value of the memory cell at `addr`.
+///
+/// # Safety
+///
+/// `cpu` has to be a valid machine.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vole_cpu_get_memory(cpu: *const VoleCpu, addr: u8) -> u8 {
+    unsafe { &*cpu }.cpu.memory[addr as usize]
+}
+
+/// Sets the memory cell at `addr` to `value`.
+///
+/// # Safety
+///
+/// `cpu` has to be a valid machine.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vole_cpu_set_memory(cpu: *mut VoleCpu, addr: u8, value: u8) {
+    unsafe { &mut *cpu }.cpu.memory[addr as usize] = value;
+}
+
+/// Writes the text of the instruction, e.g. `STORE 0x04 0x17`, null terminated into `buf`.
+/// The text is truncated to fit into `len` bytes.
+/// Returns the length of the whole text without the terminator, 0 if the instruction is illegal.
+///
+/// # Safety
+///
+/// `buf` has to point to `len` writable bytes, it may be null if `len` is 0.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vole_decode_instruction(
+    instruction: u16,
+    buf: *mut c_char,
+    len: usize,
+) -> usize {
+    let Some(opcode) = Cpu::decode_instruction(instruction) else {
+        return 0;
+    };
+    let text = opcode.to_string();
+    if len > 0 {
+        let n = text.len().min(len - 1);
+        unsafe {
+            std::ptr::copy_nonoverlapping(text.as_ptr(), buf.cast::<u8>(), n);
+            *buf.add(n) = 0;
+        }
+    }
+    text.len()
+}
+
+/// Encodes a float into the eight bit floating point format.
+#[unsafe(no_mangle)]
+pub extern "C" fn vole_floating_encode(value: f32) -> u8 {
+    Floating::encode(value).value
+}
+
+/// Decodes a value in the eight bit floating point format.
+#[unsafe(no_mangle)]
+pub extern "C" fn vole_floating_decode(value: u8) -> f32 {
+    Floating { value }.decode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn run_works() {
+        let program = [0x14, 0x02, 0x34, 0x17, 0xC0, 0x00];
+        unsafe {
+            let cpu = vole_cpu_new();
+            assert!(vole_cpu_load(cpu, program.as_ptr(), program.len()));
+            assert!(vole_cpu_step(cpu));
+            assert_eq!(vole_cpu_get_register(cpu, 4), 0x34);
+            assert_eq!(vole_cpu_run(cpu, 10), VoleStopReason::Halted);
+            assert_eq!(vole_cpu_get_memory(cpu, 0x17), 0x34);
+            assert_eq!(vole_cpu_cycles(cpu), 3);
+            assert!(vole_cpu_halted(cpu));
+            vole_cpu_free(cpu);
+        }
+    }
+
+    #[test]
+    pub fn run_with_overflowing_addition_works() {
+        let program = [0x21, 0xFF, 0x22, 0x01, 0x53, 0x12, 0xC0, 0x00];
+        unsafe {
+            let cpu = vole_cpu_new();
+            assert!(vole_cpu_load(cpu, program.as_ptr(), program.len()));
+            assert_eq!(vole_cpu_run(cpu, 10), VoleStopReason::Halted);
+            assert_eq!(vole_cpu_get_register(cpu, 3), 0x00);
+            vole_cpu_free(cpu);
+        }
+    }
+
+    #[test]
+    pub fn load_with_too_large_program_fails() {
+        let program = [0; 257];
+        unsafe {
+            let cpu = vole_cpu_new();
+            assert!(!vole_cpu_load(cpu, program.as_ptr(), program.len()));
+            vole_cpu_free(cpu);
+        }
+    }
+
+    #[test]
+    pub fn decode_instruction_works() {
+        let mut buf = [0 as c_char; 8];
+        let len = unsafe { vole_decode_instruction(0x3417, buf.as_mut_ptr(), buf.len()) };
+        assert_eq!(len, "STORE 0x04 0x17".len());
+        let text = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+        assert_eq!(text.to_str(), Ok("STORE 0"));
+        assert_eq!(
+            unsafe { vole_decode_instruction(0xF000, buf.as_mut_ptr(), 0) },
+            0
+        );
+    }
+}
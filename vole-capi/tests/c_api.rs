@@ -0,0 +1,37 @@
+//! Builds the C test program against the static library and runs it.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[cfg(unix)]
+#[test]
+fn c_test_program_passes() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // The test binary lives in target/<profile>/deps, the library in target/<profile>.
+    let exe = env::current_exe().unwrap();
+    let lib_dir = exe.parent().unwrap().parent().unwrap();
+    let out = lib_dir.join("test_vole");
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+
+    let status = Command::new(cc)
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg(manifest_dir.join("tests/test_vole.c"))
+        .arg(lib_dir.join("libvole.a"))
+        .args(["-lpthread", "-ldl", "-lm"])
+        .arg("-o")
+        .arg(&out)
+        .status()
+        .expect("failed to run the C compiler");
+    assert!(status.success(), "compiling the C test program failed");
+
+    let output = Command::new(&out).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,60 @@
+/* Exercises the C API of vole-rs, exits with 0 if all checks pass. */
+#include <stdio.h>
+#include <string.h>
+
+#include "vole.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                     \
+    do {                                                                \
+        if (!(cond)) {                                                  \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,      \
+                    __LINE__, #cond);                                   \
+            failures++;                                                 \
+        }                                                               \
+    } while (0)
+
+int main(void) {
+    const uint8_t program[] = {0x14, 0x02, 0x34, 0x17, 0xC0, 0x00};
+    const uint8_t endless[] = {0xB0, 0x02, 0x00};
+    uint8_t too_large[257] = {0};
+    char text[32];
+
+    VoleCpu *cpu = vole_cpu_new();
+    CHECK(cpu != NULL);
+    CHECK(!vole_cpu_load(cpu, too_large, sizeof too_large));
+
+    CHECK(vole_cpu_load(cpu, program, sizeof program));
+    CHECK(vole_cpu_step(cpu));
+    CHECK(vole_cpu_get_register(cpu, 4) == 0x34);
+    CHECK(vole_cpu_program_counter(cpu) == 2);
+    CHECK(vole_cpu_run(cpu, 10) == VOLE_STOP_REASON_HALTED);
+    CHECK(vole_cpu_halted(cpu));
+    CHECK(vole_cpu_cycles(cpu) == 3);
+    CHECK(vole_cpu_get_memory(cpu, 0x17) == 0x34);
+
+    vole_cpu_set_register(cpu, 0xA, 0x42);
+    CHECK(vole_cpu_get_register(cpu, 0xA) == 0x42);
+    vole_cpu_set_memory(cpu, 0xFF, 0x99);
+    CHECK(vole_cpu_get_memory(cpu, 0xFF) == 0x99);
+
+    CHECK(vole_cpu_load(cpu, endless, sizeof endless));
+    CHECK(vole_cpu_run(cpu, 100) == VOLE_STOP_REASON_CYCLE_LIMIT);
+    CHECK(vole_cpu_cycles(cpu) == 100);
+    vole_cpu_free(cpu);
+
+    CHECK(vole_decode_instruction(0x3417, text, sizeof text) == strlen("STORE 0x04 0x17"));
+    CHECK(strcmp(text, "STORE 0x04 0x17") == 0);
+    CHECK(vole_decode_instruction(0xC000, text, 3) == strlen("HALT"));
+    CHECK(strcmp(text, "HA") == 0);
+    CHECK(vole_decode_instruction(0xF000, text, sizeof text) == 0);
+
+    CHECK(vole_floating_decode(0x6B) == 2.75f);
+    CHECK(vole_floating_encode(2.75f) == 0x6B);
+
+    if (failures == 0) {
+        printf("all checks passed\n");
+    }
+    return failures == 0 ? 0 : 1;
+}
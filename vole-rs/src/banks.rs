@@ -0,0 +1,111 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// A bank-switching device mapping one of several memory banks into a window of the address space.
+///
+/// Writing a bank number into the memory-mapped bank-select register at [BankSwitch::select_addr]
+/// stores the contents of the window into the current bank and maps the selected bank into the window.
+/// The memory of the [crate::vole::Cpu] always holds the currently mapped bank.
+#[derive(Debug, Clone)]
+pub struct BankSwitch {
+    /// The address of the bank-select register.
+    pub select_addr: u8,
+    /// The first address of the window.
+    pub window_start: u8,
+    /// The number of bytes of the window and of each bank.
+    pub window_len: usize,
+    /// The contents of all banks, the one of the current bank is outdated till it gets unmapped.
+    pub banks: Vec<Vec<u8>>,
+    /// The bank mapped into the window.
+    pub current: usize,
+}
+
+impl BankSwitch {
+    /// Creates a new [BankSwitch] with the given number of empty banks and bank 0 mapped.
+    pub fn new(select_addr: u8, window_start: u8, window_len: usize, bank_count: usize) -> Self {
+        if bank_count == 0 || bank_count > 256 {
+            panic!("bank count must be between 1 and 256");
+        }
+        if window_len == 0 || window_start as usize + window_len > 256 {
+            panic!("bank window does not fit into memory");
+        }
+        let bank_switch = BankSwitch {
+            select_addr,
+            window_start,
+            window_len,
+            banks: vec![vec![0; window_len]; bank_count],
+            current: 0,
+        };
+        if bank_switch.window().contains(&(select_addr as usize)) {
+            panic!("bank-select register must not lie in the bank window");
+        }
+        bank_switch
+    }
+
+    /// Gets the addresses of the window.
+    pub fn window(&self) -> Range<usize> {
+        self.window_start as usize..self.window_start as usize + self.window_len
+    }
+
+    /// Maps the given bank into the window, wrapping around the number of banks.
+    /// The contents of the window are stored into the previously mapped bank.
+    pub fn select(&mut self, memory: &mut [u8; 256], bank: usize) {
+        let bank = bank % self.banks.len();
+        let window = self.window();
+        self.banks[self.current].copy_from_slice(&memory[window.clone()]);
+        memory[window].copy_from_slice(&self.banks[bank]);
+        memory[self.select_addr as usize] = bank as u8;
+        self.current = bank;
+    }
+
+    /// Gets the contents of the given bank, None if there is no such bank.
+    pub fn bank<'a>(&'a self, memory: &'a [u8; 256], bank: usize) -> Option<&'a [u8]> {
+        if bank == self.current {
+            Some(&memory[self.window()])
+        } else {
+            self.banks.get(bank).map(|b| b.as_slice())
+        }
+    }
+
+    /// Gets the largest program image that fits into memory and the banks.
+    pub fn capacity(&self) -> usize {
+        256 + (self.banks.len() - 1) * self.window_len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn select_swaps_window() {
+        let mut memory = [0; 256];
+        let mut banks = BankSwitch::new(0x7F, 0x80, 0x80, 4);
+        memory[0x80] = 0xAA;
+        banks.select(&mut memory, 2);
+        assert_eq!(memory[0x80], 0x00);
+        assert_eq!(memory[0x7F], 0x02);
+        assert_eq!(banks.bank(&memory, 0).unwrap()[0], 0xAA);
+        memory[0x81] = 0xBB;
+        banks.select(&mut memory, 0);
+        assert_eq!(memory[0x80], 0xAA);
+        assert_eq!(banks.bank(&memory, 2).unwrap()[1], 0xBB);
+        assert!(banks.bank(&memory, 4).is_none());
+    }
+
+    #[test]
+    pub fn select_wraps_around() {
+        let mut memory = [0; 256];
+        let mut banks = BankSwitch::new(0x0F, 0xC0, 0x40, 3);
+        banks.select(&mut memory, 4);
+        assert_eq!(banks.current, 1);
+        assert_eq!(memory[0x0F], 0x01);
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn select_register_in_window_panics() {
+        BankSwitch::new(0x90, 0x80, 0x80, 2);
+    }
+}
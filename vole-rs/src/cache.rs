@@ -0,0 +1,359 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::vole::Access;
+
+/// When a write reaches main memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Every write goes to main memory right away. Write misses don't allocate a line.
+    WriteThrough,
+    /// Writes only mark the line dirty, it is written back on eviction. Write misses allocate a line.
+    WriteBack,
+}
+
+/// Which line of a set is evicted on a miss.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replacement {
+    /// Evict the least recently used line.
+    Lru,
+    /// Evict the line that was loaded first.
+    Fifo,
+}
+
+/// The geometry and policies of a [Cache].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// The number of sets.
+    pub sets: usize,
+    /// The number of lines per set, 1 for a direct-mapped cache.
+    pub ways: usize,
+    /// The number of bytes per line.
+    pub line_size: usize,
+    /// When writes reach main memory.
+    pub write_policy: WritePolicy,
+    /// Which line of a set is evicted on a miss.
+    pub replacement: Replacement,
+}
+
+impl CacheConfig {
+    /// Creates the config of a direct-mapped cache.
+    pub const fn direct_mapped(lines: usize, line_size: usize, write_policy: WritePolicy) -> Self {
+        CacheConfig {
+            sets: lines,
+            ways: 1,
+            line_size,
+            write_policy,
+            replacement: Replacement::Lru,
+        }
+    }
+
+    /// Creates the config of a set-associative cache.
+    pub const fn set_associative(
+        sets: usize,
+        ways: usize,
+        line_size: usize,
+        write_policy: WritePolicy,
+        replacement: Replacement,
+    ) -> Self {
+        CacheConfig {
+            sets,
+            ways,
+            line_size,
+            write_policy,
+            replacement,
+        }
+    }
+}
+
+/// A line of the [Cache].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheLine {
+    /// True if the line holds a block of memory.
+    pub valid: bool,
+    /// True if the line was written but not yet written back.
+    pub dirty: bool,
+    /// The tag of the cached block.
+    pub tag: usize,
+    /// The time the line was loaded, used for FIFO replacement.
+    pub loaded_at: u64,
+    /// The time the line was last accessed, used for LRU replacement.
+    pub used_at: u64,
+}
+
+/// Counts the outcomes of the accesses of one [Access] kind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Accesses that found their block in the cache.
+    pub hits: u32,
+    /// Accesses that didn't find their block in the cache.
+    pub misses: u32,
+    /// Valid lines replaced on behalf of these accesses.
+    pub evictions: u32,
+}
+
+/// The outcome of a single access to the [Cache].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheAccess {
+    /// The accessed physical address.
+    pub addr: u8,
+    /// The kind of access.
+    pub access: Access,
+    /// The set the address maps to.
+    pub set: usize,
+    /// The line within the set that was used, None for a write miss without allocation.
+    pub way: Option<usize>,
+    /// True if the block was cached.
+    pub hit: bool,
+    /// True if a valid line was evicted.
+    pub evicted: bool,
+}
+
+/// A cache between the [crate::vole::Cpu] and its main memory.
+///
+/// The cache only models tags and line states to count hits, misses and evictions.
+/// The data always lives in main memory.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    /// The geometry and policies.
+    pub config: CacheConfig,
+    /// The lines, set after set.
+    pub lines: Vec<CacheLine>,
+    /// Statistics for fetches.
+    pub fetch_stats: CacheStats,
+    /// Statistics for loads.
+    pub load_stats: CacheStats,
+    /// Statistics for stores.
+    pub store_stats: CacheStats,
+    /// Counts the writes that reached main memory.
+    pub memory_writes: u32,
+    /// The accesses of the current cycle.
+    pub recent: Vec<CacheAccess>,
+    /// Counts all accesses, used as clock for the replacement policies.
+    clock: u64,
+}
+
+impl Cache {
+    /// Creates a new empty [Cache].
+    pub fn new(config: CacheConfig) -> Self {
+        if config.sets == 0 || config.ways == 0 || config.line_size == 0 {
+            panic!("cache needs at least one set, one way and one byte per line");
+        }
+        Cache {
+            config,
+            lines: vec![CacheLine::default(); config.sets * config.ways],
+            fetch_stats: CacheStats::default(),
+            load_stats: CacheStats::default(),
+            store_stats: CacheStats::default(),
+            memory_writes: 0,
+            recent: Vec::new(),
+            clock: 0,
+        }
+    }
+
+    /// Gets the set an address maps to.
+    pub fn set_of(&self, addr: u8) -> usize {
+        (addr as usize / self.config.line_size) % self.config.sets
+    }
+
+    /// Gets the tag of an address.
+    pub fn tag_of(&self, addr: u8) -> usize {
+        addr as usize / self.config.line_size / self.config.sets
+    }
+
+    /// Gets the first address of the block cached in the given line.
+    pub fn line_addr(&self, set: usize, line: &CacheLine) -> usize {
+        (line.tag * self.config.sets + set) * self.config.line_size
+    }
+
+    /// Gets the lines of the given set.
+    pub fn set(&self, set: usize) -> &[CacheLine] {
+        let start = set * self.config.ways;
+        &self.lines[start..start + self.config.ways]
+    }
+
+    /// Gets the statistics for the given kind of access.
+    pub fn stats(&self, access: Access) -> &CacheStats {
+        match access {
+            Access::Fetch => &self.fetch_stats,
+            Access::Load => &self.load_stats,
+            Access::Store => &self.store_stats,
+        }
+    }
+
+    fn stats_mut(&mut self, access: Access) -> &mut CacheStats {
+        match access {
+            Access::Fetch => &mut self.fetch_stats,
+            Access::Load => &mut self.load_stats,
+            Access::Store => &mut self.store_stats,
+        }
+    }
+
+    /// Picks the line of the set to replace: an invalid one or the one chosen by the policy.
+    fn victim(&self, set: usize) -> usize {
+        let lines = self.set(set);
+        if let Some(way) = lines.iter().position(|l| !l.valid) {
+            return way;
+        }
+        let age = |l: &CacheLine| match self.config.replacement {
+            Replacement::Lru => l.used_at,
+            Replacement::Fifo => l.loaded_at,
+        };
+        lines
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, l)| age(l))
+            .map(|(way, _)| way)
+            .unwrap_or(0)
+    }
+
+    /// Simulates an access to the given physical address and returns its outcome.
+    pub fn access(&mut self, addr: u8, access: Access) -> CacheAccess {
+        self.clock += 1;
+        let set = self.set_of(addr);
+        let tag = self.tag_of(addr);
+        let ways = self.config.ways;
+        let write = access == Access::Store;
+        let write_back = self.config.write_policy == WritePolicy::WriteBack;
+
+        let hit_way = self.set(set).iter().position(|l| l.valid && l.tag == tag);
+
+        let outcome = match hit_way {
+            Some(way) => {
+                self.stats_mut(access).hits += 1;
+                let clock = self.clock;
+                let line = &mut self.lines[set * ways + way];
+                line.used_at = clock;
+                if write && write_back {
+                    line.dirty = true;
+                }
+                CacheAccess {
+                    addr,
+                    access,
+                    set,
+                    way: Some(way),
+                    hit: true,
+                    evicted: false,
+                }
+            }
+            None => {
+                self.stats_mut(access).misses += 1;
+                if write && !write_back {
+                    CacheAccess {
+                        addr,
+                        access,
+                        set,
+                        way: None,
+                        hit: false,
+                        evicted: false,
+                    }
+                } else {
+                    let way = self.victim(set);
+                    let old = self.lines[set * ways + way];
+                    if old.valid {
+                        self.stats_mut(access).evictions += 1;
+                        if old.dirty {
+                            self.memory_writes += 1;
+                        }
+                    }
+                    self.lines[set * ways + way] = CacheLine {
+                        valid: true,
+                        dirty: write && write_back,
+                        tag,
+                        loaded_at: self.clock,
+                        used_at: self.clock,
+                    };
+                    CacheAccess {
+                        addr,
+                        access,
+                        set,
+                        way: Some(way),
+                        hit: false,
+                        evicted: old.valid,
+                    }
+                }
+            }
+        };
+
+        if write && !write_back {
+            self.memory_writes += 1;
+        }
+        self.recent.push(outcome);
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn direct_mapped_works() {
+        let mut cache = Cache::new(CacheConfig::direct_mapped(4, 2, WritePolicy::WriteThrough));
+        assert!(!cache.access(0x00, Access::Fetch).hit);
+        assert!(cache.access(0x01, Access::Fetch).hit);
+        assert!(!cache.access(0x08, Access::Load).hit);
+        assert!(cache.access(0x08, Access::Load).hit);
+        let a = cache.access(0x00, Access::Fetch);
+        assert!(!a.hit);
+        assert!(a.evicted);
+        assert_eq!(
+            cache.fetch_stats,
+            CacheStats {
+                hits: 1,
+                misses: 2,
+                evictions: 1
+            }
+        );
+        assert_eq!(cache.load_stats.evictions, 1);
+    }
+
+    #[test]
+    pub fn write_through_does_not_allocate() {
+        let mut cache = Cache::new(CacheConfig::direct_mapped(4, 2, WritePolicy::WriteThrough));
+        let a = cache.access(0x10, Access::Store);
+        assert!(!a.hit);
+        assert_eq!(a.way, None);
+        assert!(!cache.access(0x10, Access::Load).hit);
+        cache.access(0x10, Access::Store);
+        assert_eq!(cache.memory_writes, 2);
+    }
+
+    #[test]
+    pub fn write_back_writes_dirty_lines_on_eviction() {
+        let mut cache = Cache::new(CacheConfig::direct_mapped(1, 4, WritePolicy::WriteBack));
+        cache.access(0x10, Access::Store);
+        cache.access(0x11, Access::Store);
+        assert!(cache.lines[0].dirty);
+        assert_eq!(cache.memory_writes, 0);
+        cache.access(0x20, Access::Load);
+        assert_eq!(cache.memory_writes, 1);
+        assert!(!cache.lines[0].dirty);
+    }
+
+    #[test]
+    pub fn lru_replacement_works() {
+        let config =
+            CacheConfig::set_associative(1, 2, 1, WritePolicy::WriteBack, Replacement::Lru);
+        let mut cache = Cache::new(config);
+        cache.access(0x01, Access::Load);
+        cache.access(0x02, Access::Load);
+        cache.access(0x01, Access::Load);
+        cache.access(0x03, Access::Load);
+        assert!(cache.access(0x01, Access::Load).hit);
+        assert!(!cache.access(0x02, Access::Load).hit);
+    }
+
+    #[test]
+    pub fn fifo_replacement_works() {
+        let config =
+            CacheConfig::set_associative(1, 2, 1, WritePolicy::WriteBack, Replacement::Fifo);
+        let mut cache = Cache::new(config);
+        cache.access(0x01, Access::Load);
+        cache.access(0x02, Access::Load);
+        cache.access(0x01, Access::Load);
+        cache.access(0x03, Access::Load);
+        assert!(!cache.access(0x01, Access::Load).hit);
+        assert!(cache.access(0x03, Access::Load).hit);
+    }
+}
@@ -65,7 +65,7 @@ impl Floating {
     pub fn encode(value: f32) -> Floating {
         let sign = if value < 0.0 { 1 } else { 0 };
         let int_value = (value as i8).abs();
-        let fract_value = value.fract().abs();
+        let fract_value = (value - (value as i32) as f32).abs();
 
         let exponent_value = if int_value >= 4 {
             0x07 // 3
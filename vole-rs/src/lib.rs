@@ -1,2 +1,15 @@
+//! An implementation of the Vole machine language.
+//!
+//! The crate is `no_std` if the default `std` feature is disabled, it only needs `alloc`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod banks;
+pub mod cache;
 pub mod floating;
+pub mod mmu;
+pub mod multicore;
+pub mod pipeline;
 pub mod vole;
@@ -0,0 +1,315 @@
+use alloc::vec::Vec;
+
+use crate::vole::Access;
+
+/// The number of bytes in a page and in a frame.
+pub const PAGE_SIZE: usize = 16;
+
+/// The number of pages in the virtual address space.
+pub const PAGE_COUNT: usize = 256 / PAGE_SIZE;
+
+/// The bit marking a page table entry as valid.
+pub const PTE_VALID: u8 = 0x80;
+
+/// The bits of a page table entry holding the frame number.
+pub const PTE_FRAME: u8 = 0x0F;
+
+/// A memory management unit translating virtual into physical addresses.
+///
+/// A virtual address consists of a 4 bit page number and a 4 bit offset.
+/// The page table is stored in physical memory at [Mmu::page_table_base] and holds
+/// one byte per page: the [PTE_VALID] bit and the frame number in the low nibble.
+#[derive(Debug, Clone)]
+pub struct Mmu {
+    /// The physical address of the page table.
+    pub page_table_base: u8,
+    /// The translation lookaside buffer.
+    pub tlb: Tlb,
+    /// The most recent successful translation.
+    pub last_translation: Option<Translation>,
+    /// The page fault raised by the most recent translation, if any.
+    pub fault: Option<PageFault>,
+}
+
+impl Mmu {
+    /// Creates a new [Mmu] with the page table at the given physical address
+    /// and a [Tlb] with the given number of entries.
+    pub fn new(page_table_base: u8, tlb_size: usize) -> Self {
+        Mmu {
+            page_table_base,
+            tlb: Tlb::new(tlb_size),
+            last_translation: None,
+            fault: None,
+        }
+    }
+
+    /// Gets the page number of a virtual address.
+    pub fn page(addr: u8) -> u8 {
+        addr >> 4
+    }
+
+    /// Gets the offset of a virtual address into its page.
+    pub fn offset(addr: u8) -> u8 {
+        addr & 0x0F
+    }
+
+    /// Gets the physical address of the page table entry for the given page.
+    pub fn entry_addr(&self, page: u8) -> u8 {
+        self.page_table_base.wrapping_add(page)
+    }
+
+    /// Writes a page table into memory which maps every page onto the frame with the same number.
+    /// The page containing the page table itself is left unmapped.
+    /// Panics if the page table doesn't start at a page boundary.
+    pub fn write_identity_table(&self, memory: &mut [u8; 256]) {
+        if Mmu::offset(self.page_table_base) != 0 {
+            panic!("page table must start at a page boundary");
+        }
+        let table_page = Mmu::page(self.page_table_base);
+        for page in 0..PAGE_COUNT as u8 {
+            let entry = if page == table_page {
+                0x00
+            } else {
+                PTE_VALID | page
+            };
+            memory[self.entry_addr(page) as usize] = entry;
+        }
+    }
+
+    /// Translates the virtual address into a physical one.
+    /// Consults the [Tlb] first and walks the page table in memory on a miss.
+    /// Returns None and records a [PageFault] if the page is not mapped.
+    pub fn translate(&mut self, memory: &[u8; 256], addr: u8, access: Access) -> Option<u8> {
+        let page = Mmu::page(addr);
+        let offset = Mmu::offset(addr);
+        let (frame, tlb_hit) = match self.tlb.lookup(page) {
+            Some(frame) => (frame, true),
+            None => {
+                let entry = memory[self.entry_addr(page) as usize];
+                if entry & PTE_VALID == 0 {
+                    self.fault = Some(PageFault {
+                        virtual_addr: addr,
+                        page,
+                        access,
+                    });
+                    return None;
+                }
+                let frame = entry & PTE_FRAME;
+                self.tlb.insert(page, frame);
+                (frame, false)
+            }
+        };
+        let physical_addr = (frame << 4) | offset;
+        self.last_translation = Some(Translation {
+            virtual_addr: addr,
+            physical_addr,
+            page,
+            frame,
+            tlb_hit,
+            access,
+        });
+        Some(physical_addr)
+    }
+
+    /// Translates the virtual address like [Mmu::translate] would,
+    /// but without touching the [Tlb] or recording the translation or a fault.
+    pub fn peek(&self, memory: &[u8; 256], addr: u8) -> Option<u8> {
+        let page = Mmu::page(addr);
+        let frame = match self.tlb.entries.iter().find(|e| e.page == page) {
+            Some(entry) => entry.frame,
+            None => {
+                let entry = memory[self.entry_addr(page) as usize];
+                if entry & PTE_VALID == 0 {
+                    return None;
+                }
+                entry & PTE_FRAME
+            }
+        };
+        Some((frame << 4) | Mmu::offset(addr))
+    }
+}
+
+/// A translation lookaside buffer caching page table entries.
+/// Entries are replaced in first-in first-out order.
+#[derive(Debug, Clone)]
+pub struct Tlb {
+    /// The cached entries.
+    pub entries: Vec<TlbEntry>,
+    /// The maximum number of entries.
+    pub capacity: usize,
+    /// The index of the entry to replace next.
+    pub next_victim: usize,
+    /// Counts the lookups that found an entry.
+    pub hits: u32,
+    /// Counts the lookups that had to walk the page table.
+    pub misses: u32,
+}
+
+impl Tlb {
+    /// Creates a new empty [Tlb] with the given capacity.
+    pub fn new(capacity: usize) -> Self {
+        Tlb {
+            entries: Vec::with_capacity(capacity),
+            capacity,
+            next_victim: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Looks up the frame of the given page and counts the hit or miss.
+    pub fn lookup(&mut self, page: u8) -> Option<u8> {
+        match self.entries.iter().find(|e| e.page == page) {
+            Some(entry) => {
+                self.hits = self.hits.wrapping_add(1);
+                Some(entry.frame)
+            }
+            None => {
+                self.misses = self.misses.wrapping_add(1);
+                None
+            }
+        }
+    }
+
+    /// Caches the frame of the given page, replacing the oldest entry if full.
+    pub fn insert(&mut self, page: u8, frame: u8) {
+        if self.capacity == 0 {
+            return;
+        }
+        let entry = TlbEntry { page, frame };
+        if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+        } else {
+            self.entries[self.next_victim] = entry;
+            self.next_victim = (self.next_victim + 1) % self.capacity;
+        }
+    }
+
+    /// Drops all cached entries, e.g. after the page table was changed.
+    pub fn flush(&mut self) {
+        self.entries.clear();
+        self.next_victim = 0;
+    }
+}
+
+/// A cached page table entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlbEntry {
+    /// The virtual page number.
+    pub page: u8,
+    /// The physical frame number.
+    pub frame: u8,
+}
+
+/// The result of translating a virtual address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translation {
+    /// The translated virtual address.
+    pub virtual_addr: u8,
+    /// The resulting physical address.
+    pub physical_addr: u8,
+    /// The virtual page number.
+    pub page: u8,
+    /// The physical frame number.
+    pub frame: u8,
+    /// True if the [Tlb] held the entry, false if the page table was walked.
+    pub tlb_hit: bool,
+    /// The kind of access that was translated.
+    pub access: Access,
+}
+
+/// Raised when a virtual address lies in a page without a valid page table entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFault {
+    /// The virtual address that could not be translated.
+    pub virtual_addr: u8,
+    /// The unmapped page.
+    pub page: u8,
+    /// The kind of access that faulted.
+    pub access: Access,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn translate_works() {
+        let mut memory = [0; 256];
+        let mut mmu = Mmu::new(0xF0, 4);
+        memory[0xF2] = PTE_VALID | 0x07;
+        assert_eq!(mmu.translate(&memory, 0x2A, Access::Load), Some(0x7A));
+        let t = mmu.last_translation.unwrap();
+        assert_eq!(t.page, 0x02);
+        assert_eq!(t.frame, 0x07);
+        assert!(!t.tlb_hit);
+    }
+
+    #[test]
+    pub fn peek_leaves_state_alone() {
+        let mut memory = [0; 256];
+        let mmu = Mmu::new(0xF0, 4);
+        memory[0xF2] = PTE_VALID | 0x07;
+        assert_eq!(mmu.peek(&memory, 0x2A), Some(0x7A));
+        assert_eq!(mmu.peek(&memory, 0x3A), None);
+        assert!(mmu.fault.is_none());
+        assert!(mmu.last_translation.is_none());
+        assert_eq!((mmu.tlb.hits, mmu.tlb.misses), (0, 0));
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn identity_table_at_unaligned_base_panics() {
+        let mut memory = [0; 256];
+        Mmu::new(0xF8, 4).write_identity_table(&mut memory);
+    }
+
+    #[test]
+    pub fn translate_unmapped_page_faults() {
+        let memory = [0; 256];
+        let mut mmu = Mmu::new(0xF0, 4);
+        assert_eq!(mmu.translate(&memory, 0x31, Access::Store), None);
+        assert_eq!(
+            mmu.fault,
+            Some(PageFault {
+                virtual_addr: 0x31,
+                page: 0x03,
+                access: Access::Store
+            })
+        );
+    }
+
+    #[test]
+    pub fn tlb_counts_hits_and_misses() {
+        let mut memory = [0; 256];
+        let mut mmu = Mmu::new(0xF0, 4);
+        mmu.write_identity_table(&mut memory);
+        mmu.translate(&memory, 0x10, Access::Fetch);
+        mmu.translate(&memory, 0x11, Access::Fetch);
+        mmu.translate(&memory, 0x20, Access::Load);
+        assert_eq!(mmu.tlb.hits, 1);
+        assert_eq!(mmu.tlb.misses, 2);
+        assert_eq!(mmu.tlb.entries.len(), 2);
+    }
+
+    #[test]
+    pub fn tlb_replaces_oldest_entry() {
+        let mut tlb = Tlb::new(2);
+        tlb.insert(0x01, 0x01);
+        tlb.insert(0x02, 0x02);
+        tlb.insert(0x03, 0x03);
+        assert_eq!(tlb.lookup(0x01), None);
+        assert_eq!(tlb.lookup(0x02), Some(0x02));
+        assert_eq!(tlb.lookup(0x03), Some(0x03));
+    }
+
+    #[test]
+    pub fn identity_table_leaves_table_page_unmapped() {
+        let mut memory = [0; 256];
+        let mmu = Mmu::new(0xF0, 4);
+        mmu.write_identity_table(&mut memory);
+        assert_eq!(memory[0xF0], PTE_VALID);
+        assert_eq!(memory[0xFE], PTE_VALID | 0x0E);
+        assert_eq!(memory[0xFF], 0x00);
+    }
+}
@@ -0,0 +1,332 @@
+use alloc::vec::Vec;
+
+use crate::vole::{Cpu, OpCode};
+
+/// The register holding the number of the core when a [Multicore] starts.
+pub const CORE_ID_REGISTER: usize = 0x0F;
+
+/// Decides which core executes the next cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheduler {
+    /// The cores take turns.
+    RoundRobin,
+    /// A core is picked at random, the same seed gives the same interleaving.
+    Random { seed: u64 },
+    /// The user picks the core for every step.
+    Manual,
+}
+
+/// Several [Cpu] cores sharing one main memory.
+///
+/// Every core has its own registers, program counter and devices.
+/// After each cycle the memory of the stepped core is copied to all other cores,
+/// so every core always sees the same memory.
+/// The banks of a [crate::banks::BankSwitch] are shared the same way whenever a core switches banks.
+#[derive(Debug, Clone)]
+pub struct Multicore {
+    /// The cores.
+    pub cores: Vec<Cpu>,
+    /// Decides which core executes the next cycle.
+    pub scheduler: Scheduler,
+    /// The core that executes the next cycle, used by [Scheduler::Manual] and [Scheduler::RoundRobin].
+    pub selected: usize,
+    /// The core that wrote each memory cell last.
+    pub last_writer: [Option<usize>; 256],
+    /// The core stepped last.
+    pub last_stepped: Option<usize>,
+    /// Counts the steps of all cores.
+    pub steps: u32,
+    /// The state of the random number generator.
+    rng: u64,
+}
+
+impl Multicore {
+    /// Creates a [Multicore] with the given number of copies of the [Cpu].
+    /// Each core starts with its number in register [CORE_ID_REGISTER] and the test-and-set extension enabled.
+    pub fn new(cpu: &Cpu, core_count: usize, scheduler: Scheduler) -> Self {
+        if core_count == 0 {
+            panic!("a multicore needs at least one core");
+        }
+        let cores = (0..core_count)
+            .map(|id| {
+                let mut core = cpu.clone();
+                core.registers[CORE_ID_REGISTER] = id as u8;
+                core.test_and_set = true;
+                core
+            })
+            .collect();
+        let rng = match scheduler {
+            Scheduler::Random { seed } => seed,
+            _ => 0,
+        };
+        Multicore {
+            cores,
+            scheduler,
+            selected: 0,
+            last_writer: [None; 256],
+            last_stepped: None,
+            steps: 0,
+            rng: rng | 1,
+        }
+    }
+
+    /// Switches to another [Scheduler], a random one starts from its seed.
+    pub fn set_scheduler(&mut self, scheduler: Scheduler) {
+        if let Scheduler::Random { seed } = scheduler {
+            self.rng = seed | 1;
+        }
+        self.scheduler = scheduler;
+    }
+
+    /// Gets the shared memory.
+    pub fn memory(&self) -> &[u8; 256] {
+        &self.cores[0].memory
+    }
+
+    /// Writes a value into the shared memory, switching the banks of all cores
+    /// on a write to the bank-select register.
+    pub fn set_memory(&mut self, addr: u8, value: u8) {
+        let (first, others) = self.cores.split_first_mut().expect("at least one core");
+        first.set_memory(addr, value);
+        for other in others {
+            other.memory = first.memory;
+            other.banks = first.banks.clone();
+        }
+    }
+
+    /// True if all cores have halted, false otherwise.
+    pub fn halted(&self) -> bool {
+        self.cores.iter().all(|c| c.halted)
+    }
+
+    /// Gets the physical address the last cycle of the core wrote to, if any.
+    fn written_addr(core: &Cpu) -> Option<u8> {
+        match core.decode()? {
+            OpCode::Store { addr, .. } | OpCode::TestAndSet { addr, .. } => {
+                match core.mmu.as_ref() {
+                    Some(mmu) => mmu.last_translation.map(|t| t.physical_addr),
+                    None => Some(addr),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Lets the given core execute one cycle and shares its memory with the other cores.
+    /// Returns false if the instruction was illegal or raised a page fault, true otherwise.
+    pub fn step_core(&mut self, idx: usize) -> bool {
+        let core = &mut self.cores[idx];
+        if core.halted {
+            return true;
+        }
+        let bank = core.current_bank();
+        let r = core.cycle();
+        if r && let Some(addr) = Multicore::written_addr(core) {
+            self.last_writer[addr as usize] = Some(idx);
+        }
+        let memory = self.cores[idx].memory;
+        let banks = (self.cores[idx].current_bank() != bank).then(|| self.cores[idx].banks.clone());
+        for (i, other) in self.cores.iter_mut().enumerate() {
+            if i != idx {
+                other.memory = memory;
+                if let Some(banks) = &banks {
+                    other.banks = banks.clone();
+                }
+            }
+        }
+        self.last_stepped = Some(idx);
+        self.steps = self.steps.wrapping_add(1);
+        r
+    }
+
+    fn next_random(&mut self) -> u64 {
+        // xorshift64
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng = x;
+        x
+    }
+
+    /// Picks the core for the next step, None if all cores have halted
+    /// or the core selected for [Scheduler::Manual] has.
+    pub fn next_core(&mut self) -> Option<usize> {
+        let running = (0..self.cores.len())
+            .filter(|i| !self.cores[*i].halted)
+            .collect::<Vec<usize>>();
+        if running.is_empty() {
+            return None;
+        }
+        match self.scheduler {
+            Scheduler::Manual if self.cores[self.selected].halted => None,
+            Scheduler::Manual => Some(self.selected),
+            Scheduler::RoundRobin => {
+                let len = self.cores.len();
+                (0..len)
+                    .map(|offset| (self.selected + offset) % len)
+                    .find(|i| !self.cores[*i].halted)
+            }
+            Scheduler::Random { .. } => {
+                let pick = self.next_random() as usize % running.len();
+                Some(running[pick])
+            }
+        }
+    }
+
+    /// Steps the core chosen by the [Scheduler].
+    /// Returns the stepped core and whether its cycle succeeded, None if all cores have halted.
+    pub fn step(&mut self) -> Option<(usize, bool)> {
+        let idx = self.next_core()?;
+        let r = self.step_core(idx);
+        if self.scheduler == Scheduler::RoundRobin {
+            self.selected = (idx + 1) % self.cores.len();
+        }
+        Some((idx, r))
+    }
+
+    /// Steps the cores till all have halted or the step limit is reached.
+    /// Returns false if a core executed an illegal instruction or raised a page fault, true otherwise.
+    pub fn run(&mut self, max_steps: u32) -> bool {
+        let mut r = true;
+        for _ in 0..max_steps {
+            match self.step() {
+                Some((_, ok)) => r &= ok,
+                None => break,
+            }
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::banks::BankSwitch;
+    use alloc::vec;
+
+    /// Each core increments the counter at 0x40 under a lock at 0x41.
+    const LOCKED_INCREMENT: [u8; 18] = [
+        0x20, 0x00, // LOAD R0 with 0x00
+        0xD1, 0x41, // TESTANDSET R1 with lock 0x41
+        0xB1, 0x10, // JUMP to [0x10] if R1 == R0, lock acquired
+        0xB0, 0x11, // JUMP to [0x11], retry
+        0xC0, 0x00, // HALT
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+        0x12, 0x02, // jump targets
+    ];
+
+    fn program() -> Cpu {
+        let mut cpu = Cpu::init(&LOCKED_INCREMENT);
+        let critical = [
+            0x12, 0x40, // LOAD R2 with counter
+            0x23, 0x01, // LOAD R3 with 0x01
+            0x52, 0x23, // ADD R2 = R2 + R3
+            0x32, 0x40, // STORE R2 in counter
+            0x30, 0x41, // STORE R0 in lock, release
+            0xC0, 0x00, // HALT
+        ];
+        cpu.memory[0x12..0x12 + critical.len()].copy_from_slice(&critical);
+        cpu
+    }
+
+    #[test]
+    pub fn cores_share_memory() {
+        let cpu = program();
+        let mut multicore = Multicore::new(&cpu, 3, Scheduler::RoundRobin);
+        assert!(multicore.run(1000));
+        assert!(multicore.halted());
+        assert_eq!(multicore.memory()[0x40], 3);
+        assert_eq!(multicore.memory()[0x41], 0);
+        assert!(multicore.cores.iter().all(|c| c.memory[0x40] == 3));
+    }
+
+    #[test]
+    pub fn random_scheduler_is_reproducible() {
+        let cpu = program();
+        let mut a = Multicore::new(&cpu, 4, Scheduler::Random { seed: 7 });
+        let mut b = Multicore::new(&cpu, 4, Scheduler::Random { seed: 7 });
+        let order_a = (0..20).map(|_| a.step()).collect::<Vec<_>>();
+        let order_b = (0..20).map(|_| b.step()).collect::<Vec<_>>();
+        assert_eq!(order_a, order_b);
+        assert!(a.run(1000));
+        assert_eq!(a.memory()[0x40], 4);
+    }
+
+    #[test]
+    pub fn manual_scheduler_steps_selected_core() {
+        let cpu = program();
+        let mut multicore = Multicore::new(&cpu, 2, Scheduler::Manual);
+        multicore.selected = 1;
+        assert_eq!(multicore.step(), Some((1, true)));
+        assert_eq!(multicore.cores[1].program_counter, 2);
+        assert_eq!(multicore.cores[0].program_counter, 0);
+    }
+
+    #[test]
+    pub fn manual_scheduler_stops_when_selected_core_halts() {
+        let cpu = Cpu::init(&[0xC0, 0x00]);
+        let mut multicore = Multicore::new(&cpu, 2, Scheduler::Manual);
+        assert!(multicore.run(1000));
+        assert_eq!(multicore.steps, 1);
+        assert!(multicore.cores[0].halted);
+        assert!(!multicore.cores[1].halted);
+        assert_eq!(multicore.step(), None);
+    }
+
+    #[test]
+    pub fn cores_share_banks() {
+        let mut program = vec![
+            0x21, 0x01, // LOAD R1 with 0x01
+            0x31, 0x7F, // STORE R1 in the bank-select register
+            0xC0, 0x00, // HALT
+        ];
+        program.resize(256 + 0x80, 0x00);
+        program[0x80] = 0x11;
+        program[256] = 0xAB;
+        let cpu = Cpu::init_banked(&program, BankSwitch::new(0x7F, 0x80, 0x80, 2));
+        let mut multicore = Multicore::new(&cpu, 2, Scheduler::Manual);
+        multicore.step();
+        multicore.step();
+        let core = &multicore.cores[1];
+        assert_eq!(core.current_bank(), Some(1));
+        assert_eq!(core.memory[0x80], 0xAB);
+        assert_eq!(core.bank(0).unwrap()[0], 0x11);
+    }
+
+    #[test]
+    pub fn set_memory_switches_banks_of_all_cores() {
+        let mut program = vec![0x00; 256 + 0x80];
+        program[256] = 0xAB;
+        let cpu = Cpu::init_banked(&program, BankSwitch::new(0x7F, 0x80, 0x80, 2));
+        let mut multicore = Multicore::new(&cpu, 2, Scheduler::Manual);
+        multicore.set_memory(0x7F, 0x01);
+        multicore.set_memory(0x81, 0xCD);
+        for core in &multicore.cores {
+            assert_eq!(core.current_bank(), Some(1));
+            assert_eq!(core.memory[0x80], 0xAB);
+            assert_eq!(core.memory[0x81], 0xCD);
+        }
+        multicore.set_memory(0x7F, 0x00);
+        assert_eq!(multicore.cores[1].bank(1).unwrap()[1], 0xCD);
+    }
+
+    #[test]
+    pub fn last_writer_is_tracked() {
+        let cpu = program();
+        let mut multicore = Multicore::new(&cpu, 2, Scheduler::Manual);
+        multicore.selected = 1;
+        multicore.step();
+        multicore.step();
+        assert_eq!(multicore.last_writer[0x41], Some(1));
+        assert_eq!(multicore.memory()[0x41], 1);
+    }
+
+    #[test]
+    pub fn cores_know_their_id() {
+        let cpu = Cpu::new();
+        let multicore = Multicore::new(&cpu, 2, Scheduler::RoundRobin);
+        assert_eq!(multicore.cores[0].registers[CORE_ID_REGISTER], 0);
+        assert_eq!(multicore.cores[1].registers[CORE_ID_REGISTER], 1);
+    }
+}
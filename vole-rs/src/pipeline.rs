@@ -0,0 +1,408 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::mmu::PageFault;
+use crate::vole::{Cpu, Instruction, OpCode};
+
+/// The stages of the [Pipeline].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Instruction fetch.
+    Fetch,
+    /// Instruction decode and register read.
+    Decode,
+    /// Execute, jumps are resolved here.
+    Execute,
+    /// Memory access.
+    Memory,
+    /// Register write-back.
+    WriteBack,
+}
+
+/// All stages in pipeline order.
+pub const STAGES: [Stage; 5] = [
+    Stage::Fetch,
+    Stage::Decode,
+    Stage::Execute,
+    Stage::Memory,
+    Stage::WriteBack,
+];
+
+/// The maximum number of clocks kept in [Pipeline::history].
+pub const HISTORY_LEN: usize = 1024;
+
+/// An instruction travelling through the [Pipeline].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InFlight {
+    /// The address the instruction was fetched from.
+    pub addr: usize,
+    /// The fetched instruction.
+    pub instruction: Instruction,
+    /// The decoded instruction, None if it is illegal or its fetch faulted.
+    pub opcode: Option<OpCode>,
+    /// The page fault raised while fetching the instruction, if any.
+    pub fetch_fault: Option<PageFault>,
+}
+
+/// Something noteworthy that happened during a clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineEvent {
+    /// The instruction in decode waits for the given register.
+    Stall { reg: u8 },
+    /// The value of the given register was forwarded to the execute stage.
+    Forward { reg: u8 },
+    /// A taken jump flushed the given number of instructions.
+    Flush { count: u32 },
+}
+
+/// The occupancy of the stages during a single clock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClockRecord {
+    /// The number of the clock, starting at 1.
+    pub clock: u32,
+    /// The instruction in each stage, in the order of [STAGES].
+    pub stages: [Option<InFlight>; 5],
+    /// The events of the clock.
+    pub events: Vec<PipelineEvent>,
+}
+
+/// A five stage pipelined execution model for a [Cpu].
+///
+/// Instructions take effect on the [Cpu] when they execute, in program order,
+/// so the architectural state always matches the single-cycle model.
+/// Data hazards are detected between decode and execute: without forwarding an instruction
+/// waits until its producers have left the memory stage, with forwarding only a load directly
+/// followed by a consumer of its register stalls. Taken jumps are resolved in execute and flush
+/// the two younger instructions.
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    /// True if results are forwarded to the execute stage, false if they have to be written back first.
+    pub forwarding: bool,
+    /// The instruction in each stage, in the order of [STAGES].
+    pub stages: [Option<InFlight>; 5],
+    /// The address of the next instruction to fetch.
+    pub fetch_addr: usize,
+    /// Counts the clocks.
+    pub clock: u32,
+    /// Counts the instructions that left the write-back stage.
+    pub retired: u32,
+    /// Counts the clocks the decode stage stalled.
+    pub stalls: u32,
+    /// Counts the instructions flushed by taken jumps.
+    pub flushed: u32,
+    /// Counts the forwarded register values.
+    pub forwards: u32,
+    /// The occupancy of the last clocks, at most [HISTORY_LEN].
+    pub history: Vec<ClockRecord>,
+    /// True after fetching a halt or an instruction that can't be executed.
+    fetch_stopped: bool,
+}
+
+const IF: usize = 0;
+const ID: usize = 1;
+const EX: usize = 2;
+const MEM: usize = 3;
+const WB: usize = 4;
+
+impl Pipeline {
+    /// Creates an empty [Pipeline] fetching from the [Cpu::program_counter].
+    pub fn new(cpu: &Cpu, forwarding: bool) -> Self {
+        Pipeline {
+            forwarding,
+            stages: [None; 5],
+            fetch_addr: cpu.program_counter,
+            clock: 0,
+            retired: 0,
+            stalls: 0,
+            flushed: 0,
+            forwards: 0,
+            history: Vec::new(),
+            fetch_stopped: false,
+        }
+    }
+
+    /// Gets the cycles per instruction so far.
+    pub fn cpi(&self) -> f32 {
+        if self.retired == 0 {
+            0.0
+        } else {
+            self.clock as f32 / self.retired as f32
+        }
+    }
+
+    fn writes(slot: &Option<InFlight>, reg: u8) -> bool {
+        slot.and_then(|i| i.opcode)
+            .and_then(|o| o.target_register())
+            == Some(reg)
+    }
+
+    /// Checks whether the instruction in decode may move on to execute.
+    /// Returns the register it has to wait for, if any, and records forwarded registers.
+    fn data_hazard(&self, events: &mut Vec<PipelineEvent>) -> Option<u8> {
+        let opcode = self.stages[ID].and_then(|i| i.opcode)?;
+        for reg in opcode.source_registers() {
+            let in_ex = Pipeline::writes(&self.stages[EX], reg);
+            let in_mem = Pipeline::writes(&self.stages[MEM], reg);
+            if !self.forwarding {
+                if in_ex || in_mem {
+                    return Some(reg);
+                }
+            } else if in_ex
+                && matches!(
+                    self.stages[EX].and_then(|i| i.opcode),
+                    Some(OpCode::LoadAddr { .. } | OpCode::TestAndSet { .. })
+                )
+            {
+                return Some(reg);
+            } else if in_ex || in_mem {
+                events.push(PipelineEvent::Forward { reg });
+            }
+        }
+        None
+    }
+
+    fn fetch(&mut self, cpu: &mut Cpu) -> Option<InFlight> {
+        if self.fetch_stopped {
+            return None;
+        }
+        let addr = self.fetch_addr;
+        let instr = cpu.read_instruction(addr as u8);
+        let fetch_fault = cpu.mmu.as_mut().and_then(|mmu| mmu.fault.take());
+        let instruction = instr.unwrap_or(0);
+        let opcode = match fetch_fault {
+            Some(_) => None,
+            None => Cpu::decode_instruction(instruction).filter(|o| cpu.supports(o)),
+        };
+        if !matches!(opcode, Some(o) if o != OpCode::Halt) {
+            self.fetch_stopped = true;
+        }
+        self.fetch_addr = (self.fetch_addr + 2) % cpu.memory.len();
+        Some(InFlight {
+            addr,
+            instruction,
+            opcode,
+            fetch_fault,
+        })
+    }
+
+    /// Advances the pipeline by one clock.
+    /// Returns false if an illegal instruction was executed or a page fault was raised, true otherwise.
+    pub fn clock(&mut self, cpu: &mut Cpu) -> bool {
+        if cpu.halted {
+            return true;
+        }
+        if let Some(mmu) = &mut cpu.mmu {
+            mmu.fault = None;
+        }
+        if let Some(cache) = &mut cpu.cache {
+            cache.recent.clear();
+        }
+        self.clock = self.clock.wrapping_add(1);
+        let mut events = vec![];
+
+        let hazard = self.data_hazard(&mut events);
+        self.stages[WB] = self.stages[MEM];
+        self.stages[MEM] = self.stages[EX];
+        match hazard {
+            Some(reg) => {
+                self.stalls += 1;
+                events.clear();
+                events.push(PipelineEvent::Stall { reg });
+                self.stages[EX] = None;
+            }
+            None => {
+                self.forwards += events.len() as u32;
+                self.stages[EX] = self.stages[ID];
+                self.stages[ID] = self.stages[IF];
+                self.stages[IF] = self.fetch(cpu);
+            }
+        }
+
+        let mut result = true;
+        let mut flush = false;
+        if hazard.is_none()
+            && let Some(instr) = self.stages[EX]
+        {
+            match instr.opcode {
+                Some(OpCode::Halt) => (),
+                Some(opcode) => {
+                    cpu.program_counter = instr.addr;
+                    cpu.instruction_register = instr.instruction;
+                    cpu.execute(opcode);
+                    if cpu.page_faulted() {
+                        cpu.halted = true;
+                        result = false;
+                    } else {
+                        cpu.cycle = cpu.cycle.wrapping_add(1);
+                        if cpu.program_counter != (instr.addr + 2) % cpu.memory.len() {
+                            flush = true;
+                        }
+                    }
+                }
+                None => {
+                    cpu.program_counter = instr.addr;
+                    cpu.instruction_register = instr.instruction;
+                    if let (Some(fault), Some(mmu)) = (instr.fetch_fault, &mut cpu.mmu) {
+                        mmu.fault = Some(fault);
+                    }
+                    cpu.halted = true;
+                    result = false;
+                }
+            }
+        }
+
+        self.record(events);
+
+        if flush {
+            let count = self.stages[IF..=ID].iter().flatten().count() as u32;
+            self.flushed += count;
+            self.stages[IF] = None;
+            self.stages[ID] = None;
+            self.fetch_stopped = false;
+            self.fetch_addr = cpu.program_counter;
+            if let Some(record) = self.history.last_mut() {
+                record.events.push(PipelineEvent::Flush { count });
+            }
+        }
+
+        if let Some(instr) = self.stages[WB] {
+            self.retired += 1;
+            if let Some(OpCode::Halt) = instr.opcode {
+                cpu.program_counter = instr.addr;
+                cpu.instruction_register = instr.instruction;
+                cpu.execute(OpCode::Halt);
+                cpu.cycle = cpu.cycle.wrapping_add(1);
+            }
+        }
+
+        result
+    }
+
+    fn record(&mut self, events: Vec<PipelineEvent>) {
+        if self.history.len() == HISTORY_LEN {
+            self.history.remove(0);
+        }
+        self.history.push(ClockRecord {
+            clock: self.clock,
+            stages: self.stages,
+            events,
+        });
+    }
+
+    /// Clocks the pipeline till the [Cpu] halts.
+    /// Returns false if an illegal instruction was executed or a page fault was raised, true otherwise.
+    pub fn run(&mut self, cpu: &mut Cpu) -> bool {
+        let mut r = true;
+        while !cpu.halted {
+            r = self.clock(cpu);
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(program: &[u8], forwarding: bool) -> (Cpu, Pipeline) {
+        let mut cpu = Cpu::init(program);
+        let mut pipeline = Pipeline::new(&cpu, forwarding);
+        assert!(pipeline.run(&mut cpu));
+        (cpu, pipeline)
+    }
+
+    #[test]
+    pub fn independent_instructions_do_not_stall() {
+        let program = [0x21, 0x01, 0x22, 0x02, 0x23, 0x03, 0xC0, 0x00];
+        let (cpu, pipeline) = run(&program, false);
+        assert_eq!(cpu.registers[1..4], [0x01, 0x02, 0x03]);
+        assert_eq!(pipeline.retired, 4);
+        assert_eq!(pipeline.clock, 8);
+        assert_eq!(pipeline.stalls, 0);
+        assert_eq!(cpu.cycle, 4);
+    }
+
+    #[test]
+    pub fn dependency_stalls_without_forwarding() {
+        let program = [0x21, 0x01, 0x52, 0x11, 0xC0, 0x00];
+        let (cpu, pipeline) = run(&program, false);
+        assert_eq!(cpu.registers[2], 0x02);
+        assert_eq!(pipeline.stalls, 2);
+        assert_eq!(pipeline.clock, 9);
+    }
+
+    #[test]
+    pub fn dependency_is_forwarded() {
+        let program = [0x21, 0x01, 0x40, 0x12, 0xC0, 0x00];
+        let (cpu, pipeline) = run(&program, true);
+        assert_eq!(cpu.registers[2], 0x01);
+        assert_eq!(pipeline.stalls, 0);
+        assert_eq!(pipeline.forwards, 1);
+        assert_eq!(pipeline.clock, 7);
+    }
+
+    #[test]
+    pub fn load_use_stalls_with_forwarding() {
+        let program = [0x11, 0x08, 0x52, 0x11, 0xC0, 0x00, 0x00, 0x00, 0x03];
+        let (cpu, pipeline) = run(&program, true);
+        assert_eq!(cpu.registers[2], 0x06);
+        assert_eq!(pipeline.stalls, 1);
+        assert_eq!(pipeline.clock, 8);
+    }
+
+    #[test]
+    pub fn taken_jump_flushes() {
+        let program = [0xB0, 0x08, 0x21, 0x01, 0x22, 0x02, 0xC0, 0x00, 0x06];
+        let (cpu, pipeline) = run(&program, true);
+        assert_eq!(cpu.registers[1], 0x00);
+        assert_eq!(cpu.registers[2], 0x00);
+        assert_eq!(pipeline.flushed, 2);
+        assert_eq!(pipeline.retired, 2);
+        assert_eq!(cpu.cycle, 2);
+        assert!(
+            pipeline
+                .history
+                .iter()
+                .any(|r| r.events.contains(&PipelineEvent::Flush { count: 2 }))
+        );
+    }
+
+    #[test]
+    pub fn matches_single_cycle_model() {
+        let program = [0x13, 0xB8, 0xA3, 0x02, 0x33, 0xB8, 0xC0, 0x00, 0x0F];
+        let mut cpu = Cpu::init(&program);
+        cpu.run();
+        let (pipelined_cpu, _) = run(&program, false);
+        assert_eq!(cpu.registers, pipelined_cpu.registers);
+        assert_eq!(cpu.memory, pipelined_cpu.memory);
+        assert_eq!(cpu.program_counter, pipelined_cpu.program_counter);
+        assert_eq!(cpu.cycle, pipelined_cpu.cycle);
+    }
+
+    #[test]
+    pub fn fetch_wraps_around() {
+        let mut program = [0; 256];
+        program[..2].copy_from_slice(&[0xC0, 0x00]);
+        program[0xFC..].copy_from_slice(&[0x21, 0x01, 0x22, 0x02]);
+        let mut cpu = Cpu::init(&program);
+        cpu.program_counter = 0xFC;
+        let mut pipelined_cpu = cpu.clone();
+        cpu.run();
+        let mut pipeline = Pipeline::new(&pipelined_cpu, true);
+        assert!(pipeline.run(&mut pipelined_cpu));
+        assert_eq!(pipelined_cpu.registers[1..3], [0x01, 0x02]);
+        assert_eq!(cpu.program_counter, 0x02);
+        assert_eq!(cpu.program_counter, pipelined_cpu.program_counter);
+        assert_eq!(cpu.cycle, pipelined_cpu.cycle);
+    }
+
+    #[test]
+    pub fn illegal_instruction_halts() {
+        let program = [0x21, 0x01, 0xD3, 0x02];
+        let mut cpu = Cpu::init(&program);
+        let mut pipeline = Pipeline::new(&cpu, true);
+        assert!(!pipeline.run(&mut cpu));
+        assert!(cpu.halted);
+        assert_eq!(cpu.program_counter, 0x02);
+    }
+}
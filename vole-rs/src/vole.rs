@@ -1,15 +1,20 @@
-use std::fmt::Display;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Display;
 
+use crate::banks::BankSwitch;
+use crate::cache::Cache;
 use crate::floating::Floating;
+use crate::mmu::Mmu;
 
 /// Represents the state of the Vole-speaking CPU.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Cpu {
     /// The general purpose registers.
     pub registers: [u8; 16],
     /// The main memory.
     pub memory: [u8; 256],
-    /// Points to the next instruction in memory to fetch.
+    /// Points to the next instruction in memory to fetch, wraps around at the end of memory.
     pub program_counter: usize,
     /// Holds the next instruction to decode and execute.
     pub instruction_register: u16,
@@ -17,6 +22,14 @@ pub struct Cpu {
     pub cycle: u32,
     /// True if the [Cpu] has halted, false otherwise.
     pub halted: bool,
+    /// The optional [Mmu] translating all memory accesses from virtual to physical addresses.
+    pub mmu: Option<Mmu>,
+    /// The optional [Cache] every memory access goes through after address translation.
+    pub cache: Option<Cache>,
+    /// The optional [BankSwitch] mapping memory banks into a window of the physical address space.
+    pub banks: Option<BankSwitch>,
+    /// True if the test-and-set extension [OpCode::TestAndSet] is enabled, false otherwise.
+    pub test_and_set: bool,
 }
 
 impl Cpu {
@@ -29,6 +42,10 @@ impl Cpu {
             instruction_register: 0x0000,
             cycle: 0,
             halted: false,
+            mmu: None,
+            cache: None,
+            banks: None,
+            test_and_set: false,
         }
     }
 
@@ -42,13 +59,124 @@ impl Cpu {
         cpu
     }
 
-    /// Depending on the [Cpu::program_counter], fetches the next instruction from memory.
-    pub fn fetch(&mut self) {
-        let instr_byte0 = self.memory[self.program_counter];
-        let instr_byte1 = self.memory[self.program_counter + 1];
+    /// Initializes a new [Cpu] with bank switching and the given program image loaded.
+    /// The first 256 bytes of the image go into memory, the window of the first bank included.
+    /// The remaining bytes fill the following banks one after the other.
+    /// The byte of the image at the bank-select register is reserved and has to be 0.
+    pub fn init_banked(program: &[u8], banks: BankSwitch) -> Self {
+        if program.len() > banks.capacity() {
+            panic!("given program does not fit into memory and banks");
+        }
+        if program
+            .get(banks.select_addr as usize)
+            .is_some_and(|b| *b != 0)
+        {
+            panic!("given program uses the byte of the bank-select register");
+        }
+        let mut cpu = Cpu::new();
+        let (main, rest) = program.split_at(program.len().min(cpu.memory.len()));
+        cpu.memory[..main.len()].copy_from_slice(main);
+        let mut banks = banks;
+        for (bank, chunk) in rest.chunks(banks.window_len).enumerate() {
+            banks.banks[bank + 1][..chunk.len()].copy_from_slice(chunk);
+        }
+        cpu.memory[banks.select_addr as usize] = banks.current as u8;
+        cpu.banks = Some(banks);
+        cpu
+    }
+
+    /// Maps the given bank into the window of the [BankSwitch], if there is one.
+    pub fn select_bank(&mut self, bank: usize) {
+        if let Some(banks) = &mut self.banks {
+            banks.select(&mut self.memory, bank);
+        }
+    }
+
+    /// Gets the bank mapped into the window, None without a [BankSwitch].
+    pub fn current_bank(&self) -> Option<usize> {
+        self.banks.as_ref().map(|b| b.current)
+    }
+
+    /// Gets the contents of the given bank, None without a [BankSwitch] or if there is no such bank.
+    pub fn bank(&self, bank: usize) -> Option<&[u8]> {
+        self.banks.as_ref()?.bank(&self.memory, bank)
+    }
+
+    /// Gets the physical address of the given address, None if the [Mmu] would raise a page fault.
+    /// Unlike an access it changes no state, e.g. to show where the CPU reads next.
+    pub fn physical_addr(&self, addr: u8) -> Option<u8> {
+        match &self.mmu {
+            Some(mmu) => mmu.peek(&self.memory, addr),
+            None => Some(addr),
+        }
+    }
+
+    /// Translates the address through the [Mmu], if there is one.
+    /// Returns None if the translation raised a page fault.
+    fn translate(&mut self, addr: u8, access: Access) -> Option<u8> {
+        match &mut self.mmu {
+            Some(mmu) => mmu.translate(&self.memory, addr, access),
+            None => Some(addr),
+        }
+    }
+
+    /// Reads the memory cell at the given address.
+    /// Returns None if the access raised a page fault.
+    pub fn read_memory(&mut self, addr: u8, access: Access) -> Option<u8> {
+        let addr = self.translate(addr, access)?;
+        if let Some(cache) = &mut self.cache {
+            cache.access(addr, access);
+        }
+        Some(self.memory[addr as usize])
+    }
+
+    /// Writes the value into the memory cell at the given physical address,
+    /// a write to the bank-select register switches the bank.
+    /// Unlike [Cpu::write_memory] it bypasses the [Mmu] and the [Cache], e.g. for edits by the user.
+    pub fn set_memory(&mut self, addr: u8, value: u8) {
+        self.memory[addr as usize] = value;
+        if let Some(banks) = &mut self.banks
+            && addr == banks.select_addr
+        {
+            banks.select(&mut self.memory, value as usize);
+        }
+    }
+
+    /// Writes the value into the memory cell at the given address.
+    /// Returns false if the access raised a page fault, true otherwise.
+    pub fn write_memory(&mut self, addr: u8, value: u8) -> bool {
+        match self.translate(addr, Access::Store) {
+            Some(addr) => {
+                if let Some(cache) = &mut self.cache {
+                    cache.access(addr, Access::Store);
+                }
+                self.set_memory(addr, value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// True if the last memory access raised a page fault, false otherwise.
+    pub fn page_faulted(&self) -> bool {
+        self.mmu.as_ref().is_some_and(|mmu| mmu.fault.is_some())
+    }
+
+    /// Reads the instruction starting at the given address.
+    /// Returns None if the access raised a page fault.
+    pub fn read_instruction(&mut self, addr: u8) -> Option<Instruction> {
+        let instr_byte0 = self.read_memory(addr, Access::Fetch)?;
+        let instr_byte1 = self.read_memory(addr.wrapping_add(1), Access::Fetch)?;
         let mut instr: u16 = (instr_byte0 as u16) << 8;
         instr |= instr_byte1 as u16;
-        self.instruction_register = instr;
+        Some(instr)
+    }
+
+    /// Depending on the [Cpu::program_counter], fetches the next instruction from memory.
+    pub fn fetch(&mut self) {
+        if let Some(instr) = self.read_instruction(self.program_counter as u8) {
+            self.instruction_register = instr;
+        }
     }
 
     /// Get the bits representing the [OpCode].
@@ -83,22 +211,35 @@ impl Cpu {
 
     /// Decode the bits in the [Cpu::instruction_register] into an [OpCode].
     pub fn decode(&self) -> Option<OpCode> {
-        let opcode_bits = Cpu::get_opcode_bits(self.instruction_register);
-        let operand1 = Cpu::get_operand1_bits(self.instruction_register);
-        let operand2 = Cpu::get_operand2_bits(self.instruction_register);
-        let operand3 = Cpu::get_operand3_bits(self.instruction_register);
+        Cpu::decode_instruction(self.instruction_register).filter(|o| self.supports(o))
+    }
+
+    /// True if the [OpCode] is part of the instruction set of this [Cpu], false if it belongs to a disabled extension.
+    pub fn supports(&self, opcode: &OpCode) -> bool {
+        match opcode {
+            OpCode::TestAndSet { .. } => self.test_and_set,
+            _ => true,
+        }
+    }
+
+    /// Decode the bits of the given [Instruction] into an [OpCode], including all extensions.
+    pub fn decode_instruction(instr: Instruction) -> Option<OpCode> {
+        let opcode_bits = Cpu::get_opcode_bits(instr);
+        let operand1 = Cpu::get_operand1_bits(instr);
+        let operand2 = Cpu::get_operand2_bits(instr);
+        let operand3 = Cpu::get_operand3_bits(instr);
         match opcode_bits {
             0x1 => Some(OpCode::LoadAddr {
                 reg: operand1,
-                addr: Cpu::get_operand23_bits(self.instruction_register),
+                addr: Cpu::get_operand23_bits(instr),
             }),
             0x2 => Some(OpCode::LoadValue {
                 reg: operand1,
-                value: Cpu::get_operand23_bits(self.instruction_register),
+                value: Cpu::get_operand23_bits(instr),
             }),
             0x3 => Some(OpCode::Store {
                 reg: operand1,
-                addr: Cpu::get_operand23_bits(self.instruction_register),
+                addr: Cpu::get_operand23_bits(instr),
             }),
             0x4 => Some(OpCode::Move {
                 source_reg: operand2,
@@ -135,9 +276,13 @@ impl Cpu {
             }),
             0xB => Some(OpCode::Jump {
                 reg: operand1,
-                addr: Cpu::get_operand23_bits(self.instruction_register),
+                addr: Cpu::get_operand23_bits(instr),
             }),
             0xC => Some(OpCode::Halt),
+            0xD => Some(OpCode::TestAndSet {
+                reg: operand1,
+                addr: Cpu::get_operand23_bits(instr),
+            }),
             _ => None,
         }
     }
@@ -146,13 +291,15 @@ impl Cpu {
     pub fn execute(&mut self, opcode: OpCode) {
         match opcode {
             OpCode::LoadAddr { reg, addr: address } => {
-                self.registers[reg as usize] = self.memory[address as usize];
+                if let Some(value) = self.read_memory(address, Access::Load) {
+                    self.registers[reg as usize] = value;
+                }
             }
             OpCode::LoadValue { reg, value } => {
                 self.registers[reg as usize] = value;
             }
             OpCode::Store { reg, addr } => {
-                self.memory[addr as usize] = self.registers[reg as usize];
+                self.write_memory(addr, self.registers[reg as usize]);
             }
             OpCode::Move {
                 source_reg,
@@ -166,7 +313,7 @@ impl Cpu {
                 reg2,
             } => {
                 self.registers[target_reg as usize] =
-                    self.registers[reg1 as usize] + self.registers[reg2 as usize];
+                    self.registers[reg1 as usize].wrapping_add(self.registers[reg2 as usize]);
             }
             OpCode::AddFloat {
                 target_reg,
@@ -213,26 +360,55 @@ impl Cpu {
             }
             OpCode::Jump { reg, addr } => {
                 if self.registers[0] == self.registers[reg as usize] {
-                    self.program_counter = self.memory[addr as usize] as usize;
+                    if let Some(target) = self.read_memory(addr, Access::Load) {
+                        self.program_counter = target as usize;
+                    }
+                } else {
+                    self.program_counter = (self.program_counter + 2) % self.memory.len();
                 }
             }
             OpCode::Halt => {
                 self.halted = true;
             }
+            OpCode::TestAndSet { reg, addr } => {
+                if let Some(value) = self.read_memory(addr, Access::Load)
+                    && self.write_memory(addr, 0x01)
+                {
+                    self.registers[reg as usize] = value;
+                }
+            }
+        }
+
+        if self.page_faulted() {
+            return;
         }
 
         match opcode {
             OpCode::Jump { reg: _, addr: _ } => (),
-            _ => self.program_counter += 2,
+            _ => self.program_counter = (self.program_counter + 2) % self.memory.len(),
         }
     }
 
     /// Do a full fetch-decode-ececute cycle.
-    /// Returns false if instruction was illegal, true otherwise.
+    /// Returns false if instruction was illegal or raised a page fault, true otherwise.
     pub fn cycle(&mut self) -> bool {
+        if let Some(mmu) = &mut self.mmu {
+            mmu.fault = None;
+        }
+        if let Some(cache) = &mut self.cache {
+            cache.recent.clear();
+        }
         self.fetch();
+        if self.page_faulted() {
+            self.halted = true;
+            return false;
+        }
         if let Some(opcode) = self.decode() {
             self.execute(opcode);
+            if self.page_faulted() {
+                self.halted = true;
+                return false;
+            }
             self.cycle = self.cycle.wrapping_add(1);
             true
         } else {
@@ -241,8 +417,28 @@ impl Cpu {
         }
     }
 
+    /// Run till halt or till the given number of cycles has been executed.
+    /// Returns the reason the [Cpu] stopped.
+    pub fn run_for(&mut self, max_cycles: u32) -> StopReason {
+        let mut executed = 0;
+        while !self.halted {
+            if executed >= max_cycles {
+                return StopReason::CycleLimit;
+            }
+            if !self.cycle() {
+                return if self.page_faulted() {
+                    StopReason::PageFault
+                } else {
+                    StopReason::IllegalInstruction
+                };
+            }
+            executed += 1;
+        }
+        StopReason::Halted
+    }
+
     /// Run till halt.
-    /// Returns false if illegal instruction was fetched, true otherwise.
+    /// Returns false if illegal instruction was fetched or a page fault was raised, true otherwise.
     pub fn run(&mut self) -> bool {
         let mut r = true;
         while !self.halted {
@@ -258,10 +454,46 @@ impl Default for Cpu {
     }
 }
 
+/// The reasons a [Cpu] stops running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// A halt instruction was executed.
+    Halted,
+    /// An illegal instruction was fetched.
+    IllegalInstruction,
+    /// A memory access raised a page fault.
+    PageFault,
+    /// The cycle limit was reached before the [Cpu] halted.
+    CycleLimit,
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            StopReason::Halted => write!(f, "halted"),
+            StopReason::IllegalInstruction => write!(f, "illegal instruction"),
+            StopReason::PageFault => write!(f, "page fault"),
+            StopReason::CycleLimit => write!(f, "cycle limit reached"),
+        }
+    }
+}
+
+/// The kinds of memory accesses a [Cpu] does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Fetching an instruction.
+    Fetch,
+    /// Reading a memory cell on behalf of an instruction.
+    Load,
+    /// Writing a memory cell on behalf of an instruction.
+    Store,
+}
+
 /// Type representing the 16 bit instructions of Vole.
 pub type Instruction = u16;
 
 /// The Vole opcodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpCode {
     /// 0x1RXY - LOAD memory cell XY into register R.
     LoadAddr { reg: u8, addr: u8 },
@@ -287,10 +519,69 @@ pub enum OpCode {
     Jump { reg: u8, addr: u8 },
     /// "0xC000 - HALT the execution.
     Halt,
+    /// 0xDRXY - LOAD memory cell XY into register R and set the cell to 0x01 in one step.
+    /// Only available if [Cpu::test_and_set] is enabled.
+    TestAndSet { reg: u8, addr: u8 },
+}
+
+impl OpCode {
+    /// Gets the registers the [OpCode] reads.
+    pub fn source_registers(&self) -> Vec<u8> {
+        match *self {
+            OpCode::LoadAddr { .. }
+            | OpCode::LoadValue { .. }
+            | OpCode::Halt
+            | OpCode::TestAndSet { .. } => vec![],
+            OpCode::Store { reg, .. } | OpCode::Rotate { reg, .. } => vec![reg],
+            OpCode::Move { source_reg, .. } => vec![source_reg],
+            OpCode::AddInt { reg1, reg2, .. }
+            | OpCode::AddFloat { reg1, reg2, .. }
+            | OpCode::Or { reg1, reg2, .. }
+            | OpCode::And { reg1, reg2, .. }
+            | OpCode::Xor { reg1, reg2, .. } => vec![reg1, reg2],
+            OpCode::Jump { reg, .. } => vec![0, reg],
+        }
+    }
+
+    /// Gets the register the [OpCode] writes, if any.
+    pub fn target_register(&self) -> Option<u8> {
+        match *self {
+            OpCode::LoadAddr { reg, .. }
+            | OpCode::LoadValue { reg, .. }
+            | OpCode::Rotate { reg, .. }
+            | OpCode::TestAndSet { reg, .. } => Some(reg),
+            OpCode::Move { target_reg, .. }
+            | OpCode::AddInt { target_reg, .. }
+            | OpCode::AddFloat { target_reg, .. }
+            | OpCode::Or { target_reg, .. }
+            | OpCode::And { target_reg, .. }
+            | OpCode::Xor { target_reg, .. } => Some(target_reg),
+            OpCode::Store { .. } | OpCode::Jump { .. } | OpCode::Halt => None,
+        }
+    }
+
+    /// Gets the memory cell the [OpCode] loads from, stores to or jumps to, if any.
+    pub fn memory_address(&self) -> Option<u8> {
+        match *self {
+            OpCode::LoadAddr { addr, .. }
+            | OpCode::Store { addr, .. }
+            | OpCode::Jump { addr, .. }
+            | OpCode::TestAndSet { addr, .. } => Some(addr),
+            OpCode::LoadValue { .. }
+            | OpCode::Move { .. }
+            | OpCode::AddInt { .. }
+            | OpCode::AddFloat { .. }
+            | OpCode::Or { .. }
+            | OpCode::And { .. }
+            | OpCode::Xor { .. }
+            | OpCode::Rotate { .. }
+            | OpCode::Halt => None,
+        }
+    }
 }
 
 impl Display for OpCode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             OpCode::LoadAddr { reg, addr } => write!(f, "LOADADDR 0x{:02X} 0x{:02X}", reg, addr),
             OpCode::LoadValue { reg, value } => {
@@ -337,6 +628,9 @@ impl Display for OpCode {
             OpCode::Rotate { reg, times } => write!(f, "ROTATE 0x{:02X} 0x{:02X}", reg, times),
             OpCode::Jump { reg, addr } => write!(f, "JUMP 0x{:02X} 0x{:02X}", reg, addr),
             OpCode::Halt => write!(f, "HALT"),
+            OpCode::TestAndSet { reg, addr } => {
+                write!(f, "TESTANDSET 0x{:02X} 0x{:02X}", reg, addr)
+            }
         }
     }
 }
@@ -344,6 +638,7 @@ impl Display for OpCode {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::{CacheConfig, WritePolicy};
 
     #[test]
     pub fn opcode_loadaddr_works() {
@@ -392,6 +687,16 @@ mod tests {
         assert_eq!(cpu.registers[0x07], 0x08)
     }
 
+    #[test]
+    pub fn opcode_addint_wraps_around() {
+        let program = [0x53, 0x12];
+        let mut cpu = Cpu::init(&program);
+        cpu.registers[0x01] = 0xFF;
+        cpu.registers[0x02] = 0x02;
+        assert!(cpu.cycle());
+        assert_eq!(cpu.registers[0x03], 0x01)
+    }
+
     #[test]
     pub fn opcode_addfloat_works() {
         let program = [0x63, 0x4E];
@@ -452,6 +757,29 @@ mod tests {
         assert_eq!(cpu.program_counter, 0xAB)
     }
 
+    #[test]
+    pub fn opcode_jump_not_taken_works() {
+        let program = [0xB4, 0x3C];
+        let mut cpu = Cpu::init(&program);
+        cpu.memory[0x3C] = 0xAB;
+        cpu.registers[0x00] = 0x05;
+        cpu.registers[0x04] = 0x06;
+        assert!(cpu.cycle());
+        assert_eq!(cpu.program_counter, 0x02)
+    }
+
+    #[test]
+    pub fn program_counter_wraps_around() {
+        let program = [0x20, 0x01].repeat(128);
+        let mut cpu = Cpu::init(&program);
+        for _ in 0..128 {
+            assert!(cpu.cycle());
+        }
+        assert_eq!(cpu.program_counter, 0x00);
+        assert!(cpu.cycle());
+        assert_eq!(cpu.program_counter, 0x02);
+    }
+
     #[test]
     pub fn opcode_halt_works() {
         let program = [0xC0];
@@ -460,6 +788,35 @@ mod tests {
         assert!(cpu.halted)
     }
 
+    #[test]
+    pub fn opcode_testandset_works() {
+        let program = [0xD4, 0x3C];
+        let mut cpu = Cpu::init(&program);
+        cpu.test_and_set = true;
+        cpu.memory[0x3C] = 0x00;
+        assert!(cpu.cycle());
+        assert_eq!(cpu.registers[0x04], 0x00);
+        assert_eq!(cpu.memory[0x3C], 0x01);
+    }
+
+    #[test]
+    pub fn opcode_testandset_is_illegal_if_disabled() {
+        let program = [0xD4, 0x3C];
+        let mut cpu = Cpu::init(&program);
+        assert!(!cpu.cycle());
+        assert_eq!(cpu.memory[0x3C], 0x00);
+    }
+
+    #[test]
+    pub fn memory_address_works() {
+        let address = |instr| Cpu::decode_instruction(instr).unwrap().memory_address();
+        assert_eq!(address(0x1417), Some(0x17));
+        assert_eq!(address(0x3417), Some(0x17));
+        assert_eq!(address(0xB40A), Some(0x0A));
+        assert_eq!(address(0x2417), None);
+        assert_eq!(address(0xC000), None);
+    }
+
     #[test]
     pub fn run_works_1() {
         let program = [0x14, 0x02, 0x34, 0x17, 0xC0, 0x00];
@@ -485,6 +842,109 @@ mod tests {
         assert_eq!(cpu.memory[0xB8], 0xC3);
     }
 
+    #[test]
+    pub fn run_with_mmu_works() {
+        let program = [0x14, 0x02, 0x34, 0x17, 0xC0, 0x00];
+        let mut cpu = Cpu::init(&program);
+        let mmu = Mmu::new(0xF0, 4);
+        cpu.memory[0xF0] = 0x83;
+        cpu.memory[0xF1] = 0x82;
+        cpu.memory[0x30..0x36].copy_from_slice(&program);
+        cpu.mmu = Some(mmu);
+        assert!(cpu.run());
+        assert_eq!(cpu.registers[0x04], 0x34);
+        assert_eq!(cpu.memory[0x27], 0x34);
+        assert_eq!(cpu.memory[0x17], 0x00);
+    }
+
+    #[test]
+    pub fn page_fault_halts() {
+        let program = [0x35, 0x42, 0xC0, 0x00];
+        let mut cpu = Cpu::init(&program);
+        let mmu = Mmu::new(0xF0, 4);
+        cpu.memory[0xF0] = 0x80;
+        cpu.mmu = Some(mmu);
+        assert!(!cpu.cycle());
+        assert!(cpu.halted);
+        assert!(cpu.page_faulted());
+        assert_eq!(cpu.program_counter, 0x00);
+        assert_eq!(cpu.cycle, 0);
+    }
+
+    #[test]
+    pub fn run_with_cache_works() {
+        let program = [0x14, 0x02, 0x34, 0x17, 0xC0, 0x00];
+        let mut cpu = Cpu::init(&program);
+        let config = CacheConfig::direct_mapped(4, 4, WritePolicy::WriteBack);
+        cpu.cache = Some(Cache::new(config));
+        assert!(cpu.run());
+        let cache = cpu.cache.unwrap();
+        assert_eq!(cache.fetch_stats.misses, 2);
+        assert_eq!(cache.fetch_stats.hits, 4);
+        assert_eq!(cache.load_stats.hits, 1);
+        assert_eq!(cache.store_stats.misses, 1);
+        assert_eq!(cache.recent.len(), 2);
+    }
+
+    #[test]
+    pub fn store_to_bank_select_register_switches_bank() {
+        let mut program = vec![0; 256 + 0x80];
+        program[..6].copy_from_slice(&[0x21, 0x01, 0x31, 0x7F, 0x12, 0x80]);
+        program[6..8].copy_from_slice(&[0xC0, 0x00]);
+        program[0x80] = 0xAA;
+        program[256] = 0xBB;
+        let banks = BankSwitch::new(0x7F, 0x80, 0x80, 2);
+        let mut cpu = Cpu::init_banked(&program, banks);
+        assert_eq!(cpu.current_bank(), Some(0));
+        assert_eq!(cpu.memory[0x80], 0xAA);
+        assert!(cpu.run());
+        assert_eq!(cpu.current_bank(), Some(1));
+        assert_eq!(cpu.registers[0x02], 0xBB);
+        assert_eq!(cpu.bank(0).unwrap()[0], 0xAA);
+        cpu.select_bank(0);
+        assert_eq!(cpu.memory[0x80], 0xAA);
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn init_banked_with_byte_at_select_register_panics() {
+        let mut program = vec![0; 0x80];
+        program[0x7F] = 0xAB;
+        Cpu::init_banked(&program, BankSwitch::new(0x7F, 0x80, 0x80, 2));
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn init_banked_with_too_large_program_panics() {
+        let program = vec![0; 256 + 0x81];
+        Cpu::init_banked(&program, BankSwitch::new(0x7F, 0x80, 0x80, 2));
+    }
+
+    #[test]
+    pub fn run_for_works() {
+        let program = [0x14, 0x02, 0x34, 0x17, 0xC0, 0x00];
+        let mut cpu = Cpu::init(&program);
+        assert_eq!(cpu.run_for(2), StopReason::CycleLimit);
+        assert_eq!(cpu.cycle, 2);
+        assert_eq!(cpu.run_for(2), StopReason::Halted);
+        assert_eq!(cpu.cycle, 3);
+    }
+
+    #[test]
+    pub fn run_for_with_illegal_instruction() {
+        let program = [0xD3, 0x02];
+        let mut cpu = Cpu::init(&program);
+        assert_eq!(cpu.run_for(10), StopReason::IllegalInstruction);
+    }
+
+    #[test]
+    pub fn run_for_with_endless_loop() {
+        let program = [0xB0, 0x02, 0x00];
+        let mut cpu = Cpu::init(&program);
+        assert_eq!(cpu.run_for(100), StopReason::CycleLimit);
+        assert_eq!(cpu.cycle, 100);
+    }
+
     #[test]
     pub fn cycle_with_illegal_instruction() {
         let program = [0xD3, 0x02];
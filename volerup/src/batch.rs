@@ -0,0 +1,397 @@
+use std::collections::{BTreeMap, HashMap};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+use vole_rs::vole::{Cpu, StopReason};
+
+use crate::cli::{EXIT_SUCCESS, parse_number, read_program, stop_name};
+use crate::run::{DEFAULT_MAX_CYCLES, Format, parse_format};
+use crate::update::init_cpu;
+
+const USAGE: &str = "Usage: volerup batch <directory> [--max-cycles N] [--time-limit-ms N] [--jobs N] [--format text|json] [--output path_to_report]";
+
+/// The time limit per program if none is given.
+pub(crate) const DEFAULT_TIME_LIMIT: Duration = Duration::from_secs(1);
+
+/// The number of cycles run between two checks of the time limit.
+const TIME_CHECK_CYCLES: u32 = 10_000;
+
+/// The file extension of the programs in the directory.
+const PROGRAM_EXTENSION: &str = "vole";
+
+/// How the run of a program ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// The CPU stopped on its own or the cycle limit was reached.
+    Stopped(StopReason),
+    /// The time limit was reached.
+    TimeLimit,
+    /// The simulator panicked while running the program.
+    Crashed(String),
+    /// The program couldn't be loaded.
+    Error(String),
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Stopped(reason) => stop_name(*reason),
+            Outcome::TimeLimit => "time_limit",
+            Outcome::Crashed(_) => "crashed",
+            Outcome::Error(_) => "error",
+        }
+    }
+}
+
+/// The result of running one program of the batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BatchEntry {
+    pub(crate) path: String,
+    pub(crate) outcome: Outcome,
+    pub(crate) cycles: u32,
+    pub(crate) program_counter: usize,
+    /// The memory image right after loading, without trailing zeros.
+    pub(crate) image: Vec<u8>,
+}
+
+impl BatchEntry {
+    /// Describe why the program crashed, None if it didn't.
+    fn crash_reason(&self) -> Option<String> {
+        match &self.outcome {
+            Outcome::Stopped(StopReason::IllegalInstruction | StopReason::PageFault) => Some(
+                format!("{} at 0x{:02X}", self.outcome.name(), self.program_counter),
+            ),
+            Outcome::Crashed(e) | Outcome::Error(e) => Some(e.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// The limits every program of the batch runs with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Limits {
+    pub(crate) max_cycles: u32,
+    pub(crate) time_limit: Duration,
+}
+
+/// Run the CPU till it stops or one of the limits is reached.
+pub(crate) fn run_limited(cpu: &mut Cpu, limits: Limits) -> Outcome {
+    let start = Instant::now();
+    loop {
+        let remaining = limits.max_cycles.saturating_sub(cpu.cycle);
+        let reason = cpu.run_for(remaining.min(TIME_CHECK_CYCLES));
+        if reason != StopReason::CycleLimit || cpu.cycle >= limits.max_cycles {
+            return Outcome::Stopped(reason);
+        }
+        if start.elapsed() >= limits.time_limit {
+            return Outcome::TimeLimit;
+        }
+    }
+}
+
+/// Run the program, a panic of the simulator only ends this run.
+fn isolated(run: impl FnOnce() -> Outcome) -> Outcome {
+    panic::catch_unwind(AssertUnwindSafe(run)).unwrap_or_else(|payload| {
+        let msg = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".into());
+        Outcome::Crashed(format!("simulator panicked: {msg}"))
+    })
+}
+
+/// Load and run a single program in its own CPU.
+pub(crate) fn run_entry(path: &str, limits: Limits) -> BatchEntry {
+    match read_program(path) {
+        Ok(program) => {
+            let mut cpu = init_cpu(&program, false);
+            let len = program.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+            let outcome = isolated(|| run_limited(&mut cpu, limits));
+            BatchEntry {
+                path: path.to_string(),
+                outcome,
+                cycles: cpu.cycle,
+                program_counter: cpu.program_counter,
+                image: program[..len].to_vec(),
+            }
+        }
+        Err(e) => BatchEntry {
+            path: path.to_string(),
+            outcome: Outcome::Error(e),
+            cycles: 0,
+            program_counter: 0,
+            image: Vec::new(),
+        },
+    }
+}
+
+/// Run all programs on the given number of threads.
+/// The entries are in the order of the paths.
+pub(crate) fn run_batch(paths: &[String], limits: Limits, jobs: usize) -> Vec<BatchEntry> {
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|s| {
+        for _ in 0..jobs.max(1) {
+            let tx = tx.clone();
+            let next = &next;
+            s.spawn(move || {
+                loop {
+                    let idx = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(idx) else {
+                        break;
+                    };
+                    let _ = tx.send((idx, run_entry(path, limits)));
+                }
+            });
+        }
+    });
+    drop(tx);
+    let mut entries = rx.into_iter().collect::<Vec<(usize, BatchEntry)>>();
+    entries.sort_by_key(|(idx, _)| *idx);
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// Find the groups of loaded programs with identical memory images.
+pub(crate) fn identical_images(entries: &[BatchEntry]) -> Vec<Vec<String>> {
+    let mut groups: HashMap<&[u8], Vec<String>> = HashMap::new();
+    for entry in entries {
+        if !matches!(entry.outcome, Outcome::Error(_)) {
+            groups
+                .entry(entry.image.as_slice())
+                .or_default()
+                .push(entry.path.clone());
+        }
+    }
+    let mut groups = groups
+        .into_values()
+        .filter(|paths| paths.len() > 1)
+        .collect::<Vec<Vec<String>>>();
+    groups.sort();
+    groups
+}
+
+/// Count the entries per outcome.
+fn summary(entries: &[BatchEntry]) -> BTreeMap<&'static str, usize> {
+    let mut summary = BTreeMap::new();
+    for entry in entries {
+        *summary.entry(entry.outcome.name()).or_default() += 1;
+    }
+    summary
+}
+
+/// Format the aggregated report as text.
+pub(crate) fn text_report(entries: &[BatchEntry]) -> String {
+    let mut report = String::new();
+    for entry in entries {
+        report.push_str(&format!(
+            "{} {} {} cycles",
+            entry.path,
+            entry.outcome.name(),
+            entry.cycles
+        ));
+        if let Some(reason) = entry.crash_reason() {
+            report.push_str(&format!(" ({reason})"));
+        }
+        report.push('\n');
+    }
+    let counts = summary(entries)
+        .into_iter()
+        .map(|(name, count)| format!("{count} {name}"))
+        .collect::<Vec<String>>();
+    report.push_str(&format!(
+        "{} programs: {}\n",
+        entries.len(),
+        counts.join(", ")
+    ));
+    for group in identical_images(entries) {
+        report.push_str(&format!("identical memory images: {}\n", group.join(", ")));
+    }
+    report
+}
+
+/// Format the aggregated report as JSON.
+pub(crate) fn json_report(entries: &[BatchEntry]) -> Value {
+    let programs = entries
+        .iter()
+        .map(|entry| {
+            json!({
+                "path": entry.path,
+                "result": entry.outcome.name(),
+                "cycles": entry.cycles,
+                "crash_reason": entry.crash_reason(),
+            })
+        })
+        .collect::<Vec<Value>>();
+    json!({
+        "programs": programs,
+        "summary": summary(entries),
+        "identical_images": identical_images(entries),
+    })
+}
+
+/// Get the programs of the directory sorted by path.
+fn program_paths(dir: &str) -> Result<Vec<String>, String> {
+    let mut paths = std::fs::read_dir(dir)
+        .map_err(|e| format!("{dir}: {e}"))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| path.extension().is_some_and(|ext| ext == PROGRAM_EXTENSION))
+        .map(|path| path.display().to_string())
+        .collect::<Vec<String>>();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Run all programs of a directory in parallel and write an aggregated report.
+pub(crate) fn batch(args: &[String]) -> Result<i32, String> {
+    let mut dir = None;
+    let mut limits = Limits {
+        max_cycles: DEFAULT_MAX_CYCLES,
+        time_limit: DEFAULT_TIME_LIMIT,
+    };
+    let mut jobs = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut format = Format::Text;
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or(format!("missing value for {arg}\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--max-cycles" => limits.max_cycles = parse_number(value()?)?,
+            "--time-limit-ms" => {
+                limits.time_limit = Duration::from_millis(parse_number(value()?)?.into())
+            }
+            "--jobs" => jobs = parse_number(value()?)? as usize,
+            "--format" => format = parse_format(value()?)?,
+            "--output" => output = Some(value()?.clone()),
+            _ if arg.starts_with("--") || dir.is_some() => {
+                return Err(format!("unexpected argument: {arg}\n{USAGE}"));
+            }
+            _ => dir = Some(arg.clone()),
+        }
+    }
+    let dir = dir.ok_or(USAGE)?;
+
+    let entries = run_batch(&program_paths(&dir)?, limits, jobs);
+    let report = match format {
+        Format::Text => text_report(&entries),
+        Format::Json => format!("{}\n", json_report(&entries)),
+    };
+    match output {
+        Some(path) => {
+            std::fs::write(Path::new(&path), report).map_err(|e| format!("{path}: {e}"))?
+        }
+        None => print!("{report}"),
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        max_cycles: 100,
+        time_limit: Duration::from_secs(10),
+    };
+
+    fn write_programs(dir: &Path, programs: &[(&str, &str)]) -> Vec<String> {
+        std::fs::create_dir_all(dir).unwrap();
+        programs
+            .iter()
+            .map(|(name, text)| {
+                let path = dir.join(name);
+                std::fs::write(&path, text).unwrap();
+                path.display().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_run_limited() {
+        let mut cpu = Cpu::init(&[0xB0, 0x02, 0x00]);
+        assert_eq!(
+            run_limited(&mut cpu, LIMITS),
+            Outcome::Stopped(StopReason::CycleLimit)
+        );
+        assert_eq!(cpu.cycle, 100);
+        let mut cpu = Cpu::init(&[0xB0, 0x02, 0x00]);
+        let limits = Limits {
+            max_cycles: u32::MAX,
+            time_limit: Duration::ZERO,
+        };
+        assert_eq!(run_limited(&mut cpu, limits), Outcome::TimeLimit);
+    }
+
+    #[test]
+    fn test_run_batch() {
+        let dir = std::env::temp_dir().join(format!("volerup-batch-{}", std::process::id()));
+        let mut paths = write_programs(
+            &dir,
+            &[
+                ("a.vole", "14 02 34 17 C0 00"),
+                ("b.vole", "// copy\n0x1402 0x3417\n0xC000 00 00"),
+                ("c.vole", "D3 02"),
+                ("d.vole", "B0 02 00"),
+                ("e.vole", "zz"),
+                ("f.vole", "// overflow\n21 FF 22 01 53 12 C0 00"),
+                ("notes.txt", "14 02 34 17 C0 00"),
+            ],
+        );
+        paths.pop();
+        assert_eq!(program_paths(dir.to_str().unwrap()), Ok(paths.clone()));
+
+        let entries = run_batch(&paths, LIMITS, 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let results = entries
+            .iter()
+            .map(|e| e.outcome.name())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            results,
+            [
+                "halted",
+                "halted",
+                "illegal_instruction",
+                "timeout",
+                "error",
+                "halted"
+            ]
+        );
+        assert_eq!(entries[0].cycles, 3);
+        assert_eq!(
+            identical_images(&entries),
+            vec![vec![paths[0].clone(), paths[1].clone()]]
+        );
+        let json = json_report(&entries);
+        assert_eq!(json["summary"]["halted"], 3);
+        assert_eq!(
+            json["programs"][2]["crash_reason"],
+            "illegal_instruction at 0x00"
+        );
+        assert!(
+            text_report(&entries)
+                .contains("6 programs: 1 error, 3 halted, 1 illegal_instruction, 1 timeout\n")
+        );
+    }
+
+    #[test]
+    fn test_isolated_panic() {
+        assert_eq!(isolated(|| Outcome::TimeLimit), Outcome::TimeLimit);
+        let outcome = isolated(|| panic!("attempt to add with overflow"));
+        assert_eq!(
+            outcome,
+            Outcome::Crashed("simulator panicked: attempt to add with overflow".into())
+        );
+        assert_eq!(outcome.name(), "crashed");
+    }
+}
@@ -0,0 +1,178 @@
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// The extensions of the files shown besides directories.
+pub(crate) const PROGRAM_EXTENSIONS: [&str; 2] = ["vole", "txt"];
+
+/// The number of lines shown in the preview of a file.
+pub(crate) const PREVIEW_LINES: usize = 12;
+
+/// An entry of the browsed directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub(crate) name: String,
+    pub(crate) is_dir: bool,
+}
+
+/// Browses directories for program files to open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileBrowser {
+    pub(crate) dir: PathBuf,
+    /// The parent directory, the subdirectories and the program files, sorted by name.
+    pub(crate) entries: Vec<Entry>,
+    pub(crate) selected: usize,
+    /// The first lines of the selected file.
+    pub(crate) preview: Vec<String>,
+}
+
+fn is_program_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| PROGRAM_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+impl FileBrowser {
+    /// Browse the directory.
+    pub(crate) fn new(dir: &Path) -> std::io::Result<Self> {
+        let dir = dir.canonicalize()?;
+        let mut dirs = vec![];
+        let mut files = vec![];
+        for entry in std::fs::read_dir(&dir)?.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(Entry { name, is_dir: true });
+            } else if is_program_file(&path) {
+                files.push(Entry {
+                    name,
+                    is_dir: false,
+                });
+            }
+        }
+        dirs.sort_by(|a, b| a.name.cmp(&b.name));
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut entries = vec![];
+        if dir.parent().is_some() {
+            entries.push(Entry {
+                name: "..".into(),
+                is_dir: true,
+            });
+        }
+        entries.extend(dirs);
+        entries.extend(files);
+        let mut browser = FileBrowser {
+            dir,
+            entries,
+            selected: 0,
+            preview: vec![],
+        };
+        browser.update_preview();
+        Ok(browser)
+    }
+
+    /// The path of the selected entry.
+    pub(crate) fn selected_path(&self) -> Option<PathBuf> {
+        let entry = self.entries.get(self.selected)?;
+        if entry.name == ".." {
+            self.dir.parent().map(Path::to_path_buf)
+        } else {
+            Some(self.dir.join(&entry.name))
+        }
+    }
+
+    fn update_preview(&mut self) {
+        self.preview = match (self.entries.get(self.selected), self.selected_path()) {
+            (Some(entry), Some(path)) if !entry.is_dir => std::fs::File::open(path)
+                .map(|file| {
+                    BufReader::new(file)
+                        .lines()
+                        .map_while(Result::ok)
+                        .take(PREVIEW_LINES)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            _ => vec![],
+        };
+    }
+
+    pub(crate) fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+        self.update_preview();
+    }
+
+    pub(crate) fn select_next(&mut self) {
+        if self.selected + 1 < self.entries.len() {
+            self.selected += 1;
+        }
+        self.update_preview();
+    }
+
+    /// Go into the selected directory, or get the path of the selected file.
+    pub(crate) fn enter(&mut self) -> std::io::Result<Option<PathBuf>> {
+        let Some(path) = self.selected_path() else {
+            return Ok(None);
+        };
+        if self.entries[self.selected].is_dir {
+            *self = FileBrowser::new(&path)?;
+            Ok(None)
+        } else {
+            Ok(Some(path))
+        }
+    }
+
+    /// Go to the parent directory.
+    pub(crate) fn leave(&mut self) -> std::io::Result<()> {
+        if let Some(parent) = self.dir.parent().map(Path::to_path_buf) {
+            *self = FileBrowser::new(&parent)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_browse() {
+        let dir = std::env::temp_dir().join(format!("volerup-browse-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("exercises")).unwrap();
+        std::fs::write(dir.join("b.vole"), "// store\n0x1402\n0x3417\n0xC000\n").unwrap();
+        std::fs::write(dir.join("a.TXT"), "C000").unwrap();
+        std::fs::write(dir.join("notes.md"), "not a program").unwrap();
+        std::fs::write(dir.join("exercises/one.vole"), "C000").unwrap();
+
+        let mut browser = FileBrowser::new(&dir).unwrap();
+        let names = browser
+            .entries
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["..", "exercises", "a.TXT", "b.vole"]);
+        assert!(browser.preview.is_empty());
+        browser.select_next();
+        browser.select_next();
+        browser.select_next();
+        browser.select_next();
+        assert_eq!(browser.selected, 3);
+        assert_eq!(
+            browser.preview,
+            vec!["// store", "0x1402", "0x3417", "0xC000"]
+        );
+        assert_eq!(browser.enter().unwrap(), Some(browser.dir.join("b.vole")));
+
+        browser.select_previous();
+        browser.select_previous();
+        assert_eq!(browser.enter().unwrap(), None);
+        assert!(browser.dir.ends_with("exercises"));
+        assert_eq!(browser.entries.len(), 2);
+        browser.leave().unwrap();
+        assert_eq!(browser.dir, dir.canonicalize().unwrap());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
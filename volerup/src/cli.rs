@@ -0,0 +1,327 @@
+use std::fmt::Display;
+use std::io::{BufRead, ErrorKind, Write};
+
+use serde_json::Value;
+
+use vole_rs::floating::Floating;
+use vole_rs::vole::{Cpu, StopReason};
+
+use crate::update::{check_program, describe_diagnostics, init_cpu, parse_program_text};
+use crate::{batch, dap, debug, gdb, grade, lsp, run, serve};
+
+/// The exit code if a command other than a simulation succeeded.
+pub(crate) const EXIT_SUCCESS: i32 = 0;
+
+/// The exit code if the program halted.
+pub(crate) const EXIT_HALTED: i32 = 0;
+
+/// The exit code for bad arguments and unreadable programs.
+pub(crate) const EXIT_ERROR: i32 = 1;
+
+/// The exit code if the program executed an illegal instruction.
+pub(crate) const EXIT_ILLEGAL_INSTRUCTION: i32 = 2;
+
+/// The exit code if the program didn't halt within the cycle limit.
+pub(crate) const EXIT_TIMEOUT: i32 = 3;
+
+/// The exit code if the program raised a page fault.
+pub(crate) const EXIT_PAGE_FAULT: i32 = 4;
+
+/// The exit code if a graded test failed.
+pub(crate) const EXIT_TEST_FAILED: i32 = 5;
+
+/// A register or memory cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Location {
+    Register(u8),
+    Memory(u8),
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Register(reg) => write!(f, "R{:X}", reg),
+            Location::Memory(addr) => write!(f, "[0x{:02X}]", addr),
+        }
+    }
+}
+
+impl Location {
+    /// Get the value of the cell.
+    pub(crate) fn read(&self, cpu: &Cpu) -> u8 {
+        match self {
+            Location::Register(reg) => cpu.registers[*reg as usize],
+            Location::Memory(addr) => cpu.memory[*addr as usize],
+        }
+    }
+
+    /// Set the value of the cell, like a store of the program would.
+    pub(crate) fn write(&self, cpu: &mut Cpu, value: u8) {
+        match self {
+            Location::Register(reg) => cpu.registers[*reg as usize] = value,
+            Location::Memory(addr) => {
+                cpu.write_memory(*addr, value);
+            }
+        }
+    }
+}
+
+/// Get the exit code for the reason the CPU stopped.
+pub(crate) fn exit_code(reason: StopReason) -> i32 {
+    match reason {
+        StopReason::Halted => EXIT_HALTED,
+        StopReason::IllegalInstruction => EXIT_ILLEGAL_INSTRUCTION,
+        StopReason::CycleLimit => EXIT_TIMEOUT,
+        StopReason::PageFault => EXIT_PAGE_FAULT,
+    }
+}
+
+/// Get a name for the reason the CPU stopped that is easy to match in scripts.
+pub(crate) fn stop_name(reason: StopReason) -> &'static str {
+    match reason {
+        StopReason::Halted => "halted",
+        StopReason::IllegalInstruction => "illegal_instruction",
+        StopReason::CycleLimit => "timeout",
+        StopReason::PageFault => "page_fault",
+    }
+}
+
+/// Parse a name given by [stop_name].
+pub(crate) fn parse_stop(s: &str) -> Result<StopReason, String> {
+    [
+        StopReason::Halted,
+        StopReason::IllegalInstruction,
+        StopReason::CycleLimit,
+        StopReason::PageFault,
+    ]
+    .into_iter()
+    .find(|reason| stop_name(*reason) == s)
+    .ok_or(format!("unknown stop reason: {s}"))
+}
+
+/// Run the subcommand named by the first argument without a terminal.
+/// Returns the exit code, None if the arguments don't name a subcommand.
+pub(crate) fn dispatch(args: &[String]) -> Option<i32> {
+    let r = match args.get(1)?.as_str() {
+        "run" => run::run(&args[2..]),
+        "grade" => grade::grade(&args[2..]),
+        "batch" => batch::batch(&args[2..]),
+        "debug" => debug::debug(&args[2..]),
+        "gdb" => gdb::gdb(&args[2..]),
+        "dap" => dap::dap(&args[2..]),
+        "lsp" => lsp::lsp(&args[2..]),
+        "serve" => serve::serve(&args[2..]),
+        _ => return None,
+    };
+    Some(r.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        EXIT_ERROR
+    }))
+}
+
+/// The largest message body [read_message] accepts.
+pub(crate) const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// Read a JSON message framed by a `Content-Length` header, as used by DAP and LSP.
+/// Returns None at the end of the input.
+pub(crate) fn read_message(reader: &mut impl BufRead) -> std::io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            len = value.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(len) = len else {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            "missing Content-Length header",
+        ));
+    };
+    if len > MAX_MESSAGE_LEN {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("message of {len} bytes is too large"),
+        ));
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Write a JSON message framed by a `Content-Length` header.
+pub(crate) fn write_message(writer: &mut impl Write, message: &Value) -> std::io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    writer.flush()
+}
+
+/// Parse a decimal or 0x prefixed hex number.
+pub(crate) fn parse_number(s: &str) -> Result<u32, String> {
+    let r = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse::<u32>(),
+    };
+    r.map_err(|_| format!("invalid number: {s}"))
+}
+
+/// Parse a register number.
+pub(crate) fn parse_register(s: &str) -> Result<u8, String> {
+    let s = s.strip_prefix('R').or(s.strip_prefix('r')).unwrap_or(s);
+    match u8::from_str_radix(s, 16) {
+        Ok(reg) if reg < 16 => Ok(reg),
+        _ => Err(format!("invalid register: {s}")),
+    }
+}
+
+/// Parse a byte value.
+pub(crate) fn parse_byte(s: &str) -> Result<u8, String> {
+    parse_number(s)?
+        .try_into()
+        .map_err(|_| format!("value does not fit into a byte: {s}"))
+}
+
+/// Parse a byte value typed in by hand: `0x` prefixed hex, `0b` prefixed binary,
+/// a decimal number from -128 to 255 or a float literal like `1.5` in the eight bit floating point format.
+pub(crate) fn parse_value(s: &str) -> Result<u8, String> {
+    let s = s.trim();
+    if let Some(bin) = s.strip_prefix("0b").or_else(|| s.strip_prefix("0B")) {
+        return u8::from_str_radix(bin, 2).map_err(|_| format!("invalid binary byte: {s}"));
+    }
+    if s.contains('.') {
+        let value = s
+            .parse::<f32>()
+            .map_err(|_| format!("invalid float: {s}"))?;
+        let not_representable = || format!("not representable as an eight bit float: {s}");
+        // encoding assumes the value is in range
+        if !value.is_finite() || value.abs() > 7.5 {
+            return Err(not_representable());
+        }
+        let floating = Floating::encode(value);
+        return if floating.decode() == value {
+            Ok(floating.value)
+        } else {
+            Err(not_representable())
+        };
+    }
+    match s.strip_prefix('-') {
+        Some(neg) => match parse_number(neg) {
+            Ok(n) if n <= 128 => Ok((n as u8).wrapping_neg()),
+            _ => Err(format!("value does not fit into a byte: {s}")),
+        },
+        None => parse_byte(s),
+    }
+}
+
+/// Parse the text of a program.
+pub(crate) fn parse_program(program_text: &str) -> Result<Vec<u8>, String> {
+    let lines = program_text
+        .lines()
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>();
+    let program =
+        parse_program_text(&lines).map_err(|diagnostics| describe_diagnostics(&diagnostics))?;
+    check_program(&program, false)?;
+    Ok(program)
+}
+
+/// Read and parse a program file.
+pub(crate) fn read_program(path: &str) -> Result<Vec<u8>, String> {
+    let program_text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    parse_program(&program_text).map_err(|e| format!("{path}: {e}"))
+}
+
+/// Read a program file and create a CPU with the program loaded.
+pub(crate) fn load_program(path: &str) -> Result<Cpu, String> {
+    Ok(init_cpu(&read_program(path)?, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::update::MAX_PROGRAM_LEN;
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("10000"), Ok(10000));
+        assert_eq!(parse_number("0x1F"), Ok(0x1F));
+        assert!(parse_number("ten").is_err());
+        assert!(parse_number("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_value("0x80"), Ok(0x80));
+        assert_eq!(parse_value("128"), Ok(0x80));
+        assert_eq!(parse_value("-128"), Ok(0x80));
+        assert_eq!(parse_value("-1"), Ok(0xFF));
+        assert!(parse_value("0b1000_0000").is_err());
+        assert_eq!(parse_value("0b10000000"), Ok(0x80));
+        assert_eq!(parse_value("1.5"), Ok(0x5C));
+        assert_eq!(parse_value("-0.25"), Ok(0xB8));
+        assert!(parse_value("1.1").is_err());
+        assert!(parse_value("8.0").is_err());
+        assert!(parse_value("-200.0").is_err());
+        assert!(parse_value("-128.5").is_err());
+        assert!(parse_value("1e30.").is_err());
+        assert!(parse_value("300000000000000000000000000000000000000000.0").is_err());
+        assert!(parse_value("256").is_err());
+        assert!(parse_value("-129").is_err());
+    }
+
+    #[test]
+    fn test_messages() {
+        let message = serde_json::json!({"seq": 1, "command": "initialize"});
+        let mut framed = Vec::new();
+        write_message(&mut framed, &message).unwrap();
+        assert!(framed.starts_with(b"Content-Length: 32\r\n\r\n{"));
+        let mut reader = framed.as_slice();
+        assert_eq!(read_message(&mut reader).unwrap(), Some(message));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+        assert!(read_message(&mut "\r\n{}".as_bytes()).is_err());
+        let header = format!("Content-Length: {}\r\n\r\n", MAX_MESSAGE_LEN + 1);
+        let e = read_message(&mut header.as_bytes()).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_parse_stop() {
+        assert_eq!(parse_stop("timeout"), Ok(StopReason::CycleLimit));
+        assert_eq!(parse_stop("page_fault"), Ok(StopReason::PageFault));
+        assert!(parse_stop("crashed").is_err());
+    }
+
+    #[test]
+    fn test_dispatch_unknown_subcommand() {
+        let args = vec!["volerup".to_string(), "prog.vole".to_string()];
+        assert_eq!(dispatch(&args), None);
+        assert_eq!(dispatch(&args[..1]), None);
+    }
+
+    #[test]
+    fn test_parse_program_too_large() {
+        let program_text = "00 ".repeat(MAX_PROGRAM_LEN + 1);
+        assert!(parse_program(&program_text).is_err());
+    }
+
+    #[test]
+    fn test_parse_program_using_bank_select_register() {
+        let mut bytes = vec!["00"; 257];
+        assert!(parse_program(&bytes.join(" ")).is_ok());
+        bytes[0x7F] = "AB";
+        assert_eq!(
+            parse_program(&bytes.join(" ")),
+            Err("the byte at 0x7F is reserved for the bank-select register".into())
+        );
+        assert!(parse_program(&bytes[..256].join(" ")).is_ok());
+    }
+}
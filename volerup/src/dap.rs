@@ -0,0 +1,603 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::BufReader;
+use std::sync::mpsc;
+use std::thread;
+
+use serde_json::{Value, json};
+use vole_rs::vole::Cpu;
+
+use crate::cli::{
+    EXIT_ERROR, EXIT_HALTED, EXIT_SUCCESS, parse_program, read_message, write_message,
+};
+use crate::gdb::INTERRUPT_CHECK_CYCLES;
+use crate::run::DEFAULT_MAX_CYCLES;
+use crate::update::{SourceMap, init_cpu};
+
+/// The id of the only thread, the CPU.
+const THREAD_ID: u64 = 1;
+
+/// The variables reference of the registers scope.
+const REGISTERS_REFERENCE: u64 = 1;
+
+/// The variables reference of the memory scope.
+const MEMORY_REFERENCE: u64 = 2;
+
+/// The launched program.
+#[derive(Debug, Clone)]
+struct Session {
+    path: String,
+    cpu: Cpu,
+    source_map: SourceMap,
+    /// The addresses of the lines with breakpoints.
+    breakpoints: BTreeSet<usize>,
+    /// True if the CPU waits for the client after the launch.
+    stop_on_entry: bool,
+}
+
+impl Session {
+    /// The address of the instruction on the line, counted from 1.
+    /// None for a value at an odd address or outside of memory, the program counter never gets there.
+    fn address_of_line(&self, line: usize) -> Option<usize> {
+        line.checked_sub(1)
+            .and_then(|line| self.source_map.address_of_line(line))
+            .filter(|&addr| addr % 2 == 0 && addr < self.cpu.memory.len())
+    }
+
+    /// Replace the breakpoints by the ones on the given lines.
+    fn set_breakpoints(&mut self, lines: &[usize]) {
+        self.breakpoints = lines
+            .iter()
+            .filter_map(|&line| self.address_of_line(line))
+            .collect();
+    }
+}
+
+/// True if both paths name the same source file.
+fn same_source(a: &str, b: &str) -> bool {
+    a == b
+        || matches!(
+            (std::fs::canonicalize(a), std::fs::canonicalize(b)),
+            (Ok(a), Ok(b)) if a == b
+        )
+}
+
+/// A breakpoint as sent to the client, a line is only verified once it has an instruction.
+fn breakpoint(line: usize, addr: Option<usize>) -> Value {
+    json!({ "id": line, "verified": addr.is_some(), "line": line })
+}
+
+/// A Debug Adapter Protocol server debugging a single program.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DapServer {
+    session: Option<Session>,
+    /// The lines with breakpoints of each source, they can be set before the launch.
+    breakpoint_lines: BTreeMap<String, Vec<usize>>,
+    seq: u64,
+    /// True once the client asked to end the session.
+    pub(crate) done: bool,
+}
+
+impl DapServer {
+    fn next_seq(&mut self) -> u64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    fn response(&mut self, request: &Value, body: Result<Value, String>) -> Value {
+        let mut response = json!({
+            "seq": self.next_seq(),
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        response
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Value {
+        json!({
+            "seq": self.next_seq(),
+            "type": "event",
+            "event": event,
+            "body": body,
+        })
+    }
+
+    /// Load the program given by the launch arguments.
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments["program"]
+            .as_str()
+            .ok_or("missing program in launch arguments")?;
+        let program_text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let program = parse_program(&program_text).map_err(|e| format!("{path}: {e}"))?;
+        let lines = program_text
+            .lines()
+            .map(|s| s.to_string())
+            .collect::<Vec<String>>();
+        let mut session = Session {
+            path: path.to_string(),
+            cpu: init_cpu(&program, false),
+            source_map: SourceMap::new(&lines),
+            breakpoints: BTreeSet::new(),
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+        };
+        session.set_breakpoints(&self.lines_of(path));
+        self.session = Some(session);
+        Ok(json!({}))
+    }
+
+    /// The lines with breakpoints of the source.
+    fn lines_of(&self, path: &str) -> Vec<usize> {
+        self.breakpoint_lines
+            .iter()
+            .find(|(source, _)| same_source(source, path))
+            .map(|(_, lines)| lines.clone())
+            .unwrap_or_default()
+    }
+
+    /// Replace the breakpoints of a source by the ones on the given lines.
+    /// Before the launch they are only stored and not verified.
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments["source"]["path"]
+            .as_str()
+            .ok_or("missing source path")?;
+        let lines = arguments["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|breakpoint| breakpoint["line"].as_u64().unwrap_or(0) as usize)
+            .collect::<Vec<usize>>();
+        let session = self
+            .session
+            .as_mut()
+            .filter(|session| same_source(&session.path, path));
+        let breakpoints = match session {
+            Some(session) => {
+                session.set_breakpoints(&lines);
+                lines
+                    .iter()
+                    .map(|&line| breakpoint(line, session.address_of_line(line)))
+                    .collect::<Vec<Value>>()
+            }
+            None => lines.iter().map(|&line| breakpoint(line, None)).collect(),
+        };
+        self.breakpoint_lines.insert(path.to_string(), lines);
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    /// Tell the client which of the breakpoints set before the launch have an instruction.
+    fn breakpoint_events(&mut self) -> Vec<Value> {
+        let Some(session) = &self.session else {
+            return Vec::new();
+        };
+        let breakpoints = self
+            .lines_of(&session.path)
+            .into_iter()
+            .map(|line| breakpoint(line, session.address_of_line(line)))
+            .collect::<Vec<Value>>();
+        breakpoints
+            .into_iter()
+            .map(|breakpoint| {
+                self.event(
+                    "breakpoint",
+                    json!({ "reason": "changed", "breakpoint": breakpoint }),
+                )
+            })
+            .collect()
+    }
+
+    /// Start running the launched program, stopping at once on a breakpoint at the entry address.
+    fn start(&mut self, interrupted: impl FnMut() -> bool) -> Vec<Value> {
+        match &self.session {
+            Some(session) if session.breakpoints.contains(&session.cpu.program_counter) => {
+                vec![self.stopped("breakpoint")]
+            }
+            _ => self.resume(false, interrupted),
+        }
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let session = self.session.as_ref().ok_or("no program launched")?;
+        let cpu = &session.cpu;
+        let pc = cpu.program_counter;
+        let byte0 = cpu.memory[pc % 256] as u16;
+        let byte1 = cpu.memory[(pc + 1) % 256] as u16;
+        let name = match Cpu::decode_instruction((byte0 << 8) | byte1) {
+            Some(opcode) if cpu.supports(&opcode) => format!("0x{pc:02X}: {opcode}"),
+            _ => format!("0x{pc:02X}: (bad)"),
+        };
+        let line = session.source_map.line_of_address(pc).map_or(0, |l| l + 1);
+        Ok(json!({
+            "stackFrames": [{
+                "id": 1,
+                "name": name,
+                "line": line,
+                "column": 1,
+                "source": { "path": session.path },
+                "instructionPointerReference": format!("0x{pc:02X}"),
+            }],
+            "totalFrames": 1,
+        }))
+    }
+
+    fn variables(&self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session.as_ref().ok_or("no program launched")?;
+        let cpu = &session.cpu;
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let variables = match arguments["variablesReference"].as_u64() {
+            Some(REGISTERS_REFERENCE) => {
+                let mut variables = cpu
+                    .registers
+                    .iter()
+                    .enumerate()
+                    .map(|(reg, value)| variable(format!("R{reg:X}"), format!("0x{value:02X}")))
+                    .collect::<Vec<Value>>();
+                variables.push(variable(
+                    "PC".into(),
+                    format!("0x{:02X}", cpu.program_counter),
+                ));
+                variables.push(variable(
+                    "IR".into(),
+                    format!("0x{:04X}", cpu.instruction_register),
+                ));
+                variables
+            }
+            Some(MEMORY_REFERENCE) => cpu
+                .memory
+                .chunks(16)
+                .enumerate()
+                .map(|(row, bytes)| {
+                    let bytes = bytes
+                        .iter()
+                        .map(|b| format!("{b:02X}"))
+                        .collect::<Vec<String>>();
+                    variable(format!("0x{:02X}", row * 16), bytes.join(" "))
+                })
+                .collect(),
+            _ => return Err("unknown variables reference".into()),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn stopped(&mut self, reason: &str) -> Value {
+        let body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        self.event("stopped", body)
+    }
+
+    /// Execute cycles and create the events telling the client where the CPU stopped.
+    /// Executes a single cycle if `step` is true, otherwise runs till a breakpoint, the CPU stops
+    /// or `interrupted` returns true.
+    fn resume(&mut self, step: bool, mut interrupted: impl FnMut() -> bool) -> Vec<Value> {
+        let Some(session) = self.session.as_mut() else {
+            return Vec::new();
+        };
+        let cpu = &mut session.cpu;
+        let mut reason = "pause";
+        let mut description = format!("stopped after {DEFAULT_MAX_CYCLES} cycles");
+        for executed in 1..=DEFAULT_MAX_CYCLES {
+            if cpu.halted {
+                break;
+            }
+            let pc = cpu.program_counter;
+            if !cpu.cycle() {
+                reason = "exception";
+                description = if cpu.page_faulted() {
+                    format!("page fault at 0x{pc:02X}")
+                } else {
+                    format!("illegal instruction at 0x{pc:02X}")
+                };
+                break;
+            }
+            if step {
+                reason = "step";
+                description = String::new();
+                break;
+            }
+            if session.breakpoints.contains(&cpu.program_counter) {
+                reason = "breakpoint";
+                description = String::new();
+                break;
+            }
+            if executed % INTERRUPT_CHECK_CYCLES == 0 && interrupted() {
+                description = String::new();
+                break;
+            }
+        }
+        if session.cpu.halted && reason != "exception" {
+            let output = format!("halted after {} cycles\n", session.cpu.cycle);
+            return vec![
+                self.event("output", json!({ "category": "console", "output": output })),
+                self.event("exited", json!({ "exitCode": EXIT_HALTED })),
+                self.event("terminated", json!({})),
+            ];
+        }
+        let mut stopped = self.stopped(reason);
+        if !description.is_empty() {
+            stopped["body"]["description"] = json!(description);
+            stopped["body"]["text"] = json!(description);
+        }
+        vec![stopped]
+    }
+
+    /// Handle a request and return the response followed by any events.
+    /// A run started by the request ends early once `interrupted` returns true.
+    pub(crate) fn handle(
+        &mut self,
+        request: &Value,
+        interrupted: impl FnMut() -> bool,
+    ) -> Vec<Value> {
+        let arguments = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or_default();
+        let body = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "configurationDone" => Ok(json!({})),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CPU" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                { "name": "Memory", "variablesReference": MEMORY_REFERENCE, "expensive": false },
+            ]})),
+            "variables" => self.variables(arguments),
+            "continue" => Ok(json!({ "allThreadsContinued": true })),
+            "next" | "stepIn" | "stepOut" | "pause" => Ok(json!({})),
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(json!({}))
+            }
+            _ => Err(format!("unsupported command: {command}")),
+        };
+        let success = body.is_ok();
+        let mut messages = vec![self.response(request, body)];
+        let stop_on_entry = self.session.as_ref().map(|s| s.stop_on_entry);
+        match (command, stop_on_entry) {
+            ("initialize", _) => messages.push(self.event("initialized", json!({}))),
+            ("launch", _) if success => messages.extend(self.breakpoint_events()),
+            ("configurationDone", Some(true)) => messages.push(self.stopped("entry")),
+            ("configurationDone", Some(false)) => messages.extend(self.start(interrupted)),
+            ("continue", _) => messages.extend(self.resume(false, interrupted)),
+            ("next" | "stepIn" | "stepOut", _) => messages.extend(self.resume(true, || false)),
+            _ => {}
+        }
+        messages
+    }
+}
+
+/// Serve the Debug Adapter Protocol over stdin and stdout.
+pub(crate) fn dap(args: &[String]) -> Result<i32, String> {
+    if !args.is_empty() {
+        return Err("Usage: volerup dap".into());
+    }
+    // read on a thread of its own so a pause request arrives while the program runs
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(std::io::stdin());
+        loop {
+            let message = read_message(&mut reader);
+            let end = !matches!(message, Ok(Some(_)));
+            if sender.send(message).is_err() || end {
+                break;
+            }
+        }
+    });
+    let mut server = DapServer::default();
+    let mut writer = std::io::stdout();
+    let mut pending = VecDeque::new();
+    while !server.done {
+        let message = pending
+            .pop_front()
+            .unwrap_or_else(|| receiver.recv().unwrap_or(Ok(None)));
+        match message {
+            Ok(Some(request)) => {
+                // requests sent during a run are answered after it, a pause ends it
+                let interrupted = || {
+                    let mut paused = false;
+                    while let Ok(message) = receiver.try_recv() {
+                        paused |= matches!(&message, Ok(Some(r)) if r["command"] == "pause");
+                        pending.push_back(message);
+                    }
+                    paused
+                };
+                for message in server.handle(&request, interrupted) {
+                    write_message(&mut writer, &message).map_err(|e| e.to_string())?;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("{e}");
+                return Ok(EXIT_ERROR);
+            }
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "// copy a value
+14 02 // load
+34 17
+C0 00 // halt
+";
+
+    fn request(server: &mut DapServer, command: &str, arguments: Value) -> Vec<Value> {
+        let request = json!({
+            "seq": 1,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        });
+        server.handle(&request, || false)
+    }
+
+    fn launch(stop_on_entry: bool) -> (DapServer, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "volerup-dap-{}-{stop_on_entry}.vole",
+            std::process::id()
+        ));
+        std::fs::write(&path, PROGRAM).unwrap();
+        let mut server = DapServer::default();
+        let messages = request(&mut server, "initialize", json!({}));
+        assert_eq!(messages[1]["event"], "initialized");
+        let messages = request(
+            &mut server,
+            "launch",
+            json!({ "program": path, "stopOnEntry": stop_on_entry }),
+        );
+        assert_eq!(messages[0]["success"], true);
+        (server, path)
+    }
+
+    #[test]
+    fn test_breakpoints_and_stepping() {
+        let (mut server, path) = launch(false);
+        let messages = request(
+            &mut server,
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [{ "line": 1 }, { "line": 3 }] }),
+        );
+        assert_eq!(messages[0]["body"]["breakpoints"][0]["verified"], false);
+        assert_eq!(messages[0]["body"]["breakpoints"][1]["verified"], true);
+
+        let messages = request(&mut server, "configurationDone", json!({}));
+        assert_eq!(messages[1]["event"], "stopped");
+        assert_eq!(messages[1]["body"]["reason"], "breakpoint");
+        let messages = request(&mut server, "stackTrace", json!({ "threadId": THREAD_ID }));
+        let frame = &messages[0]["body"]["stackFrames"][0];
+        assert_eq!(frame["line"], 3);
+        assert_eq!(frame["name"], "0x02: STORE 0x04 0x17");
+
+        let messages = request(
+            &mut server,
+            "variables",
+            json!({ "variablesReference": REGISTERS_REFERENCE }),
+        );
+        assert_eq!(messages[0]["body"]["variables"][4]["value"], "0x34");
+        assert_eq!(messages[0]["body"]["variables"][16]["name"], "PC");
+
+        let messages = request(&mut server, "next", json!({ "threadId": THREAD_ID }));
+        assert_eq!(messages[1]["body"]["reason"], "step");
+        let messages = request(
+            &mut server,
+            "variables",
+            json!({ "variablesReference": MEMORY_REFERENCE }),
+        );
+        assert_eq!(
+            messages[0]["body"]["variables"][1]["value"],
+            "00 00 00 00 00 00 00 34 00 00 00 00 00 00 00 00"
+        );
+
+        let messages = request(&mut server, "continue", json!({ "threadId": THREAD_ID }));
+        assert_eq!(messages[1]["body"]["output"], "halted after 3 cycles\n");
+        assert_eq!(messages[2]["event"], "exited");
+        assert_eq!(messages[3]["event"], "terminated");
+        request(&mut server, "disconnect", json!({}));
+        assert!(server.done);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_stop_on_entry_and_exceptions() {
+        let (mut server, path) = launch(true);
+        let messages = request(&mut server, "configurationDone", json!({}));
+        assert_eq!(messages[1]["body"]["reason"], "entry");
+        server.session.as_mut().unwrap().cpu.memory[0x02] = 0xD3;
+        request(&mut server, "next", json!({ "threadId": THREAD_ID }));
+        let messages = request(&mut server, "continue", json!({ "threadId": THREAD_ID }));
+        assert_eq!(messages[1]["body"]["reason"], "exception");
+        assert_eq!(
+            messages[1]["body"]["description"],
+            "illegal instruction at 0x02"
+        );
+        let messages = request(&mut server, "evaluate", json!({}));
+        assert_eq!(messages[0]["success"], false);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_breakpoints_on_odd_addresses() {
+        let path =
+            std::env::temp_dir().join(format!("volerup-dap-{}-odd.vole", std::process::id()));
+        std::fs::write(&path, "20\n14 02\n34\n17 C0 00\n").unwrap();
+        let mut server = DapServer::default();
+        request(&mut server, "launch", json!({ "program": path }));
+        let messages = request(
+            &mut server,
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [{ "line": 2 }, { "line": 3 }, { "line": 4 }] }),
+        );
+        let breakpoints = &messages[0]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["verified"], false);
+        assert_eq!(breakpoints[1]["verified"], false);
+        assert_eq!(breakpoints[2]["verified"], true);
+        let session = server.session.as_ref().unwrap();
+        assert_eq!(session.breakpoints, BTreeSet::from([0x04]));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_pause_interrupts_run() {
+        let path =
+            std::env::temp_dir().join(format!("volerup-dap-{}-loop.vole", std::process::id()));
+        std::fs::write(&path, "B0 02 00").unwrap();
+        let mut server = DapServer::default();
+        request(&mut server, "launch", json!({ "program": path }));
+        let configuration_done =
+            json!({ "seq": 2, "type": "request", "command": "configurationDone" });
+        let mut checks = 0;
+        let messages = server.handle(&configuration_done, || {
+            checks += 1;
+            checks == 3
+        });
+        assert_eq!(messages[1]["body"]["reason"], "pause");
+        assert!(messages[1]["body"]["description"].is_null());
+        let cycles = server.session.as_ref().unwrap().cpu.cycle;
+        assert_eq!(cycles, 3 * INTERRUPT_CHECK_CYCLES);
+        let messages = request(&mut server, "pause", json!({ "threadId": THREAD_ID }));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["success"], true);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_breakpoints_before_launch() {
+        let path =
+            std::env::temp_dir().join(format!("volerup-dap-{}-early.vole", std::process::id()));
+        std::fs::write(&path, PROGRAM).unwrap();
+        let mut server = DapServer::default();
+        request(&mut server, "initialize", json!({}));
+        let messages = request(
+            &mut server,
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [{ "line": 1 }, { "line": 2 }, { "line": 3 }] }),
+        );
+        assert_eq!(messages[0]["success"], true);
+        assert_eq!(messages[0]["body"]["breakpoints"][1]["verified"], false);
+
+        let messages = request(&mut server, "launch", json!({ "program": path }));
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[1]["event"], "breakpoint");
+        assert_eq!(messages[1]["body"]["breakpoint"]["verified"], false);
+        assert_eq!(messages[2]["body"]["breakpoint"]["id"], 2);
+        assert_eq!(messages[2]["body"]["breakpoint"]["verified"], true);
+
+        let messages = request(&mut server, "configurationDone", json!({}));
+        assert_eq!(messages[1]["body"]["reason"], "breakpoint");
+        assert_eq!(server.session.as_ref().unwrap().cpu.cycle, 0);
+        let messages = request(&mut server, "continue", json!({ "threadId": THREAD_ID }));
+        assert_eq!(messages[1]["body"]["reason"], "breakpoint");
+        assert_eq!(server.session.as_ref().unwrap().cpu.program_counter, 0x02);
+        std::fs::remove_file(path).unwrap();
+    }
+}
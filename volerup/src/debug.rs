@@ -0,0 +1,493 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{BufRead, Write};
+
+use vole_rs::floating::Floating;
+use vole_rs::vole::Cpu;
+
+use crate::cli::{EXIT_SUCCESS, Location, load_program, parse_byte, parse_register};
+use crate::run::DEFAULT_MAX_CYCLES;
+
+const USAGE: &str = "Usage: volerup debug <path_to_file>";
+
+const PROMPT: &str = "(vole) ";
+
+const HELP: &str = "\
+step [n]               execute n cycles, 1 if omitted (s)
+continue               run till a breakpoint, a watch triggers or the CPU stops (c)
+back [n]               undo n cycles, 1 if omitted
+break <addr>           stop before executing the instruction at addr (b)
+delete <addr>          remove the breakpoint at addr
+watch <cell>           stop when the cell changes, cell is r0 .. rF or mem <addr>
+unwatch <cell>         remove the watch of the cell
+print <cell>[/fmt]     print pc or a cell as x (hex), d (decimal), t (binary) or f (float) (p)
+x/<n><b|i> <addr>      examine n (at most 256) bytes or instructions starting at addr
+set <cell> = <value>   set pc or a cell
+disas [addr] [n]       disassemble n instructions starting at addr, the pc if omitted
+info                   list breakpoints, watches and registers
+help                   show this help
+quit                   leave the debugger (q)
+An empty line repeats the last command.";
+
+/// The number of cycles [Debugger] can step back.
+pub(crate) const HISTORY_LEN: usize = 1024;
+
+/// The number of instructions disas shows if no count is given.
+const DISAS_LEN: u8 = 8;
+
+/// A cell the user wants to stop at when it changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Watch {
+    location: Location,
+    value: u8,
+}
+
+/// A line-oriented debugger controlling a [Cpu].
+#[derive(Debug, Clone)]
+pub(crate) struct Debugger {
+    pub(crate) cpu: Cpu,
+    pub(crate) breakpoints: BTreeSet<u8>,
+    watches: Vec<Watch>,
+    /// The states before the last cycles, the latest at the back.
+    history: VecDeque<Cpu>,
+    last_command: String,
+}
+
+/// The value of pc or of a cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    ProgramCounter,
+    Cell(Location),
+}
+
+/// Parse `pc`, `r<n>` or `mem <addr>`.
+fn parse_target(words: &[&str]) -> Result<Target, String> {
+    match words {
+        ["pc"] => Ok(Target::ProgramCounter),
+        ["mem", addr] => Ok(Target::Cell(Location::Memory(parse_byte(addr)?))),
+        [reg] if reg.starts_with(['r', 'R']) => {
+            Ok(Target::Cell(Location::Register(parse_register(reg)?)))
+        }
+        _ => Err(format!("invalid location: {}", words.join(" "))),
+    }
+}
+
+/// Parse `r<n>` or `mem <addr>`.
+fn parse_location(words: &[&str]) -> Result<Location, String> {
+    match parse_target(words)? {
+        Target::Cell(location) => Ok(location),
+        Target::ProgramCounter => Err("pc can't be used here".into()),
+    }
+}
+
+/// Format a value as hex, decimal, binary or float.
+fn format_value(value: u8, format: &str) -> Result<String, String> {
+    match format {
+        "x" => Ok(format!("0x{:02X}", value)),
+        "d" => Ok(format!("{}", value)),
+        "t" => Ok(format!("0b{:08b}", value)),
+        "f" => Ok(format!("{}", Floating { value }.decode())),
+        _ => Err(format!("unknown format: {format}")),
+    }
+}
+
+impl Debugger {
+    pub(crate) fn new(cpu: Cpu) -> Self {
+        Debugger {
+            cpu,
+            breakpoints: BTreeSet::new(),
+            watches: Vec::new(),
+            history: VecDeque::new(),
+            last_command: String::new(),
+        }
+    }
+
+    /// Disassemble the instruction at the given address.
+    fn disassemble(&self, addr: u8) -> String {
+        let byte0 = self.cpu.memory[addr as usize];
+        let byte1 = self.cpu.memory[addr.wrapping_add(1) as usize];
+        let instr = ((byte0 as u16) << 8) | byte1 as u16;
+        let opcode = match Cpu::decode_instruction(instr) {
+            Some(opcode) if self.cpu.supports(&opcode) => opcode.to_string(),
+            _ => "(bad)".into(),
+        };
+        let marker = if addr as usize == self.cpu.program_counter {
+            "=>"
+        } else {
+            "  "
+        };
+        let breakpoint = if self.breakpoints.contains(&addr) {
+            "*"
+        } else {
+            " "
+        };
+        format!("{marker}{breakpoint}0x{addr:02X}: {byte0:02X} {byte1:02X}  {opcode}")
+    }
+
+    /// Execute up to the given number of cycles.
+    /// Stops early when the CPU stops, a watched cell changes or, if asked for, at a breakpoint.
+    fn advance(&mut self, max_cycles: u32, stop_at_breakpoints: bool) -> String {
+        let mut report = String::new();
+        for i in 0..max_cycles {
+            if self.cpu.halted {
+                report.push_str("the CPU has halted\n");
+                break;
+            }
+            let pc = self.cpu.program_counter as u8;
+            if stop_at_breakpoints && i > 0 && self.breakpoints.contains(&pc) {
+                report.push_str(&format!("breakpoint at 0x{pc:02X}\n"));
+                break;
+            }
+            if self.history.len() == HISTORY_LEN {
+                self.history.pop_front();
+            }
+            self.history.push_back(self.cpu.clone());
+            if !self.cpu.cycle() {
+                let reason = if self.cpu.page_faulted() {
+                    "page fault"
+                } else {
+                    "illegal instruction"
+                };
+                report.push_str(&format!("{reason} at 0x{pc:02X}\n"));
+                break;
+            }
+            let mut triggered = false;
+            for watch in &mut self.watches {
+                let value = watch.location.read(&self.cpu);
+                if value != watch.value {
+                    report.push_str(&format!(
+                        "watch {}: 0x{:02X} -> 0x{:02X}\n",
+                        watch.location, watch.value, value
+                    ));
+                    watch.value = value;
+                    triggered = true;
+                }
+            }
+            if triggered {
+                break;
+            }
+            if self.cpu.halted {
+                report.push_str(&format!("halted after {} cycles\n", self.cpu.cycle));
+                break;
+            }
+            if stop_at_breakpoints && i + 1 == max_cycles {
+                report.push_str(&format!("stopped after {max_cycles} cycles\n"));
+            }
+        }
+        report.push_str(&self.disassemble(self.cpu.program_counter as u8));
+        report
+    }
+
+    fn info(&self) -> String {
+        let mut lines = Vec::new();
+        for addr in &self.breakpoints {
+            lines.push(format!("breakpoint 0x{addr:02X}"));
+        }
+        for watch in &self.watches {
+            lines.push(format!("watch {} = 0x{:02X}", watch.location, watch.value));
+        }
+        lines.push(format!(
+            "pc = 0x{:02X}, ir = 0x{:04X}, cycles = {}",
+            self.cpu.program_counter, self.cpu.instruction_register, self.cpu.cycle
+        ));
+        for (row, regs) in self.cpu.registers.chunks(8).enumerate() {
+            let regs = regs
+                .iter()
+                .enumerate()
+                .map(|(i, v)| format!("R{:X} = 0x{:02X}", row * 8 + i, v))
+                .collect::<Vec<String>>();
+            lines.push(regs.join("  "));
+        }
+        lines.join("\n")
+    }
+
+    fn examine(&self, spec: &str, addr: u8) -> Result<String, String> {
+        let (count, format) = spec.split_at(spec.len().saturating_sub(1));
+        let count = if count.is_empty() {
+            1
+        } else {
+            count
+                .parse::<usize>()
+                .map_err(|_| format!("invalid count: {count}"))?
+        };
+        // more would only repeat the memory
+        let count = count.min(self.cpu.memory.len());
+        match format {
+            "b" => Ok((0..count)
+                .map(|i| self.cpu.memory[addr.wrapping_add((i % 256) as u8) as usize])
+                .collect::<Vec<u8>>()
+                .chunks(8)
+                .enumerate()
+                .map(|(row, bytes)| {
+                    let bytes = bytes
+                        .iter()
+                        .map(|b| format!("{b:02X}"))
+                        .collect::<Vec<String>>();
+                    format!(
+                        "0x{:02X}: {}",
+                        addr.wrapping_add((row * 8 % 256) as u8),
+                        bytes.join(" ")
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n")),
+            "i" => Ok((0..count)
+                .map(|i| self.disassemble(addr.wrapping_add((i * 2 % 256) as u8)))
+                .collect::<Vec<String>>()
+                .join("\n")),
+            _ => Err(format!("unknown format: {format}")),
+        }
+    }
+
+    /// Execute a command and return its output.
+    pub(crate) fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            self.last_command = line.trim().to_string();
+            line.to_string()
+        };
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        let count = |words: &[&str]| match words {
+            [] => Ok(1),
+            [n] => crate::cli::parse_number(n),
+            _ => Err(format!("invalid count: {}", words.join(" "))),
+        };
+        match words.as_slice() {
+            [] => Ok(String::new()),
+            ["step" | "s", rest @ ..] => Ok(self.advance(count(rest)?, false)),
+            ["continue" | "c"] => Ok(self.advance(DEFAULT_MAX_CYCLES, true)),
+            ["back", rest @ ..] => {
+                let n = count(rest)?;
+                let mut undone = 0;
+                while undone < n
+                    && let Some(cpu) = self.history.pop_back()
+                {
+                    self.cpu = cpu;
+                    undone += 1;
+                }
+                for watch in &mut self.watches {
+                    watch.value = watch.location.read(&self.cpu);
+                }
+                Ok(format!(
+                    "undid {undone} cycles\n{}",
+                    self.disassemble(self.cpu.program_counter as u8)
+                ))
+            }
+            ["break" | "b", addr] => {
+                let addr = parse_byte(addr)?;
+                self.breakpoints.insert(addr);
+                Ok(format!("breakpoint at 0x{addr:02X}"))
+            }
+            ["delete", addr] => {
+                let addr = parse_byte(addr)?;
+                if self.breakpoints.remove(&addr) {
+                    Ok(format!("deleted breakpoint at 0x{addr:02X}"))
+                } else {
+                    Err(format!("no breakpoint at 0x{addr:02X}"))
+                }
+            }
+            ["watch", cell @ ..] => {
+                let location = parse_location(cell)?;
+                let value = location.read(&self.cpu);
+                self.watches.retain(|w| w.location != location);
+                self.watches.push(Watch { location, value });
+                Ok(format!("watch {location} = 0x{value:02X}"))
+            }
+            ["unwatch", cell @ ..] => {
+                let location = parse_location(cell)?;
+                let len = self.watches.len();
+                self.watches.retain(|w| w.location != location);
+                if self.watches.len() < len {
+                    Ok(format!("removed watch {location}"))
+                } else {
+                    Err(format!("no watch {location}"))
+                }
+            }
+            ["print" | "p", target @ ..] if !target.is_empty() => {
+                let mut target = target.to_vec();
+                let last = target.pop().unwrap_or_default();
+                let (last, format) = last.split_once('/').unwrap_or((last, "x"));
+                target.push(last);
+                match parse_target(&target)? {
+                    Target::ProgramCounter => Ok(format!(
+                        "pc = {}",
+                        format_value(self.cpu.program_counter as u8, format)?
+                    )),
+                    Target::Cell(location) => Ok(format!(
+                        "{location} = {}",
+                        format_value(location.read(&self.cpu), format)?
+                    )),
+                }
+            }
+            [x, addr] if x.starts_with("x/") => self.examine(&x[2..], parse_byte(addr)?),
+            ["x", addr] => self.examine("b", parse_byte(addr)?),
+            ["set", rest @ ..] => {
+                let (target, value) = match rest {
+                    [target @ .., "=", value] => (target, parse_byte(value)?),
+                    _ => return Err("usage: set <cell> = <value>".into()),
+                };
+                match parse_target(target)? {
+                    Target::ProgramCounter => {
+                        self.cpu.program_counter = value as usize;
+                        Ok(format!("pc = 0x{value:02X}"))
+                    }
+                    Target::Cell(location) => {
+                        location.write(&mut self.cpu, value);
+                        Ok(format!("{location} = 0x{value:02X}"))
+                    }
+                }
+            }
+            ["disas", rest @ ..] => {
+                let (addr, n) = match rest {
+                    [] => (self.cpu.program_counter as u8, DISAS_LEN),
+                    [addr] => (parse_byte(addr)?, DISAS_LEN),
+                    [addr, n] => (parse_byte(addr)?, parse_byte(n)?),
+                    _ => return Err("usage: disas [addr] [n]".into()),
+                };
+                self.examine(&format!("{n}i"), addr)
+            }
+            ["info"] => Ok(self.info()),
+            ["help"] => Ok(HELP.into()),
+            _ => Err(format!("unknown command: {line}, try help")),
+        }
+    }
+}
+
+/// Debug a program with commands read line by line from stdin.
+pub(crate) fn debug(args: &[String]) -> Result<i32, String> {
+    let [path] = args else {
+        return Err(USAGE.into());
+    };
+    let mut debugger = Debugger::new(load_program(path)?);
+    println!(
+        "{}",
+        debugger.disassemble(debugger.cpu.program_counter as u8)
+    );
+
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("{PROMPT}");
+        let _ = std::io::stdout().flush();
+        let Some(Ok(line)) = lines.next() else {
+            println!();
+            break;
+        };
+        if matches!(line.trim(), "quit" | "q") {
+            break;
+        }
+        match debugger.execute(&line) {
+            Ok(output) if output.is_empty() => {}
+            Ok(output) => println!("{output}"),
+            Err(e) => println!("{e}"),
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: [u8; 10] = [
+        0x14, 0x02, // LOAD R4 with [0x02]
+        0x34, 0x17, // STORE R4 in 0x17
+        0x23, 0x05, // LOAD R3 with 0x05
+        0x53, 0x34, // ADD R3 = R3 + R4
+        0xC0, 0x00, // HALT
+    ];
+
+    fn debugger() -> Debugger {
+        Debugger::new(Cpu::init(&PROGRAM))
+    }
+
+    #[test]
+    fn test_step_and_back() {
+        let mut debugger = debugger();
+        assert_eq!(
+            debugger.execute("step"),
+            Ok("=> 0x02: 34 17  STORE 0x04 0x17".into())
+        );
+        assert_eq!(debugger.execute("print r4/x"), Ok("R4 = 0x34".into()));
+        debugger.execute("s 2").unwrap();
+        assert_eq!(debugger.cpu.program_counter, 0x06);
+        assert!(
+            debugger
+                .execute("")
+                .unwrap()
+                .starts_with("halted after 5 cycles")
+        );
+        assert!(
+            debugger
+                .execute("step")
+                .unwrap()
+                .starts_with("the CPU has halted")
+        );
+        debugger.execute("back 3").unwrap();
+        assert_eq!(debugger.cpu.program_counter, 0x04);
+        assert!(!debugger.cpu.halted);
+    }
+
+    #[test]
+    fn test_breakpoints_and_watches() {
+        let mut debugger = debugger();
+        debugger.execute("break 0x06").unwrap();
+        debugger.execute("watch mem 0x17").unwrap();
+        assert_eq!(
+            debugger.execute("continue"),
+            Ok("watch [0x17]: 0x00 -> 0x34\n=> 0x04: 23 05  LOADVALUE 0x03 0x05".into())
+        );
+        assert_eq!(
+            debugger.execute("c"),
+            Ok("breakpoint at 0x06\n=>*0x06: 53 34  ADDINT 0x03 0x03 0x04".into())
+        );
+        debugger.execute("delete 0x06").unwrap();
+        assert!(debugger.execute("delete 0x06").is_err());
+        assert!(debugger.execute("c").unwrap().starts_with("halted"));
+        assert_eq!(debugger.execute("print r3/d"), Ok("R3 = 57".into()));
+    }
+
+    #[test]
+    fn test_examine_set_and_disas() {
+        let mut debugger = debugger();
+        assert_eq!(
+            debugger.execute("x/10b 0x00"),
+            Ok("0x00: 14 02 34 17 23 05 53 34\n0x08: C0 00".into())
+        );
+        assert_eq!(debugger.execute("set r3 = 0x05"), Ok("R3 = 0x05".into()));
+        assert_eq!(debugger.cpu.registers[3], 0x05);
+        debugger.execute("set mem 0x20 = 7").unwrap();
+        assert_eq!(
+            debugger.execute("print mem 0x20/t"),
+            Ok("[0x20] = 0b00000111".into())
+        );
+        debugger.execute("set pc = 0x08").unwrap();
+        assert_eq!(
+            debugger.execute("disas 0x06 2"),
+            Ok("   0x06: 53 34  ADDINT 0x03 0x03 0x04\n=> 0x08: C0 00  HALT".into())
+        );
+        assert!(debugger.execute("print r4/q").is_err());
+        assert!(debugger.execute("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_examine_past_end_of_memory() {
+        let mut debugger = debugger();
+        let bytes = debugger.execute("x/300b 0x08").unwrap();
+        let rows = bytes.lines().collect::<Vec<&str>>();
+        assert_eq!(rows.len(), 32);
+        assert_eq!(rows[31], "0x00: 14 02 34 17 23 05 53 34");
+        assert_eq!(
+            debugger.execute("x/99999999999b 0").unwrap(),
+            debugger.execute("x/256b 0").unwrap()
+        );
+        let rows = debugger.execute("x/40b 0xF8").unwrap();
+        assert!(rows.starts_with("0xF8: 00 00 00 00 00 00 00 00\n0x00: 14 02"));
+
+        let instructions = debugger.execute("disas 0 200").unwrap();
+        let lines = instructions.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 200);
+        assert_eq!(lines[128], lines[0]);
+        assert!(lines[199].contains("0x8E:"));
+    }
+}
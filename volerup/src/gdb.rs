@@ -0,0 +1,437 @@
+use std::collections::BTreeSet;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use vole_rs::vole::Cpu;
+
+use crate::cli::{EXIT_SUCCESS, load_program, parse_number};
+
+const USAGE: &str = "Usage: volerup gdb <path_to_file> [--port N]";
+
+/// The port the stub listens on if none is given.
+pub(crate) const DEFAULT_PORT: u16 = 1234;
+
+/// The number of cycles run between two checks for an interrupt from the client.
+pub(crate) const INTERRUPT_CHECK_CYCLES: u32 = 1000;
+
+/// The index of the program counter in the register file seen by the client, after R0 to RF.
+const PC_REGISTER: usize = 16;
+
+/// The signal reported when the CPU stops at a breakpoint, after a step or an interrupt.
+const SIGTRAP: u8 = 5;
+/// The signal reported when the CPU fetched an illegal instruction.
+const SIGILL: u8 = 4;
+/// The signal reported when the CPU raised a page fault.
+const SIGSEGV: u8 = 11;
+
+/// A packet received from the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Packet {
+    Command(String),
+    Interrupt,
+}
+
+/// Calculate the checksum of the data of a packet.
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, b| sum.wrapping_add(b))
+}
+
+/// Frame the data as a packet.
+fn frame(data: &str) -> String {
+    format!("${data}#{:02x}", checksum(data))
+}
+
+/// The largest packet accepted, advertised in hex as `PacketSize`.
+const PACKET_SIZE: usize = 0x1000;
+
+/// Read the next packet, acknowledging it.
+/// Packets longer than [PACKET_SIZE] are dropped.
+/// Returns None if the client closed the connection.
+fn read_packet(reader: &mut impl Read, writer: &mut impl Write) -> std::io::Result<Option<Packet>> {
+    let mut byte = [0];
+    'packets: loop {
+        loop {
+            if reader.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            match byte[0] {
+                0x03 => return Ok(Some(Packet::Interrupt)),
+                b'$' => break,
+                _ => {}
+            }
+        }
+        let mut data = Vec::new();
+        loop {
+            if reader.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'#' {
+                break;
+            }
+            if data.len() == PACKET_SIZE {
+                writer.write_all(b"-")?;
+                continue 'packets;
+            }
+            data.push(byte[0]);
+        }
+        let mut sum = [0; 2];
+        reader.read_exact(&mut sum)?;
+        let data = String::from_utf8_lossy(&data).to_string();
+        let expected = u8::from_str_radix(&String::from_utf8_lossy(&sum), 16).ok();
+        if expected == Some(checksum(&data)) {
+            writer.write_all(b"+")?;
+            return Ok(Some(Packet::Command(data)));
+        }
+        // ask for a retransmission and wait for it
+        writer.write_all(b"-")?;
+    }
+}
+
+/// Encode bytes as hex.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Decode hex into bytes.
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Parse `addr,len` with hex numbers.
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    let addr = usize::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    addr.checked_add(len)
+        .is_some_and(|end| end <= 256)
+        .then_some((addr, len))
+}
+
+/// Maps the commands of the GDB remote serial protocol onto a [Cpu].
+#[derive(Debug, Clone)]
+pub(crate) struct GdbStub {
+    pub(crate) cpu: Cpu,
+    pub(crate) breakpoints: BTreeSet<u8>,
+}
+
+impl GdbStub {
+    pub(crate) fn new(cpu: Cpu) -> Self {
+        GdbStub {
+            cpu,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    /// The reply telling the client why the CPU stopped.
+    fn stop_reply(&self, signal: u8) -> String {
+        if self.cpu.halted && signal == SIGTRAP {
+            "W00".into()
+        } else {
+            format!("S{signal:02x}")
+        }
+    }
+
+    fn registers(&self) -> Vec<u8> {
+        let mut registers = self.cpu.registers.to_vec();
+        registers.push(self.cpu.program_counter as u8);
+        registers
+    }
+
+    fn set_register(&mut self, reg: usize, value: u8) -> bool {
+        match reg {
+            0..PC_REGISTER => self.cpu.registers[reg] = value,
+            PC_REGISTER => self.cpu.program_counter = value as usize,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Execute cycles till the CPU stops, a breakpoint is hit or `interrupted` returns true.
+    /// Executes a single cycle if `step` is true.
+    /// Returns the stop reply.
+    pub(crate) fn resume(&mut self, step: bool, mut interrupted: impl FnMut() -> bool) -> String {
+        let mut executed = 0;
+        loop {
+            if self.cpu.halted {
+                return self.stop_reply(SIGTRAP);
+            }
+            if !self.cpu.cycle() {
+                let signal = if self.cpu.page_faulted() {
+                    SIGSEGV
+                } else {
+                    SIGILL
+                };
+                return self.stop_reply(signal);
+            }
+            executed += 1;
+            if step || self.breakpoints.contains(&(self.cpu.program_counter as u8)) {
+                return self.stop_reply(SIGTRAP);
+            }
+            if executed % INTERRUPT_CHECK_CYCLES == 0 && interrupted() {
+                return self.stop_reply(SIGTRAP);
+            }
+        }
+    }
+
+    /// Handle a command other than step and continue.
+    /// Returns the reply, an empty one for unsupported commands, None if the session ends.
+    pub(crate) fn handle(&mut self, command: &str) -> Option<String> {
+        let reply = match command.split_at(command.len().min(1)) {
+            ("?", _) => self.stop_reply(SIGTRAP),
+            ("g", "") => to_hex(&self.registers()),
+            ("G", values) => match from_hex(values) {
+                Some(values) if values.len() == PC_REGISTER + 1 => {
+                    for (reg, value) in values.into_iter().enumerate() {
+                        self.set_register(reg, value);
+                    }
+                    "OK".into()
+                }
+                _ => "E01".into(),
+            },
+            ("p", reg) => match usize::from_str_radix(reg, 16)
+                .ok()
+                .and_then(|reg| self.registers().get(reg).copied())
+            {
+                Some(value) => to_hex(&[value]),
+                None => "E01".into(),
+            },
+            ("P", assignment) => {
+                let set = assignment.split_once('=').and_then(|(reg, value)| {
+                    let reg = usize::from_str_radix(reg, 16).ok()?;
+                    let value = from_hex(value)?;
+                    (value.len() == 1 && self.set_register(reg, value[0])).then_some(())
+                });
+                if set.is_some() { "OK" } else { "E01" }.into()
+            }
+            ("m", range) => match parse_range(range) {
+                Some((addr, len)) => to_hex(&self.cpu.memory[addr..addr + len]),
+                None => "E01".into(),
+            },
+            ("M", write) => {
+                let data = write.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = parse_range(range)?;
+                    let data = from_hex(data)?;
+                    (data.len() == len).then_some((addr, data))
+                });
+                match data {
+                    Some((addr, data)) => {
+                        self.cpu.memory[addr..addr + data.len()].copy_from_slice(&data);
+                        "OK".into()
+                    }
+                    None => "E01".into(),
+                }
+            }
+            ("Z" | "z", breakpoint) => {
+                let addr = breakpoint
+                    .strip_prefix("0,")
+                    .and_then(|b| b.split(',').next())
+                    .and_then(|addr| u8::from_str_radix(addr, 16).ok());
+                match addr {
+                    Some(addr) if command.starts_with('Z') => {
+                        self.breakpoints.insert(addr);
+                        "OK".into()
+                    }
+                    Some(addr) => {
+                        self.breakpoints.remove(&addr);
+                        "OK".into()
+                    }
+                    None => String::new(),
+                }
+            }
+            ("q", _) if command.starts_with("qSupported") => format!("PacketSize={PACKET_SIZE:x}"),
+            ("q", _) if command == "qAttached" => "1".into(),
+            ("H", _) => "OK".into(),
+            ("D", _) => {
+                self.breakpoints.clear();
+                return None;
+            }
+            ("k", _) => return None,
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+}
+
+/// True if the client sent an interrupt since the last check.
+fn interrupt_pending(stream: &mut TcpStream) -> bool {
+    let mut byte = [0];
+    let _ = stream.set_nonblocking(true);
+    let r = stream.read(&mut byte);
+    let _ = stream.set_nonblocking(false);
+    match r {
+        Ok(1) => byte[0] == 0x03,
+        Err(e) if e.kind() == ErrorKind::WouldBlock => false,
+        _ => true,
+    }
+}
+
+/// Serve a client till it detaches, kills the session or disconnects.
+/// Returns true if the client killed the session.
+pub(crate) fn serve(stub: &mut GdbStub, stream: TcpStream) -> std::io::Result<bool> {
+    let mut writer = stream.try_clone()?;
+    let mut control = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    while let Some(packet) = read_packet(&mut reader, &mut writer)? {
+        let command = match packet {
+            Packet::Command(command) => command,
+            Packet::Interrupt => continue,
+        };
+        let reply = match command.as_str() {
+            "s" => stub.resume(true, || false),
+            "c" => stub.resume(false, || interrupt_pending(&mut control)),
+            _ => match stub.handle(&command) {
+                Some(reply) => reply,
+                None => {
+                    writer.write_all(frame("OK").as_bytes())?;
+                    return Ok(command == "k");
+                }
+            },
+        };
+        writer.write_all(frame(&reply).as_bytes())?;
+    }
+    Ok(false)
+}
+
+/// Serve the GDB remote serial protocol for a program on a local TCP port.
+pub(crate) fn gdb(args: &[String]) -> Result<i32, String> {
+    let (path, port) = match args {
+        [path] => (path, DEFAULT_PORT),
+        [path, flag, port] if flag == "--port" => {
+            let port = parse_number(port)?;
+            (
+                path,
+                u16::try_from(port).map_err(|_| format!("invalid port: {port}"))?,
+            )
+        }
+        _ => return Err(USAGE.into()),
+    };
+    let mut stub = GdbStub::new(load_program(path)?);
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| e.to_string())?;
+    println!("listening on 127.0.0.1:{port}");
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|e| e.to_string())?;
+        if serve(&mut stub, stream).map_err(|e| e.to_string())? {
+            break;
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: [u8; 8] = [
+        0x14, 0x02, // LOAD R4 with [0x02]
+        0x34, 0x17, // STORE R4 in 0x17
+        0x23, 0x05, // LOAD R3 with 0x05
+        0xC0, 0x00, // HALT
+    ];
+
+    /// Send a command and read the acknowledgement and the reply.
+    fn request(stream: &mut TcpStream, command: &str) -> String {
+        stream.write_all(frame(command).as_bytes()).unwrap();
+        let mut ack = [0];
+        stream.read_exact(&mut ack).unwrap();
+        assert_eq!(ack[0], b'+');
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut sink = Vec::new();
+        match read_packet(&mut reader, &mut sink).unwrap() {
+            Some(Packet::Command(reply)) => reply,
+            other => panic!("unexpected reply {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_packets() {
+        assert_eq!(frame("OK"), "$OK#9a");
+        let mut reader = "+$g#67".as_bytes();
+        let mut ack = Vec::new();
+        assert_eq!(
+            read_packet(&mut reader, &mut ack).unwrap(),
+            Some(Packet::Command("g".into()))
+        );
+        assert_eq!(ack, b"+");
+        let mut reader = "$g#00$?#3f".as_bytes();
+        let mut ack = Vec::new();
+        assert_eq!(
+            read_packet(&mut reader, &mut ack).unwrap(),
+            Some(Packet::Command("?".into()))
+        );
+        assert_eq!(ack, b"-+");
+        let corrupted = "$g#00".repeat(100_000) + "$?#3f";
+        let mut reader = corrupted.as_bytes();
+        let mut ack = Vec::new();
+        assert_eq!(
+            read_packet(&mut reader, &mut ack).unwrap(),
+            Some(Packet::Command("?".into()))
+        );
+        assert_eq!(ack.len(), 100_001);
+        let oversized = format!("${}#00$?#3f", "g".repeat(10 * PACKET_SIZE));
+        let mut reader = oversized.as_bytes();
+        let mut ack = Vec::new();
+        assert_eq!(
+            read_packet(&mut reader, &mut ack).unwrap(),
+            Some(Packet::Command("?".into()))
+        );
+        assert_eq!(ack, b"-+");
+    }
+
+    #[test]
+    fn test_handle() {
+        let mut stub = GdbStub::new(Cpu::init(&PROGRAM));
+        assert_eq!(stub.handle("?"), Some("S05".into()));
+        assert_eq!(stub.handle("m0,4"), Some("14023417".into()));
+        assert_eq!(stub.handle("M20,2:abcd"), Some("OK".into()));
+        assert_eq!(stub.cpu.memory[0x21], 0xCD);
+        assert_eq!(stub.handle("mff,2"), Some("E01".into()));
+        assert_eq!(stub.handle("mffffffffffffffff,2"), Some("E01".into()));
+        assert_eq!(stub.handle("M1,ffffffffffffffff:00"), Some("E01".into()));
+        assert_eq!(stub.handle("P3=7f"), Some("OK".into()));
+        assert_eq!(stub.handle("p3"), Some("7f".into()));
+        assert_eq!(stub.handle("P10=04"), Some("OK".into()));
+        assert_eq!(stub.cpu.program_counter, 4);
+        assert_eq!(stub.handle("p11"), Some("E01".into()));
+        assert_eq!(stub.handle("Z1,4,2"), Some("".into()));
+        assert_eq!(stub.handle("vMustReplyEmpty"), Some("".into()));
+        assert_eq!(stub.handle("k"), None);
+    }
+
+    #[test]
+    fn test_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut stub = GdbStub::new(Cpu::init(&PROGRAM));
+            let (stream, _) = listener.accept().unwrap();
+            serve(&mut stub, stream).unwrap()
+        });
+        let mut client = TcpStream::connect(addr).unwrap();
+        assert_eq!(
+            request(&mut client, "qSupported:swbreak+"),
+            "PacketSize=1000"
+        );
+        assert_eq!(request(&mut client, "s"), "S05");
+        assert_eq!(
+            request(&mut client, "g"),
+            format!(
+                "{}02",
+                to_hex(&[0, 0, 0, 0, 0x34, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+            )
+        );
+        assert_eq!(request(&mut client, "Z0,4,2"), "OK");
+        assert_eq!(request(&mut client, "c"), "S05");
+        assert_eq!(request(&mut client, "p10"), "04");
+        assert_eq!(request(&mut client, "m17,1"), "34");
+        assert_eq!(request(&mut client, "z0,4,2"), "OK");
+        assert_eq!(request(&mut client, "c"), "W00");
+        assert_eq!(request(&mut client, "k"), "OK");
+        assert!(server.join().unwrap());
+    }
+}
@@ -0,0 +1,391 @@
+use serde_json::{Value, json};
+use vole_rs::vole::StopReason;
+
+use crate::cli::{
+    EXIT_SUCCESS, EXIT_TEST_FAILED, Location, parse_byte, parse_number, parse_register, parse_stop,
+    read_program, stop_name,
+};
+use crate::run::{DEFAULT_MAX_CYCLES, Format, parse_format};
+use crate::update::init_cpu;
+
+const USAGE: &str =
+    "Usage: volerup grade <path_to_file>... --spec <path_to_spec>... [--format text|json]";
+
+/// A test of a spec file: the initial state and the expected final state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TestSpec {
+    pub(crate) name: String,
+    pub(crate) max_cycles: u32,
+    pub(crate) setup: Vec<(Location, u8)>,
+    pub(crate) expected: Vec<(Location, u8)>,
+    pub(crate) expected_stop: StopReason,
+}
+
+/// A cell that doesn't hold the expected value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Mismatch {
+    pub(crate) location: Location,
+    pub(crate) expected: u8,
+    pub(crate) actual: u8,
+}
+
+/// The outcome of running a program against a [TestSpec].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TestResult {
+    pub(crate) name: String,
+    pub(crate) stop: StopReason,
+    pub(crate) expected_stop: StopReason,
+    pub(crate) cycles: u32,
+    pub(crate) mismatches: Vec<Mismatch>,
+}
+
+impl TestResult {
+    pub(crate) fn passed(&self) -> bool {
+        self.stop == self.expected_stop && self.mismatches.is_empty()
+    }
+}
+
+/// Parse `reg <register> = <value>` or `mem <addr> = <value>...` into the cells it sets.
+fn parse_cells(words: &[&str]) -> Result<Vec<(Location, u8)>, String> {
+    match words {
+        ["reg", reg, "=", value] => Ok(vec![(
+            Location::Register(parse_register(reg)?),
+            parse_byte(value)?,
+        )]),
+        ["mem", addr, "=", values @ ..] if !values.is_empty() => {
+            let addr = parse_byte(addr)?;
+            if addr as usize + values.len() > 256 {
+                return Err("values do not fit into memory".into());
+            }
+            values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| Ok((Location::Memory(addr + i as u8), parse_byte(value)?)))
+                .collect()
+        }
+        _ => Err(format!("invalid cells: {}", words.join(" "))),
+    }
+}
+
+/// Parse the text of a spec file into its tests.
+pub(crate) fn parse_spec(spec_text: &str) -> Result<Vec<TestSpec>, String> {
+    let mut tests: Vec<TestSpec> = Vec::new();
+    for (idx, line) in spec_text.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or_default();
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        if words.is_empty() {
+            continue;
+        }
+        let r = match (words.as_slice(), tests.last_mut()) {
+            (["test", name], _) => {
+                tests.push(TestSpec {
+                    name: name.to_string(),
+                    max_cycles: DEFAULT_MAX_CYCLES,
+                    setup: Vec::new(),
+                    expected: Vec::new(),
+                    expected_stop: StopReason::Halted,
+                });
+                Ok(())
+            }
+            (_, None) => Err("expected `test <name>` first".into()),
+            (["max-cycles", n], Some(test)) => parse_number(n).map(|n| test.max_cycles = n),
+            (["expect", "stop", reason], Some(test)) => {
+                parse_stop(reason).map(|r| test.expected_stop = r)
+            }
+            (["expect", cells @ ..], Some(test)) => {
+                parse_cells(cells).map(|cells| test.expected.extend(cells))
+            }
+            (cells, Some(test)) => parse_cells(cells).map(|cells| test.setup.extend(cells)),
+        };
+        r.map_err(|e| format!("line {}: {e}", idx + 1))?;
+    }
+    Ok(tests)
+}
+
+/// Run the program against a test.
+pub(crate) fn run_test(program: &[u8], test: &TestSpec) -> TestResult {
+    let mut cpu = init_cpu(program, false);
+    for (location, value) in &test.setup {
+        location.write(&mut cpu, *value);
+    }
+    let stop = cpu.run_for(test.max_cycles);
+    let mismatches = test
+        .expected
+        .iter()
+        .filter_map(|(location, expected)| {
+            let actual = location.read(&cpu);
+            (actual != *expected).then_some(Mismatch {
+                location: *location,
+                expected: *expected,
+                actual,
+            })
+        })
+        .collect();
+    TestResult {
+        name: test.name.clone(),
+        stop,
+        expected_stop: test.expected_stop,
+        cycles: cpu.cycle,
+        mismatches,
+    }
+}
+
+/// The results of all tests for one program, or why it couldn't be loaded.
+pub(crate) struct ProgramReport {
+    pub(crate) path: String,
+    pub(crate) results: Result<Vec<TestResult>, String>,
+}
+
+impl ProgramReport {
+    fn passed(&self) -> usize {
+        self.results
+            .as_ref()
+            .map(|r| r.iter().filter(|t| t.passed()).count())
+            .unwrap_or(0)
+    }
+}
+
+/// Format the reports as text with the differences of failed tests.
+pub(crate) fn text_report(reports: &[ProgramReport], total: usize) -> String {
+    let mut report = String::new();
+    for program in reports {
+        report.push_str(&format!("{}\n", program.path));
+        match &program.results {
+            Ok(results) => {
+                for result in results {
+                    if result.passed() {
+                        report.push_str(&format!("  PASS {}\n", result.name));
+                        continue;
+                    }
+                    report.push_str(&format!(
+                        "  FAIL {} ({} after {} cycles)\n",
+                        result.name,
+                        stop_name(result.stop),
+                        result.cycles
+                    ));
+                    if result.stop != result.expected_stop {
+                        report.push_str(&format!(
+                            "    stop: expected {}, got {}\n",
+                            stop_name(result.expected_stop),
+                            stop_name(result.stop)
+                        ));
+                    }
+                    for m in &result.mismatches {
+                        report.push_str(&format!(
+                            "    {}: expected 0x{:02X}, got 0x{:02X}\n",
+                            m.location, m.expected, m.actual
+                        ));
+                    }
+                }
+            }
+            Err(e) => report.push_str(&format!("  ERROR {e}\n")),
+        }
+        report.push_str(&format!("  {}/{} tests passed\n", program.passed(), total));
+    }
+    report
+}
+
+/// Format the reports as JSON.
+pub(crate) fn json_report(reports: &[ProgramReport], total: usize) -> Value {
+    let programs = reports
+        .iter()
+        .map(|program| {
+            let mut report = json!({
+                "path": program.path,
+                "passed": program.passed(),
+                "total": total,
+            });
+            match &program.results {
+                Ok(results) => {
+                    report["tests"] = results
+                        .iter()
+                        .map(|result| {
+                            json!({
+                                "name": result.name,
+                                "passed": result.passed(),
+                                "stop": stop_name(result.stop),
+                                "expected_stop": stop_name(result.expected_stop),
+                                "cycles": result.cycles,
+                                "mismatches": result.mismatches.iter().map(|m| json!({
+                                    "location": m.location.to_string(),
+                                    "expected": m.expected,
+                                    "actual": m.actual,
+                                })).collect::<Vec<Value>>(),
+                            })
+                        })
+                        .collect()
+                }
+                Err(e) => report["error"] = json!(e),
+            }
+            report
+        })
+        .collect::<Vec<Value>>();
+    json!({ "programs": programs })
+}
+
+/// Run every program against the tests of all spec files and print the results.
+/// Returns a failure exit code if any test failed.
+pub(crate) fn grade(args: &[String]) -> Result<i32, String> {
+    let mut paths = Vec::new();
+    let mut specs = Vec::new();
+    let mut format = Format::Text;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or(format!("missing value for {arg}\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--spec" => specs.push(value()?.clone()),
+            "--format" => format = parse_format(value()?)?,
+            _ if arg.starts_with("--") => {
+                return Err(format!("unexpected argument: {arg}\n{USAGE}"));
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+    if paths.is_empty() || specs.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let mut tests = Vec::new();
+    for spec in &specs {
+        let spec_text = std::fs::read_to_string(spec).map_err(|e| format!("{spec}: {e}"))?;
+        tests.extend(parse_spec(&spec_text).map_err(|e| format!("{spec}: {e}"))?);
+    }
+
+    let reports = paths
+        .into_iter()
+        .map(|path| {
+            let results = read_program(&path)
+                .map(|program| tests.iter().map(|t| run_test(&program, t)).collect());
+            ProgramReport { path, results }
+        })
+        .collect::<Vec<ProgramReport>>();
+
+    match format {
+        Format::Text => print!("{}", text_report(&reports, tests.len())),
+        Format::Json => println!("{}", json_report(&reports, tests.len())),
+    }
+    if reports.iter().all(|r| r.passed() == tests.len()) {
+        Ok(EXIT_SUCCESS)
+    } else {
+        Ok(EXIT_TEST_FAILED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = "
+// adds R1 and R2 into R0 and stores it at 0x80
+test add
+reg 1 = 0x05
+reg 2 = 3
+expect reg 0 = 0x08
+expect mem 0x80 = 0x08
+
+test overflow
+max-cycles 10
+mem 0x40 = 0xFF 0x01 // inputs
+reg R1 = 0xFF
+reg R2 = 0x01
+expect reg 0 = 0x00
+expect stop halted
+";
+
+    const PROGRAM: [u8; 6] = [
+        0x50, 0x12, // ADD R0 = R1 + R2
+        0x30, 0x80, // STORE R0 in 0x80
+        0xC0, 0x00, // HALT
+    ];
+
+    #[test]
+    fn test_parse_spec() {
+        let tests = parse_spec(SPEC).unwrap();
+        assert_eq!(tests.len(), 2);
+        assert_eq!(tests[0].name, "add");
+        assert_eq!(tests[0].max_cycles, DEFAULT_MAX_CYCLES);
+        assert_eq!(
+            tests[0].setup,
+            vec![(Location::Register(1), 5), (Location::Register(2), 3)]
+        );
+        assert_eq!(tests[1].max_cycles, 10);
+        assert_eq!(tests[1].setup[1], (Location::Memory(0x41), 0x01));
+        assert!(parse_spec("reg 1 = 2").is_err());
+        assert_eq!(
+            parse_spec("test a\nreg 16 = 0"),
+            Err("line 2: invalid register: 16".into())
+        );
+        assert!(parse_spec("test a\nexpect mem 0xFF = 1 2").is_err());
+        assert!(parse_spec("test a\nexpect stop crashed").is_err());
+    }
+
+    #[test]
+    fn test_run_test() {
+        let tests = parse_spec(SPEC).unwrap();
+        for test in &tests {
+            let result = run_test(&PROGRAM, test);
+            assert!(result.passed(), "test {} failed", test.name);
+            assert_eq!(result.cycles, 3);
+        }
+
+        let mut test = tests[0].clone();
+        test.expected.push((Location::Memory(0x81), 0x08));
+        test.max_cycles = 2;
+        let result = run_test(&PROGRAM, &test);
+        assert!(!result.passed());
+        assert_eq!(result.stop, StopReason::CycleLimit);
+        assert_eq!(
+            result.mismatches,
+            vec![Mismatch {
+                location: Location::Memory(0x81),
+                expected: 0x08,
+                actual: 0x00,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_run_test_switches_banks() {
+        let mut program = PROGRAM.to_vec();
+        program.resize(256 + 2, 0x00);
+        program[256 + 1] = 0x42;
+        let tests = parse_spec("test bank\nmem 0x7F = 1\nexpect mem 0x81 = 0x42").unwrap();
+        let result = run_test(&program, &tests[0]);
+        assert!(result.passed());
+    }
+
+    #[test]
+    fn test_reports() {
+        let tests = parse_spec(SPEC).unwrap();
+        let mut failing = tests[0].clone();
+        failing.expected[0].1 = 0x09;
+        let reports = vec![
+            ProgramReport {
+                path: "a.vole".into(),
+                results: Ok(vec![
+                    run_test(&PROGRAM, &tests[0]),
+                    run_test(&PROGRAM, &failing),
+                ]),
+            },
+            ProgramReport {
+                path: "b.vole".into(),
+                results: Err("b.vole: not found".into()),
+            },
+        ];
+        let text = text_report(&reports, 2);
+        assert!(text.contains("  PASS add\n  FAIL add (halted after 3 cycles)\n"));
+        assert!(text.contains("    R0: expected 0x09, got 0x08\n"));
+        assert!(text.contains("  ERROR b.vole: not found\n  0/2 tests passed\n"));
+        let json = json_report(&reports, 2);
+        assert_eq!(json["programs"][0]["passed"], 1);
+        assert_eq!(
+            json["programs"][0]["tests"][1]["mismatches"][0]["actual"],
+            8
+        );
+        assert_eq!(json["programs"][1]["error"], "b.vole: not found");
+    }
+}
@@ -0,0 +1,528 @@
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::ops::Range;
+
+use serde_json::{Value, json};
+use vole_rs::vole::{Cpu, OpCode};
+
+use crate::cli::{EXIT_ERROR, EXIT_SUCCESS, read_message, write_message};
+use crate::update::{Diagnostic, map_program};
+
+/// The instruction templates offered for completion: mnemonic, snippet and description.
+const TEMPLATES: [(&str, &str, &str); 13] = [
+    (
+        "LOADADDR",
+        "1${1:R}${2:XY}",
+        "1RXY - LOAD register R with memory cell XY",
+    ),
+    (
+        "LOADVALUE",
+        "2${1:R}${2:XY}",
+        "2RXY - LOAD register R with value XY",
+    ),
+    (
+        "STORE",
+        "3${1:R}${2:XY}",
+        "3RXY - STORE register R in memory cell XY",
+    ),
+    (
+        "MOVE",
+        "40${1:R}${2:S}",
+        "40RS - MOVE register R to register S",
+    ),
+    (
+        "ADDINT",
+        "5${1:R}${2:S}${3:T}",
+        "5RST - ADD registers S and T as integers into R",
+    ),
+    (
+        "ADDFLOAT",
+        "6${1:R}${2:S}${3:T}",
+        "6RST - ADD registers S and T as floats into R",
+    ),
+    (
+        "OR",
+        "7${1:R}${2:S}${3:T}",
+        "7RST - OR registers S and T into R",
+    ),
+    (
+        "AND",
+        "8${1:R}${2:S}${3:T}",
+        "8RST - AND registers S and T into R",
+    ),
+    (
+        "XOR",
+        "9${1:R}${2:S}${3:T}",
+        "9RST - XOR registers S and T into R",
+    ),
+    (
+        "ROTATE",
+        "A${1:R}0${2:X}",
+        "AR0X - ROTATE register R X times to the right",
+    ),
+    (
+        "JUMP",
+        "B${1:R}${2:XY}",
+        "BRXY - JUMP to the address in memory cell XY if register R equals R0",
+    ),
+    ("HALT", "C000", "C000 - HALT"),
+    (
+        "TESTANDSET",
+        "D${1:R}${2:XY}",
+        "DRXY - load memory cell XY into R and set it to 1, multicore only",
+    ),
+];
+
+/// The diagnostic severities of LSP.
+const ERROR: u8 = 1;
+const WARNING: u8 = 2;
+
+/// A word of the program text holding values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Word {
+    line: usize,
+    /// The columns of the word, in UTF-16 code units.
+    columns: Range<usize>,
+    addresses: Range<usize>,
+}
+
+impl Word {
+    fn range(&self) -> Value {
+        range(self.line, &self.columns)
+    }
+}
+
+/// The LSP range of the columns of the line.
+fn range(line: usize, columns: &Range<usize>) -> Value {
+    json!({
+        "start": { "line": line, "character": columns.start },
+        "end": { "line": line, "character": columns.end },
+    })
+}
+
+/// The words of a program text and the memory image they make up.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Document {
+    words: Vec<Word>,
+    image: Vec<u8>,
+    /// The invalid words with their columns in UTF-16 code units.
+    errors: Vec<(Range<usize>, Diagnostic)>,
+}
+
+impl Document {
+    /// Split the program text into words like the editor does, skipping comments.
+    pub(crate) fn new(text: &str) -> Self {
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        let (image, map, diagnostics) = map_program(&lines);
+        let utf16_columns = |line: usize, columns: &Range<usize>| {
+            let mut chars = lines[line].chars();
+            let start = chars
+                .by_ref()
+                .take(columns.start)
+                .map(char::len_utf16)
+                .sum();
+            let len: usize = chars.take(columns.len()).map(char::len_utf16).sum();
+            start..start + len
+        };
+        Document {
+            words: map
+                .words
+                .iter()
+                .map(|word| Word {
+                    line: word.line,
+                    columns: utf16_columns(word.line, &word.columns),
+                    addresses: word.addresses.clone(),
+                })
+                .collect(),
+            image,
+            errors: diagnostics
+                .into_iter()
+                .map(|d| (utf16_columns(d.line, &d.columns), d))
+                .collect(),
+        }
+    }
+
+    fn word_at(&self, line: usize, character: usize) -> Option<&Word> {
+        self.words
+            .iter()
+            .find(|w| w.line == line && w.columns.start <= character && character <= w.columns.end)
+    }
+
+    fn word_of_address(&self, addr: usize) -> Option<&Word> {
+        self.words.iter().find(|w| w.addresses.contains(&addr))
+    }
+
+    /// Get the address and the bits of the instruction the word belongs to.
+    fn instruction_of(&self, word: &Word) -> Option<(usize, u16)> {
+        let addr = if word.addresses.len() == 2 {
+            word.addresses.start
+        } else {
+            word.addresses.start & !1
+        };
+        let byte0 = *self.image.get(addr)? as u16;
+        let byte1 = *self.image.get(addr + 1)? as u16;
+        Some((addr, (byte0 << 8) | byte1))
+    }
+
+    /// Find invalid words, misaligned instruction words and an incomplete last instruction.
+    pub(crate) fn diagnostics(&self) -> Vec<Value> {
+        let diagnostic = |range: Value, severity: u8, message: String| {
+            json!({
+                "range": range,
+                "severity": severity,
+                "source": "volerup",
+                "message": message,
+            })
+        };
+        let mut diagnostics: Vec<Value> = self
+            .errors
+            .iter()
+            .map(|(columns, d)| {
+                diagnostic(
+                    range(d.line, columns),
+                    ERROR,
+                    format!("`{}` {}", d.token, d.reason),
+                )
+            })
+            .collect();
+        for word in &self.words {
+            if word.addresses.len() == 2 && word.addresses.start % 2 == 1 {
+                diagnostics.push(diagnostic(
+                    word.range(),
+                    WARNING,
+                    format!(
+                        "instruction word starts at the odd address 0x{:02X}",
+                        word.addresses.start
+                    ),
+                ))
+            }
+        }
+        if !self.image.len().is_multiple_of(2)
+            && let Some(word) = self.words.last()
+        {
+            diagnostics.push(diagnostic(
+                word.range(),
+                WARNING,
+                format!(
+                    "odd number of bytes ({}), the last instruction is incomplete",
+                    self.image.len()
+                ),
+            ));
+        }
+        diagnostics
+    }
+
+    /// Describe the instruction the word at the position belongs to.
+    pub(crate) fn hover(&self, line: usize, character: usize) -> Option<Value> {
+        let word = self.word_at(line, character)?;
+        let text = match self.instruction_of(word) {
+            Some((addr, instr)) => {
+                let mut text = format!("`0x{addr:02X}`: `{instr:04X}` ");
+                match Cpu::decode_instruction(instr) {
+                    Some(OpCode::Jump { reg, addr }) => {
+                        let target = self.image.get(addr as usize).copied().unwrap_or(0);
+                        text.push_str(&format!(
+                            "{}\n\njumps to 0x{target:02X}, stored at 0x{addr:02X}, if R{reg:X} equals R0",
+                            OpCode::Jump { reg, addr }
+                        ));
+                    }
+                    Some(opcode) => text.push_str(&opcode.to_string()),
+                    None => text.push_str("illegal instruction"),
+                }
+                text
+            }
+            None => format!("`0x{:02X}`: incomplete instruction", word.addresses.start),
+        };
+        Some(json!({
+            "contents": { "kind": "markdown", "value": text },
+            "range": word.range(),
+        }))
+    }
+
+    /// Find the word the jump at the position continues at.
+    pub(crate) fn definition(&self, line: usize, character: usize) -> Option<Value> {
+        let word = self.word_at(line, character)?;
+        let (_, instr) = self.instruction_of(word)?;
+        let Some(OpCode::Jump { addr, .. }) = Cpu::decode_instruction(instr) else {
+            return None;
+        };
+        let target = self.image.get(addr as usize).copied().unwrap_or(0);
+        Some(self.word_of_address(target as usize)?.range())
+    }
+}
+
+/// The completion items for the instruction templates.
+pub(crate) fn completions() -> Vec<Value> {
+    TEMPLATES
+        .iter()
+        .map(|(mnemonic, snippet, description)| {
+            json!({
+                "label": mnemonic,
+                "kind": 15,
+                "detail": description,
+                "insertText": snippet,
+                "insertTextFormat": 2,
+            })
+        })
+        .collect()
+}
+
+/// A language server for Vole program texts.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LspServer {
+    documents: HashMap<String, Document>,
+    /// True once the client sent the exit notification.
+    pub(crate) exited: bool,
+}
+
+impl LspServer {
+    fn publish_diagnostics(&self, uri: &str) -> Value {
+        let diagnostics = self
+            .documents
+            .get(uri)
+            .map(|d| d.diagnostics())
+            .unwrap_or_default();
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        })
+    }
+
+    /// Handle a request or notification and return the messages to send.
+    pub(crate) fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let position = |p: &Value| {
+            (
+                p["position"]["line"].as_u64().unwrap_or(0) as usize,
+                p["position"]["character"].as_u64().unwrap_or(0) as usize,
+            )
+        };
+        let result = match message["method"].as_str().unwrap_or_default() {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "completionProvider": {},
+                    "definitionProvider": true,
+                },
+                "serverInfo": { "name": "volerup" },
+            })),
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), Document::new(text));
+                return vec![self.publish_diagnostics(&uri)];
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()?["text"].as_str()) {
+                    self.documents.insert(uri.clone(), Document::new(text));
+                }
+                return vec![self.publish_diagnostics(&uri)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![self.publish_diagnostics(&uri)];
+            }
+            "textDocument/hover" => {
+                let (line, character) = position(params);
+                Ok(json!(
+                    self.documents
+                        .get(&uri)
+                        .and_then(|d| d.hover(line, character))
+                ))
+            }
+            "textDocument/completion" => Ok(json!(completions())),
+            "textDocument/definition" => {
+                let (line, character) = position(params);
+                Ok(json!(
+                    self.documents
+                        .get(&uri)
+                        .and_then(|d| d.definition(line, character))
+                        .map(|range| json!({ "uri": uri, "range": range }))
+                ))
+            }
+            "shutdown" => Ok(Value::Null),
+            "exit" => {
+                self.exited = true;
+                return Vec::new();
+            }
+            method => Err(format!("method not found: {method}")),
+        };
+        let Some(id) = message.get("id") else {
+            return Vec::new();
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(message) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": -32601, "message": message },
+            }),
+        };
+        vec![response]
+    }
+}
+
+/// Serve the Language Server Protocol over stdin and stdout.
+pub(crate) fn lsp(args: &[String]) -> Result<i32, String> {
+    if !args.is_empty() {
+        return Err("Usage: volerup lsp".into());
+    }
+    let mut server = LspServer::default();
+    let mut reader = BufReader::new(std::io::stdin());
+    let mut writer = std::io::stdout();
+    while !server.exited {
+        match read_message(&mut reader) {
+            Ok(Some(message)) => {
+                for message in server.handle(&message) {
+                    write_message(&mut writer, &message).map_err(|e| e.to_string())?;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("{e}");
+                return Ok(EXIT_ERROR);
+            }
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::update::diagnose_program;
+
+    const PROGRAM: &str = "// count down R1
+21 03 0x2201 // R1 = 3, R2 = 1
+B1 0C        // done?
+0x5112 0xB00C
+C000
+06 x";
+
+    #[test]
+    fn test_document() {
+        let document = Document::new(PROGRAM);
+        assert_eq!(document.words.len(), 9);
+        assert_eq!(document.image.len(), 13);
+        assert_eq!(document.words[2].columns, 6..12);
+        assert_eq!(document.words[2].addresses, 2..4);
+        let diagnostics = document.diagnostics();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0]["severity"], ERROR);
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 5);
+        assert_eq!(diagnostics[0]["range"]["start"]["character"], 3);
+        assert_eq!(
+            diagnostics[0]["message"],
+            "`x` is not a number in hex notation"
+        );
+        assert_eq!(diagnostics[1]["severity"], WARNING);
+        assert!(
+            diagnostics[1]["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("odd number")
+        );
+        let misaligned = Document::new("20 2233 C000");
+        assert!(
+            misaligned.diagnostics()[0]["message"]
+                .as_str()
+                .unwrap()
+                .contains("odd address 0x01")
+        );
+    }
+
+    #[test]
+    fn test_document_matches_editor() {
+        let text = "20 \u{1F600}x // \u{E4}\n\u{E4}\u{1F600} 0x2233 3";
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        let expected = diagnose_program(&lines);
+        let diagnostics = Document::new(text).diagnostics();
+        assert_eq!(diagnostics.len(), expected.len() + 1);
+        for (diagnostic, expected) in diagnostics.iter().zip(&expected) {
+            assert_eq!(diagnostic["range"]["start"]["line"], expected.line);
+            assert_eq!(
+                diagnostic["message"],
+                format!("`{}` {}", expected.token, expected.reason)
+            );
+        }
+        assert_eq!(diagnostics[0]["range"]["start"]["character"], 3);
+        assert_eq!(diagnostics[0]["range"]["end"]["character"], 6);
+        assert_eq!(diagnostics[1]["range"]["end"]["character"], 3);
+    }
+
+    #[test]
+    fn test_hover_and_definition() {
+        let document = Document::new(PROGRAM);
+        let hover = document.hover(1, 7).unwrap();
+        assert_eq!(
+            hover["contents"]["value"],
+            "`0x02`: `2201` LOADVALUE 0x02 0x01"
+        );
+        let hover = document.hover(2, 4).unwrap();
+        assert_eq!(
+            hover["contents"]["value"],
+            "`0x04`: `B10C` JUMP 0x01 0x0C\n\njumps to 0x06, stored at 0x0C, if R1 equals R0"
+        );
+        assert!(document.hover(0, 3).is_none());
+        assert_eq!(
+            document.hover(5, 0).unwrap()["contents"]["value"],
+            "`0x0C`: incomplete instruction"
+        );
+        let definition = document.definition(3, 8).unwrap();
+        assert_eq!(definition["start"]["line"], 3);
+        assert_eq!(definition["start"]["character"], 0);
+        assert!(document.definition(1, 0).is_none());
+    }
+
+    #[test]
+    fn test_server() {
+        let mut server = LspServer::default();
+        let messages = server
+            .handle(&json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}));
+        assert_eq!(messages[0]["result"]["capabilities"]["hoverProvider"], true);
+        let messages = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///a.vole", "text": "C0 00 zz" } },
+        }));
+        assert_eq!(
+            messages[0]["params"]["diagnostics"][0]["range"]["start"]["character"],
+            6
+        );
+        let messages = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": { "textDocument": { "uri": "file:///a.vole" }, "contentChanges": [{ "text": "C000" }] },
+        }));
+        assert_eq!(messages[0]["params"]["diagnostics"], json!([]));
+        let messages = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "textDocument/hover",
+            "params": { "textDocument": { "uri": "file:///a.vole" }, "position": { "line": 0, "character": 1 } },
+        }));
+        assert_eq!(
+            messages[0]["result"]["contents"]["value"],
+            "`0x00`: `C000` HALT"
+        );
+        let messages = server.handle(
+            &json!({"jsonrpc": "2.0", "id": 3, "method": "textDocument/completion", "params": {}}),
+        );
+        assert_eq!(
+            messages[0]["result"].as_array().unwrap().len(),
+            TEMPLATES.len()
+        );
+        let messages = server.handle(
+            &json!({"jsonrpc": "2.0", "id": 4, "method": "workspace/symbol", "params": {}}),
+        );
+        assert_eq!(messages[0]["error"]["code"], -32601);
+        server.handle(&json!({"jsonrpc": "2.0", "method": "exit"}));
+        assert!(server.exited);
+    }
+}
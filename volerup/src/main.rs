@@ -1,6 +1,17 @@
+pub mod batch;
+pub mod browser;
+pub mod cli;
+pub mod dap;
+pub mod debug;
+pub mod gdb;
+pub mod grade;
+pub mod lsp;
 pub mod model;
+pub mod run;
+pub mod serve;
 pub mod update;
 pub mod view;
+pub mod worker;
 
 use std::env;
 
@@ -9,9 +20,13 @@ use update::{handle_event, update};
 use view::view;
 
 fn main() -> color_eyre::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if let Some(code) = cli::dispatch(&args) {
+        std::process::exit(code);
+    }
+
     color_eyre::install()?;
 
-    let args: Vec<String> = env::args().collect();
     match get_model(args) {
         Ok(mut model) => {
             let mut terminal = ratatui::init();
@@ -35,14 +50,35 @@ fn get_model<'a>(args: Vec<String>) -> Result<Model<'a>, String> {
         Ok(Model::default())
     } else if args.len() == 2 {
         match std::fs::read_to_string(args[1].as_str()) {
-            Ok(input) => Model::init_from_source(input.as_str()),
+            Ok(input) => Model::init_from_source(input.as_str()).map(|mut model| {
+                model.path = Some(args[1].as_str().into());
+                model
+            }),
             Err(e) => {
                 let s = e.to_string();
                 Err(s)
             }
         }
     } else {
-        let usage = format!("Usage: {} [path_to_file]", args[0]);
+        let usage = [
+            "[path_to_file]",
+            "run <path_to_file> [options]",
+            "grade <path_to_file>... --spec <path_to_spec>... [options]",
+            "batch <directory> [options]",
+            "debug <path_to_file>",
+            "gdb <path_to_file> [--port N]",
+            "dap",
+            "lsp",
+            "serve [--port N] [--allow-origin ORIGIN]",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let prefix = if i == 0 { "Usage:" } else { "      " };
+            format!("{} {} {}", prefix, args[0], line)
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
         Err(usage)
     }
 }
@@ -57,6 +93,16 @@ mod tests {
         assert!(model.is_err())
     }
 
+    #[test]
+    fn test_usage_lists_subcommands() {
+        let usage = get_model(vec!["volerup".into(), "a".into(), "b".into()]).unwrap_err();
+        for subcommand in [
+            "run", "grade", "batch", "debug", "gdb", "dap", "lsp", "serve",
+        ] {
+            assert!(usage.contains(&format!("volerup {subcommand}")));
+        }
+    }
+
     #[test]
     fn test_no_args() {
         let model = get_model(vec!["volerup".into()]);
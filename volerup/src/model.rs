@@ -1,8 +1,17 @@
+use std::cell::Cell;
+use std::path::PathBuf;
+use std::time::Duration;
+
 use ratatui::style::{Color, Style};
 use tui_textarea::TextArea;
-use vole_rs::vole::Cpu;
+use vole_rs::{floating::Floating, multicore::Multicore, pipeline::Pipeline, vole::Cpu};
 
-use crate::update::parse_program_text;
+use crate::browser::FileBrowser;
+use crate::update::{
+    ANIMATION_SPEEDS, Diagnostic, PROGRESS_REDRAW_INTERVAL, SourceMap, check_program,
+    describe_diagnostics, init_cpu, parse_program_with_map,
+};
+use crate::worker::Worker;
 
 #[derive(Debug, PartialEq)]
 pub(crate) enum Focus {
@@ -11,18 +20,177 @@ pub(crate) enum Focus {
     Program,
 }
 
+/// The optional panels shown below the CPU state.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Panel {
+    Mmu,
+    Cache,
+    Pipeline,
+}
+
+/// How the values of the registers or the memory are shown.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub(crate) enum NumberFormat {
+    #[default]
+    Hex,
+    Unsigned,
+    Signed,
+    Binary,
+    Ascii,
+    Float,
+}
+
+impl NumberFormat {
+    /// The format to switch to next.
+    pub(crate) fn next(self) -> Self {
+        match self {
+            NumberFormat::Hex => NumberFormat::Unsigned,
+            NumberFormat::Unsigned => NumberFormat::Signed,
+            NumberFormat::Signed => NumberFormat::Binary,
+            NumberFormat::Binary => NumberFormat::Ascii,
+            NumberFormat::Ascii => NumberFormat::Float,
+            NumberFormat::Float => NumberFormat::Hex,
+        }
+    }
+
+    /// The short name shown in the title of a pane.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            NumberFormat::Hex => "hex",
+            NumberFormat::Unsigned => "dec",
+            NumberFormat::Signed => "signed",
+            NumberFormat::Binary => "bin",
+            NumberFormat::Ascii => "ascii",
+            NumberFormat::Float => "float",
+        }
+    }
+
+    /// Explain how a byte is read in this format.
+    pub(crate) fn description(self) -> &'static str {
+        match self {
+            NumberFormat::Hex => "two hex digits, each one stands for four bits",
+            NumberFormat::Unsigned => "unsigned integer from 0 to 255",
+            NumberFormat::Signed => {
+                "two's complement from -128 to 127, the highest bit counts -128"
+            }
+            NumberFormat::Binary => "the eight bits, the highest one first",
+            NumberFormat::Ascii => "the ASCII character with this code, \\xNN if not printable",
+            NumberFormat::Float => "sign bit, 3 bit exponent in excess-4 notation, 4 bit mantissa",
+        }
+    }
+
+    /// The widest text of a value in this format.
+    pub(crate) fn width(self) -> usize {
+        match self {
+            NumberFormat::Hex | NumberFormat::Unsigned | NumberFormat::Signed => 4,
+            NumberFormat::Ascii => 4,
+            NumberFormat::Binary | NumberFormat::Float => 10,
+        }
+    }
+
+    pub(crate) fn format(self, value: u8) -> String {
+        match self {
+            NumberFormat::Hex => format!("0x{:02X}", value),
+            NumberFormat::Unsigned => value.to_string(),
+            NumberFormat::Signed => (value as i8).to_string(),
+            NumberFormat::Binary => format!("0b{:08b}", value),
+            NumberFormat::Ascii if value.is_ascii_graphic() || value == b' ' => {
+                format!("'{}'", value as char)
+            }
+            NumberFormat::Ascii => format!("\\x{:02X}", value),
+            NumberFormat::Float => Floating { value }.decode().to_string(),
+        }
+    }
+}
+
+/// The state of an animated run.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct Animation {
+    /// The index into the animation speeds.
+    pub(crate) speed: usize,
+    pub(crate) paused: bool,
+}
+
+/// A question which takes the keyboard until it's answered.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Prompt {
+    /// Ask for the path to save the program to.
+    SaveAs { path: String },
+    /// Ask whether to quit with unsaved changes.
+    ConfirmQuit,
+    /// Ask whether to open another file with unsaved changes.
+    ConfirmOpen { path: PathBuf },
+    /// Ask for the new value of a register or memory cell.
+    EditValue { target: EditTarget, text: String },
+}
+
+/// A register or memory cell to edit by hand.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum EditTarget {
+    /// A register of the core, core 0 is the CPU if there is only one.
+    Register { core: usize, reg: usize },
+    /// A memory cell, with the bank if it lies in the window of a browsed bank that isn't mapped.
+    Memory { addr: usize, bank: Option<usize> },
+}
+
+/// A value edited by hand, kept to undo the edit.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct Edit {
+    pub(crate) target: EditTarget,
+    pub(crate) old: u8,
+}
+
+/// Create the editor for the lines of a program text.
+pub(crate) fn program_textarea<'a>(lines: Vec<String>) -> TextArea<'a> {
+    let mut program_textarea = TextArea::new(lines);
+    let style = Style::default().fg(Color::Green);
+    program_textarea.set_line_number_style(style);
+    program_textarea.set_style(style);
+    program_textarea
+}
+
 #[derive(Debug)]
 pub(crate) struct Model<'a> {
     pub(crate) cpu: vole_rs::vole::Cpu,
+    pub(crate) pipeline: Option<Pipeline>,
+    pub(crate) multicore: Option<Multicore>,
     pub(crate) program_textarea: TextArea<'a>,
+    /// The map of the loaded program text.
+    pub(crate) source_map: SourceMap,
+    /// The invalid words of the program text, updated while it is edited.
+    pub(crate) diagnostics: Vec<Diagnostic>,
+    /// The first line shown in the program pane, set when it is drawn.
+    pub(crate) program_scroll: Cell<usize>,
+    /// The file the program was opened from or saved to.
+    pub(crate) path: Option<PathBuf>,
+    /// True if the program has been edited since it was opened or saved.
+    pub(crate) dirty: bool,
+    pub(crate) prompt: Option<Prompt>,
+    pub(crate) browser: Option<FileBrowser>,
     pub(crate) running: bool,
     pub(crate) focus: Focus,
     pub(crate) memory_scroll: usize,
     pub(crate) registers_scroll: usize,
+    pub(crate) selected_memory: usize,
+    pub(crate) selected_register: usize,
+    pub(crate) memory_format: NumberFormat,
+    /// True if the memory is shown as a grid of 16 by 16 cells instead of a list.
+    pub(crate) memory_grid: bool,
+    /// True if the memory list scrolls to keep the program counter visible.
+    pub(crate) follow_pc: bool,
+    pub(crate) register_format: NumberFormat,
+    /// The number of rows shown in the register and memory panes, set when they are drawn.
+    pub(crate) pane_rows: Cell<usize>,
+    /// The values edited by hand since the program was loaded, the last one is undone first.
+    pub(crate) edits: Vec<Edit>,
     pub(crate) modified_register: Option<usize>,
     pub(crate) modified_memory: Option<usize>,
     pub(crate) show_help: bool,
-    pub(crate) error_msg: Option<&'a str>,
+    pub(crate) panel: Option<Panel>,
+    pub(crate) browsed_bank: Option<usize>,
+    pub(crate) animation: Option<Animation>,
+    pub(crate) worker: Option<Worker>,
+    pub(crate) error_msg: Option<String>,
 }
 
 impl<'a> Default for Model<'a> {
@@ -44,52 +212,237 @@ impl<'a> Model<'a> {
                 }
             })
             .collect();
-        let mut program_textarea = TextArea::new(program_text);
-        let style = Style::default().fg(Color::Green);
-        program_textarea.set_line_number_style(style);
-        program_textarea.set_style(style);
+        let program_textarea = program_textarea(program_text);
+        let source_map = SourceMap::new(program_textarea.lines());
 
         Model {
-            cpu: Cpu::init(&program),
+            cpu: init_cpu(&program, false),
+            pipeline: None,
+            multicore: None,
             program_textarea,
+            source_map,
+            diagnostics: vec![],
+            program_scroll: Cell::new(0),
+            path: None,
+            dirty: false,
+            prompt: None,
+            browser: None,
             running: true,
             focus: Focus::Memory,
             memory_scroll: 0,
             registers_scroll: 0,
+            selected_memory: 0,
+            selected_register: 0,
+            memory_format: NumberFormat::Hex,
+            memory_grid: false,
+            follow_pc: true,
+            register_format: NumberFormat::Hex,
+            pane_rows: Cell::new(0),
+            edits: vec![],
             modified_register: None,
             modified_memory: None,
             show_help: false,
+            panel: None,
+            browsed_bank: None,
+            animation: None,
+            worker: None,
             error_msg: None,
         }
     }
 
+    /// The CPU to show, the selected core if there are several.
+    pub(crate) fn shown_cpu(&self) -> &Cpu {
+        match &self.multicore {
+            Some(multicore) => &multicore.cores[multicore.selected],
+            None => &self.cpu,
+        }
+    }
+
+    /// The bank shown in the memory view, the mapped one unless another one is browsed.
+    pub(crate) fn shown_bank(&self) -> Option<usize> {
+        let banks = self.shown_cpu().banks.as_ref()?;
+        Some(
+            self.browsed_bank
+                .filter(|b| *b < banks.banks.len())
+                .unwrap_or(banks.current),
+        )
+    }
+
+    /// The register or memory cell selected in the focused pane.
+    pub(crate) fn edit_target(&self) -> Option<EditTarget> {
+        match self.focus {
+            Focus::Registers => Some(EditTarget::Register {
+                core: self.multicore.as_ref().map_or(0, |m| m.selected),
+                reg: self.selected_register,
+            }),
+            Focus::Memory => {
+                let addr = self.selected_memory;
+                let bank = match (&self.shown_cpu().banks, self.shown_bank()) {
+                    (Some(banks), Some(shown))
+                        if self.multicore.is_none()
+                            && shown != banks.current
+                            && banks.window().contains(&addr) =>
+                    {
+                        Some(shown)
+                    }
+                    _ => None,
+                };
+                Some(EditTarget::Memory { addr, bank })
+            }
+            Focus::Program => None,
+        }
+    }
+
+    /// Get the value of a register or memory cell.
+    pub(crate) fn value(&self, target: EditTarget) -> u8 {
+        match (target, &self.multicore) {
+            (EditTarget::Register { core, reg }, Some(multicore)) => {
+                multicore.cores[core].registers[reg]
+            }
+            (EditTarget::Register { reg, .. }, None) => self.cpu.registers[reg],
+            (EditTarget::Memory { addr, .. }, Some(multicore)) => multicore.memory()[addr],
+            (EditTarget::Memory { addr, bank }, None) => match (bank, &self.cpu.banks) {
+                (Some(bank), Some(banks)) if bank != banks.current => {
+                    banks.banks[bank][addr - banks.window().start]
+                }
+                _ => self.cpu.memory[addr],
+            },
+        }
+    }
+
+    /// Set the value of a register or memory cell and highlight it.
+    pub(crate) fn set_value(&mut self, target: EditTarget, value: u8) {
+        match (target, &mut self.multicore) {
+            (EditTarget::Register { core, reg }, Some(multicore)) => {
+                multicore.cores[core].registers[reg] = value
+            }
+            (EditTarget::Register { reg, .. }, None) => self.cpu.registers[reg] = value,
+            (EditTarget::Memory { addr, .. }, Some(multicore)) => {
+                multicore.set_memory(addr as u8, value)
+            }
+            (EditTarget::Memory { addr, bank }, None) => match (bank, &mut self.cpu.banks) {
+                (Some(bank), Some(banks)) if bank != banks.current => {
+                    let start = banks.window().start;
+                    banks.banks[bank][addr - start] = value
+                }
+                _ => self.cpu.set_memory(addr as u8, value),
+            },
+        }
+        match target {
+            EditTarget::Register { reg, .. } => {
+                self.modified_register = Some(reg);
+                self.selected_register = reg;
+            }
+            EditTarget::Memory { addr, .. } => {
+                self.modified_memory = Some(addr);
+                self.selected_memory = addr;
+            }
+        }
+    }
+
+    /// The offset into the loaded program of the next instruction of the shown CPU,
+    /// which lies in a later bank if such a bank is mapped.
+    pub(crate) fn next_instruction_offset(&self) -> usize {
+        let cpu = self.shown_cpu();
+        let pc = cpu.program_counter;
+        match &cpu.banks {
+            Some(banks) if banks.current > 0 && banks.window().contains(&pc) => {
+                let window = banks.window();
+                cpu.memory.len() + (banks.current - 1) * window.len() + pc - window.start
+            }
+            _ => pc,
+        }
+    }
+
+    /// True if the CPU has halted, or all cores if there are several.
+    pub(crate) fn halted(&self) -> bool {
+        match &self.multicore {
+            Some(multicore) => multicore.halted(),
+            None => self.cpu.halted,
+        }
+    }
+
+    /// The time to wait for input before the next tick, None if nothing runs in the background.
+    pub(crate) fn tick_interval(&self) -> Option<Duration> {
+        if self.worker.is_some() {
+            return Some(PROGRESS_REDRAW_INTERVAL);
+        }
+        self.animation
+            .filter(|animation| !animation.paused)
+            .map(|animation| ANIMATION_SPEEDS[animation.speed].0)
+    }
+
+    /// The panels of the devices attached to the CPU.
+    pub(crate) fn available_panels(&self) -> Vec<Panel> {
+        let mut panels = vec![];
+        let cpu = self.shown_cpu();
+        if cpu.mmu.is_some() {
+            panels.push(Panel::Mmu);
+        }
+        if cpu.cache.is_some() {
+            panels.push(Panel::Cache);
+        }
+        if self.pipeline.is_some() {
+            panels.push(Panel::Pipeline);
+        }
+        panels
+    }
+
+    /// The panel to show, falls back to the first available one.
+    pub(crate) fn shown_panel(&self) -> Option<Panel> {
+        let panels = self.available_panels();
+        match self.panel {
+            Some(panel) if panels.contains(&panel) => Some(panel),
+            _ => panels.first().copied(),
+        }
+    }
+
     pub(crate) fn init_from_source(program_text: &str) -> Result<Model<'a>, String> {
         let lines = program_text
             .lines()
             .map(|s| s.to_string())
             .collect::<Vec<String>>();
-        let program = parse_program_text(&lines);
-        let mut program_textarea = TextArea::new(lines);
-        let style = Style::default().fg(Color::Green);
-        program_textarea.set_line_number_style(style);
-        program_textarea.set_style(style);
+        let program = parse_program_with_map(&lines);
+        let program_textarea = program_textarea(lines);
         match program {
-            Ok(program) => {
+            Ok((program, source_map)) => {
+                check_program(&program, false)?;
                 let model = Model {
-                    cpu: Cpu::init(&program),
+                    cpu: init_cpu(&program, false),
+                    pipeline: None,
+                    multicore: None,
                     program_textarea,
+                    source_map,
+                    diagnostics: vec![],
+                    program_scroll: Cell::new(0),
+                    path: None,
+                    dirty: false,
+                    prompt: None,
+                    browser: None,
                     running: true,
                     focus: Focus::Memory,
                     memory_scroll: 0,
                     registers_scroll: 0,
+                    selected_memory: 0,
+                    selected_register: 0,
+                    memory_format: NumberFormat::Hex,
+                    memory_grid: false,
+                    follow_pc: true,
+                    register_format: NumberFormat::Hex,
+                    pane_rows: Cell::new(0),
+                    edits: vec![],
                     modified_register: None,
                     modified_memory: None,
                     show_help: false,
+                    panel: None,
+                    browsed_bank: None,
+                    animation: None,
+                    worker: None,
                     error_msg: None,
                 };
                 Ok(model)
             }
-            Err(e) => Err(e.into()),
+            Err(diagnostics) => Err(describe_diagnostics(&diagnostics)),
         }
     }
 }
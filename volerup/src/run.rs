@@ -0,0 +1,206 @@
+use serde_json::{Value, json};
+use vole_rs::vole::{Cpu, StopReason};
+
+use crate::cli::{exit_code, load_program, parse_number, stop_name};
+
+/// The cycle limit if none is given.
+pub(crate) const DEFAULT_MAX_CYCLES: u32 = 1_000_000;
+
+const USAGE: &str = "Usage: volerup run <path_to_file> [--max-cycles N] [--dump registers,memory] [--format text|json]";
+
+/// The parts of the final state to print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Dump {
+    Registers,
+    Memory,
+}
+
+/// How the final state is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Text,
+    Json,
+}
+
+/// The arguments of the run subcommand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RunArgs {
+    pub(crate) path: String,
+    pub(crate) max_cycles: u32,
+    pub(crate) dumps: Vec<Dump>,
+    pub(crate) format: Format,
+}
+
+/// Parse a comma separated list of parts to dump.
+pub(crate) fn parse_dumps(s: &str) -> Result<Vec<Dump>, String> {
+    s.split(',')
+        .filter(|part| !part.is_empty())
+        .map(|part| match part {
+            "registers" => Ok(Dump::Registers),
+            "memory" => Ok(Dump::Memory),
+            _ => Err(format!("unknown dump: {part}")),
+        })
+        .collect()
+}
+
+/// Parse the output format.
+pub(crate) fn parse_format(s: &str) -> Result<Format, String> {
+    match s {
+        "text" => Ok(Format::Text),
+        "json" => Ok(Format::Json),
+        _ => Err(format!("unknown format: {s}")),
+    }
+}
+
+/// Parse the arguments following the subcommand.
+fn parse_args(args: &[String]) -> Result<RunArgs, String> {
+    let mut path = None;
+    let mut max_cycles = DEFAULT_MAX_CYCLES;
+    let mut dumps = vec![Dump::Registers];
+    let mut format = Format::Text;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or(format!("missing value for {arg}\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--max-cycles" => max_cycles = parse_number(value()?)?,
+            "--dump" => dumps = parse_dumps(value()?)?,
+            "--format" => format = parse_format(value()?)?,
+            _ if arg.starts_with("--") || path.is_some() => {
+                return Err(format!("unexpected argument: {arg}\n{USAGE}"));
+            }
+            _ => path = Some(arg.clone()),
+        }
+    }
+
+    match path {
+        Some(path) => Ok(RunArgs {
+            path,
+            max_cycles,
+            dumps,
+            format,
+        }),
+        None => Err(USAGE.into()),
+    }
+}
+
+/// Format bytes as rows of 16 hex values prefixed by the address of the row.
+pub(crate) fn hex_rows(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let values = chunk
+                .iter()
+                .map(|b| format!("{b:02X}"))
+                .collect::<Vec<String>>()
+                .join(" ");
+            format!("{:02X}: {values}\n", row * 16)
+        })
+        .collect()
+}
+
+/// Format the final state as text.
+pub(crate) fn text_report(cpu: &Cpu, reason: StopReason, dumps: &[Dump]) -> String {
+    let mut report = format!(
+        "stop: {}\ncycles: {}\nprogram counter: 0x{:02X}\n",
+        stop_name(reason),
+        cpu.cycle,
+        cpu.program_counter
+    );
+    for dump in dumps {
+        match dump {
+            Dump::Registers => {
+                report.push_str("registers:\n");
+                report.push_str(&hex_rows(&cpu.registers));
+            }
+            Dump::Memory => {
+                report.push_str("memory:\n");
+                report.push_str(&hex_rows(&cpu.memory));
+            }
+        }
+    }
+    report
+}
+
+/// Format the final state as JSON.
+pub(crate) fn json_report(cpu: &Cpu, reason: StopReason, dumps: &[Dump]) -> Value {
+    let mut report = json!({
+        "stop": stop_name(reason),
+        "exit_code": exit_code(reason),
+        "cycles": cpu.cycle,
+        "program_counter": cpu.program_counter,
+    });
+    for dump in dumps {
+        match dump {
+            Dump::Registers => report["registers"] = json!(cpu.registers),
+            Dump::Memory => report["memory"] = json!(cpu.memory.to_vec()),
+        }
+    }
+    report
+}
+
+/// Run a program without a terminal and print its final state.
+/// Returns the exit code for the reason the CPU stopped.
+pub(crate) fn run(args: &[String]) -> Result<i32, String> {
+    let args = parse_args(args)?;
+    let mut cpu = load_program(&args.path)?;
+    let reason = cpu.run_for(args.max_cycles);
+    match args.format {
+        Format::Text => print!("{}", text_report(&cpu, reason, &args.dumps)),
+        Format::Json => println!("{}", json_report(&cpu, reason, &args.dumps)),
+    }
+    Ok(exit_code(reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let parsed = parse_args(&args(&[
+            "prog.vole",
+            "--max-cycles",
+            "10000",
+            "--dump",
+            "registers,memory",
+            "--format",
+            "json",
+        ]));
+        assert_eq!(
+            parsed,
+            Ok(RunArgs {
+                path: "prog.vole".into(),
+                max_cycles: 10000,
+                dumps: vec![Dump::Registers, Dump::Memory],
+                format: Format::Json,
+            })
+        );
+        assert!(parse_args(&args(&[])).is_err());
+        assert!(parse_args(&args(&["a.vole", "b.vole"])).is_err());
+        assert!(parse_args(&args(&["a.vole", "--max-cycles"])).is_err());
+        assert!(parse_args(&args(&["a.vole", "--dump", "stack"])).is_err());
+    }
+
+    #[test]
+    fn test_reports() {
+        let program = [0x14, 0x02, 0x34, 0x17, 0xC0, 0x00];
+        let mut cpu = Cpu::init(&program);
+        let reason = cpu.run_for(10);
+        let text = text_report(&cpu, reason, &[Dump::Registers]);
+        assert!(text.starts_with("stop: halted\ncycles: 3\n"));
+        assert!(text.contains("00: 00 00 00 00 34 00"));
+        let json = json_report(&cpu, reason, &[Dump::Memory]);
+        assert_eq!(json["stop"], "halted");
+        assert_eq!(json["memory"][0x17], 0x34);
+        assert!(json.get("registers").is_none());
+    }
+}
@@ -0,0 +1,674 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+use vole_rs::vole::{Cpu, StopReason};
+
+use crate::cli::{EXIT_SUCCESS, parse_number, parse_program, stop_name};
+use crate::update::init_cpu;
+
+const USAGE: &str = "Usage: volerup serve [--port N] [--allow-origin ORIGIN]";
+
+/// The port the service listens on if none is given.
+pub(crate) const DEFAULT_PORT: u16 = 8080;
+
+/// The maximum number of sessions kept at the same time.
+pub(crate) const MAX_SESSIONS: usize = 64;
+
+/// How long a session is kept without requests once [MAX_SESSIONS] sessions exist.
+pub(crate) const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// The maximum number of cycles a single step or run request executes.
+pub(crate) const MAX_CYCLES_PER_REQUEST: u32 = 1_000_000;
+
+/// The maximum size of a request body in bytes.
+pub(crate) const MAX_BODY_LEN: usize = 64 * 1024;
+
+/// The number of executed instructions kept per session.
+pub(crate) const TRACE_LEN: usize = 1000;
+
+/// The maximum length of the request line and of each header line in bytes.
+pub(crate) const MAX_LINE_LEN: u64 = 8 * 1024;
+
+/// The maximum number of header lines of a request.
+pub(crate) const MAX_HEADERS: usize = 100;
+
+/// The maximum number of connections served at the same time.
+pub(crate) const MAX_CONNECTIONS: usize = 32;
+
+/// How long a client may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client may take to receive the reply.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An executed instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TraceEntry {
+    cycle: u32,
+    addr: usize,
+    instruction: u16,
+}
+
+/// A machine created by a client.
+#[derive(Debug, Clone)]
+struct Session {
+    cpu: Cpu,
+    trace: VecDeque<TraceEntry>,
+}
+
+impl Session {
+    fn state(&self) -> Value {
+        json!({
+            "program_counter": self.cpu.program_counter,
+            "instruction_register": self.cpu.instruction_register,
+            "cycles": self.cpu.cycle,
+            "halted": self.cpu.halted,
+            "registers": self.cpu.registers,
+        })
+    }
+
+    /// Execute up to the given number of cycles, recording them in the trace.
+    /// Returns the reply with the stop reason and the state.
+    fn run(&mut self, max_cycles: u32) -> Value {
+        let mut executed = 0;
+        let reason = loop {
+            if self.cpu.halted {
+                break StopReason::Halted;
+            }
+            if executed == max_cycles {
+                break StopReason::CycleLimit;
+            }
+            let addr = self.cpu.program_counter;
+            let ok = self.cpu.cycle();
+            if self.trace.len() == TRACE_LEN {
+                self.trace.pop_front();
+            }
+            self.trace.push_back(TraceEntry {
+                cycle: self.cpu.cycle,
+                addr,
+                instruction: self.cpu.instruction_register,
+            });
+            executed += 1;
+            if !ok {
+                break if self.cpu.page_faulted() {
+                    StopReason::PageFault
+                } else {
+                    StopReason::IllegalInstruction
+                };
+            }
+        };
+        json!({ "stop": stop_name(reason), "executed": executed, "state": self.state() })
+    }
+
+    fn trace(&self) -> Value {
+        let entries = self
+            .trace
+            .iter()
+            .map(|entry| {
+                let opcode = Cpu::decode_instruction(entry.instruction)
+                    .filter(|opcode| self.cpu.supports(opcode))
+                    .map_or("illegal instruction".to_string(), |opcode| {
+                        opcode.to_string()
+                    });
+                json!({
+                    "cycle": entry.cycle,
+                    "address": entry.addr,
+                    "instruction": format!("{:04X}", entry.instruction),
+                    "opcode": opcode,
+                })
+            })
+            .collect::<Vec<Value>>();
+        json!({ "trace": entries })
+    }
+}
+
+/// The reply to a request: the status code and the body.
+type Reply = (u16, Value);
+
+fn error(status: u16, message: impl Into<String>) -> Reply {
+    (status, json!({ "error": message.into() }))
+}
+
+/// Get a byte value from a field of the body.
+fn byte_field(body: &Value, field: &str) -> Result<u8, Reply> {
+    body[field]
+        .as_u64()
+        .and_then(|v| u8::try_from(v).ok())
+        .ok_or_else(|| error(400, format!("{field} must be a number from 0 to 255")))
+}
+
+/// Get the cycle limit from a field of the body, capped at [MAX_CYCLES_PER_REQUEST].
+fn cycles_field(body: &Value, field: &str, default: u32) -> Result<u32, Reply> {
+    match &body[field] {
+        Value::Null => Ok(default),
+        value => value
+            .as_u64()
+            .map(|v| v.min(MAX_CYCLES_PER_REQUEST as u64) as u32)
+            .ok_or_else(|| error(400, format!("{field} must be a number"))),
+    }
+}
+
+/// A session and the time of the last request to it.
+#[derive(Debug)]
+struct Entry {
+    session: Arc<Mutex<Session>>,
+    last_used: Instant,
+}
+
+/// The sessions of the service.
+/// Each session has its own lock, so a long run only blocks requests to the same session.
+#[derive(Debug)]
+pub(crate) struct Api {
+    sessions: Mutex<BTreeMap<u64, Entry>>,
+    next_id: AtomicU64,
+    /// Sessions idle for longer are dropped when a new one doesn't fit.
+    idle_timeout: Duration,
+}
+
+impl Default for Api {
+    fn default() -> Self {
+        Api {
+            sessions: Mutex::default(),
+            next_id: AtomicU64::new(0),
+            idle_timeout: SESSION_IDLE_TIMEOUT,
+        }
+    }
+}
+
+/// Lock the mutex, also if a thread panicked while holding it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Api {
+    fn create(&self, body: &Value) -> Reply {
+        let Some(program_text) = body["program"].as_str() else {
+            return error(400, "program must be the program text");
+        };
+        match parse_program(program_text) {
+            Ok(program) => {
+                let mut sessions = lock(&self.sessions);
+                let now = Instant::now();
+                if sessions.len() >= MAX_SESSIONS {
+                    // a session taken by a request is not idle
+                    sessions.retain(|_, entry| {
+                        Arc::strong_count(&entry.session) > 1
+                            || now.duration_since(entry.last_used) < self.idle_timeout
+                    });
+                }
+                if sessions.len() >= MAX_SESSIONS {
+                    return error(429, "too many sessions");
+                }
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+                let session = Session {
+                    cpu: init_cpu(&program, false),
+                    trace: VecDeque::new(),
+                };
+                let reply = json!({ "id": id, "state": session.state() });
+                let entry = Entry {
+                    session: Arc::new(Mutex::new(session)),
+                    last_used: now,
+                };
+                sessions.insert(id, entry);
+                (201, reply)
+            }
+            Err(e) => error(400, e),
+        }
+    }
+
+    fn session_request(
+        session: &mut Session,
+        method: &str,
+        path: &[&str],
+        body: &Value,
+    ) -> Result<Reply, Reply> {
+        let reply = match (method, path) {
+            ("GET", []) => session.state(),
+            ("POST", ["step"]) => session.run(cycles_field(body, "count", 1)?),
+            ("POST", ["run"]) => {
+                session.run(cycles_field(body, "max_cycles", MAX_CYCLES_PER_REQUEST)?)
+            }
+            ("GET", ["registers"]) => json!({ "registers": session.cpu.registers }),
+            ("GET", ["memory"]) => json!({ "memory": session.cpu.memory.to_vec() }),
+            ("PUT", ["registers", reg]) => {
+                let reg = parse_number(reg)
+                    .ok()
+                    .filter(|reg| *reg < 16)
+                    .ok_or_else(|| error(404, "no such register"))?;
+                session.cpu.registers[reg as usize] = byte_field(body, "value")?;
+                json!({ "registers": session.cpu.registers })
+            }
+            ("PUT", ["memory", addr]) => {
+                let addr = parse_number(addr)
+                    .ok()
+                    .filter(|addr| *addr < 256)
+                    .ok_or_else(|| error(404, "no such memory cell"))?;
+                session.cpu.memory[addr as usize] = byte_field(body, "value")?;
+                json!({ "memory": session.cpu.memory.to_vec() })
+            }
+            ("GET", ["trace"]) => session.trace(),
+            _ => return Err(error(404, "not found")),
+        };
+        Ok((200, reply))
+    }
+
+    /// Handle a request with a JSON body.
+    pub(crate) fn handle(&self, method: &str, path: &str, body: &str) -> Reply {
+        let body = if body.trim().is_empty() {
+            Value::Null
+        } else {
+            match serde_json::from_str(body) {
+                Ok(body) => body,
+                Err(e) => return error(400, e.to_string()),
+            }
+        };
+        let path = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<&str>>();
+        match (method, path.as_slice()) {
+            ("POST", ["sessions"]) => self.create(&body),
+            ("GET", ["sessions"]) => (
+                200,
+                json!({ "sessions": lock(&self.sessions).keys().collect::<Vec<_>>() }),
+            ),
+            (_, ["sessions", id, rest @ ..]) => {
+                let mut sessions = lock(&self.sessions);
+                let Some((id, entry)) = id
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|id| Some((id, sessions.get_mut(&id)?)))
+                else {
+                    return error(404, "no such session");
+                };
+                entry.last_used = Instant::now();
+                let session = Arc::clone(&entry.session);
+                if method == "DELETE" && rest.is_empty() {
+                    sessions.remove(&id);
+                    return (200, json!({ "deleted": id }));
+                }
+                // don't block the other sessions while this one runs
+                drop(sessions);
+                let mut session = lock(&session);
+                Api::session_request(&mut session, method, rest, &body).unwrap_or_else(|e| e)
+            }
+            _ => error(404, "not found"),
+        }
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Read a line of at most [MAX_LINE_LEN] bytes.
+fn read_line(reader: &mut impl BufRead) -> Result<String, Reply> {
+    let mut line = String::new();
+    reader
+        .take(MAX_LINE_LEN)
+        .read_line(&mut line)
+        .map_err(|_| error(400, "bad request"))?;
+    if line.len() as u64 == MAX_LINE_LEN && !line.ends_with('\n') {
+        return Err(error(431, "request line or header too long"));
+    }
+    Ok(line)
+}
+
+/// Read a request: the method, the path and the body.
+fn read_request(reader: &mut impl BufRead) -> Result<(String, String, String), Reply> {
+    let bad_request = |_| error(400, "bad request");
+    let request_line = read_line(reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(error(400, "bad request"));
+    };
+    let mut len = 0;
+    for headers in 0.. {
+        let header = read_line(reader)?;
+        if header.trim().is_empty() {
+            break;
+        }
+        if headers == MAX_HEADERS {
+            return Err(error(431, "too many headers"));
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            len = value
+                .trim()
+                .parse()
+                .map_err(|_| error(400, "bad content length"))?;
+        }
+    }
+    if len > MAX_BODY_LEN {
+        return Err(error(413, "request body too large"));
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).map_err(bad_request)?;
+    let body = String::from_utf8(body).map_err(|_| error(400, "body is not UTF-8"))?;
+    Ok((method.to_string(), path.to_string(), body))
+}
+
+/// Answer a single request on the connection.
+/// Browsers only let pages from `allow_origin` use the reply, no page at all without it.
+pub(crate) fn serve_connection(
+    api: &Api,
+    mut stream: TcpStream,
+    allow_origin: Option<&str>,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let reply = match read_request(&mut reader) {
+        Ok((method, _, _)) if method == "OPTIONS" => (204, Value::Null),
+        Ok((method, path, body)) => api.handle(&method, &path, &body),
+        Err(reply) => reply,
+    };
+    write_reply(&mut stream, reply, allow_origin)
+}
+
+/// Write the reply and close the connection.
+fn write_reply(
+    stream: &mut impl Write,
+    (status, body): Reply,
+    allow_origin: Option<&str>,
+) -> std::io::Result<()> {
+    let body = if body.is_null() {
+        String::new()
+    } else {
+        body.to_string()
+    };
+    let cors = allow_origin.map_or(String::new(), |origin| {
+        format!(
+            "Access-Control-Allow-Origin: {origin}\r\nAccess-Control-Allow-Methods: GET, POST, PUT, DELETE, OPTIONS\r\nAccess-Control-Allow-Headers: Content-Type\r\nVary: Origin\r\n"
+        )
+    });
+    write!(
+        stream,
+        "HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{cors}Connection: close\r\n\r\n{body}",
+        reason_phrase(status),
+        body.len()
+    )?;
+    stream.flush()
+}
+
+/// A connection being served, counted in the shared counter till it is dropped.
+#[derive(Debug)]
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    /// Count a new connection, None if [MAX_CONNECTIONS] are served already.
+    fn open(connections: &Arc<AtomicUsize>) -> Option<Self> {
+        connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < MAX_CONNECTIONS).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Connection(Arc::clone(connections)))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Serve the HTTP/JSON API on a local TCP port.
+pub(crate) fn serve(args: &[String]) -> Result<i32, String> {
+    let mut port = DEFAULT_PORT;
+    let mut allow_origin = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or(format!("missing value for {arg}\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--port" => {
+                let n = parse_number(value()?)?;
+                port = u16::try_from(n).map_err(|_| format!("invalid port: {n}"))?;
+            }
+            "--allow-origin" => allow_origin = Some(value()?.clone()),
+            _ => return Err(format!("unexpected argument: {arg}\n{USAGE}")),
+        }
+    }
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| e.to_string())?;
+    println!("listening on http://127.0.0.1:{port}");
+    let api = Arc::new(Api::default());
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        let Some(connection) = Connection::open(&connections) else {
+            let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+            let _ = write_reply(
+                &mut stream,
+                error(503, "too many connections"),
+                allow_origin.as_deref(),
+            );
+            continue;
+        };
+        let api = Arc::clone(&api);
+        let allow_origin = allow_origin.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = serve_connection(&api, stream, allow_origin.as_deref()) {
+                eprintln!("{e}");
+            }
+            drop(connection);
+        });
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "14 02 34 17 C0 00";
+
+    fn create(api: &Api, program: &str) -> u64 {
+        let body = json!({ "program": program }).to_string();
+        let (status, reply) = api.handle("POST", "/sessions", &body);
+        assert_eq!(status, 201);
+        reply["id"].as_u64().unwrap()
+    }
+
+    #[test]
+    fn test_sessions() {
+        let api = Api::default();
+        let id = create(&api, PROGRAM);
+        let (status, reply) = api.handle("POST", &format!("/sessions/{id}/step"), "");
+        assert_eq!(status, 200);
+        assert_eq!(reply["executed"], 1);
+        assert_eq!(reply["state"]["registers"][4], 0x34);
+        let (_, reply) = api.handle(
+            "POST",
+            &format!("/sessions/{id}/run"),
+            r#"{"max_cycles": 10}"#,
+        );
+        assert_eq!(reply["stop"], "halted");
+        assert_eq!(reply["state"]["cycles"], 3);
+        let (_, reply) = api.handle("GET", &format!("/sessions/{id}/trace"), "");
+        assert_eq!(reply["trace"][1]["opcode"], "STORE 0x04 0x17");
+        assert_eq!(reply["trace"][2]["address"], 4);
+
+        let (status, _) = api.handle(
+            "PUT",
+            &format!("/sessions/{id}/registers/3"),
+            r#"{"value": 7}"#,
+        );
+        assert_eq!(status, 200);
+        let (_, reply) = api.handle(
+            "PUT",
+            &format!("/sessions/{id}/memory/0x20"),
+            r#"{"value": 9}"#,
+        );
+        assert_eq!(reply["memory"][0x20], 9);
+        let (status, _) = api.handle(
+            "PUT",
+            &format!("/sessions/{id}/memory/256"),
+            r#"{"value": 9}"#,
+        );
+        assert_eq!(status, 404);
+        let (status, _) = api.handle(
+            "PUT",
+            &format!("/sessions/{id}/registers/1"),
+            r#"{"value": 300}"#,
+        );
+        assert_eq!(status, 400);
+        let (_, reply) = api.handle("GET", &format!("/sessions/{id}"), "");
+        assert_eq!(reply["registers"][3], 7);
+
+        let (status, _) = api.handle("DELETE", &format!("/sessions/{id}"), "");
+        assert_eq!(status, 200);
+        let (status, _) = api.handle("GET", &format!("/sessions/{id}"), "");
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn test_busy_session_blocks_only_itself() {
+        let api = Api::default();
+        let busy = create(&api, "B0 02 00");
+        let other = create(&api, PROGRAM);
+        let session = Arc::clone(&lock(&api.sessions)[&busy].session);
+        let _running = lock(&session);
+        let (status, reply) = api.handle("POST", &format!("/sessions/{other}/step"), "");
+        assert_eq!(status, 200);
+        assert_eq!(reply["executed"], 1);
+        let (status, _) = api.handle("POST", "/sessions", r#"{"program": "C000"}"#);
+        assert_eq!(status, 201);
+    }
+
+    #[test]
+    fn test_limits() {
+        let api = Api::default();
+        let id = create(&api, "B0 02 00");
+        let (_, reply) = api.handle(
+            "POST",
+            &format!("/sessions/{id}/run"),
+            r#"{"max_cycles": 5000}"#,
+        );
+        assert_eq!(reply["stop"], "timeout");
+        let (_, reply) = api.handle("GET", &format!("/sessions/{id}/trace"), "");
+        assert_eq!(reply["trace"].as_array().unwrap().len(), TRACE_LEN);
+        let (status, _) = api.handle("POST", "/sessions", r#"{"program": "zz"}"#);
+        assert_eq!(status, 400);
+        let (status, _) = api.handle("POST", "/sessions", "{");
+        assert_eq!(status, 400);
+        for _ in 1..MAX_SESSIONS {
+            create(&api, PROGRAM);
+        }
+        let (status, _) = api.handle("POST", "/sessions", r#"{"program": "C000"}"#);
+        assert_eq!(status, 429);
+    }
+
+    #[test]
+    fn test_idle_sessions_expire() {
+        let api = Api {
+            idle_timeout: Duration::ZERO,
+            ..Api::default()
+        };
+        let busy = create(&api, PROGRAM);
+        let session = Arc::clone(&lock(&api.sessions)[&busy].session);
+        for _ in 1..MAX_SESSIONS {
+            create(&api, PROGRAM);
+        }
+        let id = create(&api, PROGRAM);
+        let (_, reply) = api.handle("GET", "/sessions", "");
+        assert_eq!(reply["sessions"], json!([busy, id]));
+        drop(session);
+    }
+
+    #[test]
+    fn test_request_limits() {
+        let long_path = "/".repeat(MAX_LINE_LEN as usize);
+        let request = format!("GET {long_path} HTTP/1.1\r\n\r\n");
+        let reply = read_request(&mut request.as_bytes()).unwrap_err();
+        assert_eq!(reply.0, 431);
+        let request = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-A: b\r\n".repeat(MAX_HEADERS + 1)
+        );
+        let reply = read_request(&mut request.as_bytes()).unwrap_err();
+        assert_eq!(reply, error(431, "too many headers"));
+        let request = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(MAX_HEADERS));
+        assert!(read_request(&mut request.as_bytes()).is_ok());
+
+        let connections = Arc::new(AtomicUsize::new(0));
+        let open = (0..MAX_CONNECTIONS)
+            .map(|_| Connection::open(&connections).unwrap())
+            .collect::<Vec<Connection>>();
+        assert!(Connection::open(&connections).is_none());
+        drop(open);
+        assert_eq!(connections.load(Ordering::Acquire), 0);
+        assert!(Connection::open(&connections).is_some());
+    }
+
+    #[test]
+    fn test_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let api = Arc::new(Api::default());
+        let server_api = Arc::clone(&api);
+        let server = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                serve_connection(&server_api, stream, None).unwrap();
+            }
+        });
+
+        let body = json!({ "program": PROGRAM }).to_string();
+        let mut client = TcpStream::connect(addr).unwrap();
+        write!(
+            client,
+            "POST /sessions HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(!response.contains("Access-Control-Allow-Origin"));
+        assert!(response.ends_with(r#""id":1,"state":{"cycles":0,"halted":false,"instruction_register":0,"program_counter":0,"registers":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0]}}"#));
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        write!(
+            client,
+            "POST /sessions HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_LEN + 1
+        )
+        .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        server.join().unwrap();
+
+        let mut response = Vec::new();
+        write_reply(
+            &mut response,
+            (204, Value::Null),
+            Some("http://localhost:3000"),
+        )
+        .unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.contains("\r\nAccess-Control-Allow-Origin: http://localhost:3000\r\n"));
+    }
+}
@@ -1,8 +1,21 @@
-use std::num::ParseIntError;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::model::{Focus, Model};
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
-use vole_rs::vole::Cpu;
+use crate::browser::FileBrowser;
+use crate::cli::parse_value;
+use crate::model::{Animation, Edit, Focus, Model, Panel, Prompt, program_textarea};
+use crate::worker::{Machine, Outcome, Worker};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use vole_rs::{
+    banks::BankSwitch,
+    cache::{Cache, CacheConfig, Replacement, WritePolicy},
+    mmu::Mmu,
+    multicore::{Multicore, Scheduler},
+    pipeline::Pipeline,
+    vole::Cpu,
+};
 
 pub(crate) enum Msg {
     /// Exit the application
@@ -11,8 +24,12 @@ pub(crate) enum Msg {
     Load,
     /// Do a CPU cycle of fetch, decode, execute
     Cycle,
-    /// Run program to completion
+    /// Run program to completion on a worker thread
     Run,
+    /// Run program on a worker thread till it reaches the word at the cursor of the editor
+    RunToCursor,
+    /// Cancel the run on the worker thread
+    CancelRun,
     /// Focus the next controll
     FocusNext,
     /// Focus the previous controll
@@ -21,13 +38,74 @@ pub(crate) enum Msg {
     ScrollUp,
     /// Scroll down
     ScrollDown,
+    /// Move the cursor of the memory grid to the previous cell
+    CursorLeft,
+    /// Move the cursor of the memory grid to the next cell
+    CursorRight,
+    /// Show the memory as a list or as a grid
+    ToggleMemoryGrid,
+    /// Switch scrolling the memory to the program counter on or off
+    ToggleFollowPc,
     /// Toggle the help screen
     ToggleHelp,
+    /// Attach or detach the MMU
+    ToggleMmu,
+    /// Switch to the next cache configuration or detach the cache
+    CycleCache,
+    /// Show the next panel
+    NextPanel,
+    /// Switch between single-cycle and pipelined execution
+    TogglePipeline,
+    /// Switch forwarding in the pipeline on or off
+    ToggleForwarding,
+    /// Switch to the next number of cores
+    CycleCores,
+    /// Switch to the next scheduler for the cores
+    CycleScheduler,
+    /// Select the core to show and to step manually
+    SelectCore { core: usize },
+    /// Attach or detach the bank-switching device
+    ToggleBanks,
+    /// Show the previous memory bank
+    PreviousBank,
+    /// Show the next memory bank
+    NextBank,
+    /// Start or stop an animated run
+    ToggleAnimation,
+    /// Run more cycles per second in the animated run
+    SpeedUp,
+    /// Run fewer cycles per second in the animated run
+    SlowDown,
+    /// Pause or resume the animated run
+    TogglePause,
+    /// The time between two steps of the animated run or two progress updates has passed
+    Tick,
+    /// Save the program to the file it came from, ask for a path if there is none
+    Save,
+    /// Ask for the path to save the program to
+    SaveAs,
+    /// Input for the shown prompt
+    PromptKey { code: KeyCode },
+    /// Show the file browser to open a program
+    OpenBrowser,
+    /// Input for the file browser
+    BrowserKey { code: KeyCode },
+    /// Ask for a new value of the selected register or memory cell
+    EditValue,
+    /// Undo the last edit of a register or memory cell
+    UndoEdit,
+    /// Show the values of the focused pane in the next number format
+    NextNumberFormat,
     /// Input for the program editor
     KeyInput { key: Event },
 }
 
 pub(crate) fn handle_event(model: &mut Model) -> color_eyre::Result<Option<Msg>> {
+    if let Some(interval) = model.tick_interval()
+        && !event::poll(interval)?
+    {
+        return Result::Ok(Some(Msg::Tick));
+    }
     match event::read()? {
         // it's important to check KeyEventKind::Press to avoid handling key release events
         Event::Key(key) if key.kind == KeyEventKind::Press => Result::Ok(on_key_event(model, key)),
@@ -36,12 +114,27 @@ pub(crate) fn handle_event(model: &mut Model) -> color_eyre::Result<Option<Msg>>
 }
 
 fn on_key_event(model: &mut Model, key: KeyEvent) -> Option<Msg> {
+    if model.prompt.is_some() {
+        return Some(Msg::PromptKey { code: key.code });
+    }
+    if model.browser.is_some() {
+        return Some(Msg::BrowserKey { code: key.code });
+    }
     match key.code {
+        KeyCode::Char('o') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            Some(Msg::OpenBrowser)
+        }
+        KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => Some(Msg::Save),
+        KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::ALT) => Some(Msg::SaveAs),
+        KeyCode::Char('t') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            Some(Msg::RunToCursor)
+        }
         KeyCode::Esc | KeyCode::Char('q') => Some(Msg::Exit),
         KeyCode::Char('?') => Some(Msg::ToggleHelp),
         KeyCode::Char('r') => Some(Msg::Load),
         KeyCode::Char('p') => Some(Msg::Cycle),
         KeyCode::Char('P') => Some(Msg::Run),
+        KeyCode::Char('x') if model.worker.is_some() => Some(Msg::CancelRun),
         KeyCode::Tab => Some(Msg::FocusNext),
         KeyCode::BackTab => Some(Msg::FocusPrevious),
         _ if model.focus == Focus::Program => Some(Msg::KeyInput {
@@ -49,114 +142,572 @@ fn on_key_event(model: &mut Model, key: KeyEvent) -> Option<Msg> {
         }),
         KeyCode::Up => Some(Msg::ScrollUp),
         KeyCode::Down => Some(Msg::ScrollDown),
+        KeyCode::Char('g') => Some(Msg::ToggleAnimation),
+        KeyCode::Char('+') if model.animation.is_some() => Some(Msg::SpeedUp),
+        KeyCode::Char('-') if model.animation.is_some() => Some(Msg::SlowDown),
+        KeyCode::Char(' ') if model.animation.is_some() => Some(Msg::TogglePause),
+        KeyCode::Left => Some(Msg::CursorLeft),
+        KeyCode::Right => Some(Msg::CursorRight),
+        KeyCode::Enter => Some(Msg::EditValue),
+        KeyCode::Char('u') => Some(Msg::UndoEdit),
+        KeyCode::Char('d') => Some(Msg::NextNumberFormat),
+        KeyCode::Char('h') => Some(Msg::ToggleMemoryGrid),
+        KeyCode::Char('a') => Some(Msg::ToggleFollowPc),
+        KeyCode::Char('m') => Some(Msg::ToggleMmu),
+        KeyCode::Char('c') => Some(Msg::CycleCache),
+        KeyCode::Char('v') => Some(Msg::NextPanel),
+        KeyCode::Char('i') => Some(Msg::TogglePipeline),
+        KeyCode::Char('f') => Some(Msg::ToggleForwarding),
+        KeyCode::Char('n') => Some(Msg::CycleCores),
+        KeyCode::Char('s') => Some(Msg::CycleScheduler),
+        KeyCode::Char('b') => Some(Msg::ToggleBanks),
+        KeyCode::Char('[') => Some(Msg::PreviousBank),
+        KeyCode::Char(']') => Some(Msg::NextBank),
+        KeyCode::Char(c) if c.is_ascii_digit() => Some(Msg::SelectCore {
+            core: c as usize - '0' as usize,
+        }),
         _ => None,
     }
 }
 
-pub(crate) fn parse_program_text(lines: &[String]) -> Result<Vec<u8>, &'static str> {
-    fn parse_single_word(s: &str) -> Result<Vec<u8>, ParseIntError> {
-        let s = s.trim().trim_start_matches("0x").trim_start_matches("0X");
-        if s.len() >= 3 {
-            match u16::from_str_radix(s, 16) {
-                Ok(u16) => {
-                    let f = (u16 >> 8) as u8;
-                    let s = u16 as u8;
-                    Ok(vec![f, s])
-                }
-                Err(e) => Err(e),
+/// Parse a word of the program text into one byte with up to two digits, or two bytes with four digits.
+/// Returns why the word is not valid otherwise.
+pub(crate) fn parse_single_word(s: &str) -> Result<Vec<u8>, &'static str> {
+    let s = s.trim().trim_start_matches("0x").trim_start_matches("0X");
+    if s.is_empty() {
+        Err("has no digits")
+    } else if !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        Err("is not a number in hex notation")
+    } else if s.len() == 3 {
+        Err("is ambiguous, write two digits for a byte or four for an instruction")
+    } else if s.len() > 4 {
+        Err("has more than four digits")
+    } else if s.len() == 4 {
+        let word = u16::from_str_radix(s, 16).expect("expected four hex digits");
+        Ok(word.to_be_bytes().to_vec())
+    } else {
+        Ok(vec![
+            u8::from_str_radix(s, 16).expect("expected two hex digits"),
+        ])
+    }
+}
+
+/// A word of a program text which is not a valid value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Diagnostic {
+    pub(crate) line: usize,
+    /// The columns of the word, in characters.
+    pub(crate) columns: Range<usize>,
+    pub(crate) token: String,
+    pub(crate) reason: &'static str,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: `{}` {}",
+            self.line + 1,
+            self.columns.start + 1,
+            self.token,
+            self.reason
+        )
+    }
+}
+
+/// Describe the diagnostics of a program text, one per line.
+pub(crate) fn describe_diagnostics(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(Diagnostic::to_string)
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Split a line of a program text into its words with their columns in characters, skipping the comment.
+fn line_words(line: &str) -> Vec<(Range<usize>, &str)> {
+    let code = line.find("//").map_or(line, |i| &line[..i]);
+    let mut words = vec![];
+    let mut word = None;
+    let chars = code.char_indices().chain([(code.len(), ' ')]);
+    for (column, (idx, c)) in chars.enumerate() {
+        match (word, c.is_whitespace()) {
+            (None, false) => word = Some((column, idx)),
+            (Some((start_column, start_idx)), true) => {
+                words.push((start_column..column, &code[start_idx..idx]));
+                word = None;
             }
-        } else {
-            match u8::from_str_radix(s, 16) {
-                Ok(u8) => Ok(vec![u8]),
-                Err(e) => Err(e),
+            _ => (),
+        }
+    }
+    words
+}
+
+/// Parse the words of the program text which are valid and diagnose the others.
+pub(crate) fn map_program(lines: &[String]) -> (Vec<u8>, SourceMap, Vec<Diagnostic>) {
+    let mut program = vec![];
+    let mut map = SourceMap::default();
+    let mut diagnostics = vec![];
+    for (line, text) in lines.iter().enumerate() {
+        let start = program.len();
+        for (columns, word) in line_words(text) {
+            match parse_single_word(word) {
+                Ok(bytes) => {
+                    map.words.push(MappedWord {
+                        line,
+                        columns,
+                        addresses: program.len()..program.len() + bytes.len(),
+                    });
+                    program.extend(bytes);
+                }
+                Err(reason) => diagnostics.push(Diagnostic {
+                    line,
+                    columns,
+                    token: word.to_string(),
+                    reason,
+                }),
             }
         }
+        map.lines.push(start..program.len());
+    }
+    (program, map, diagnostics)
+}
+
+/// Parse the program text and map its lines and columns to the addresses of the bytes.
+/// Returns the diagnostics of all invalid words if there are any.
+pub(crate) fn parse_program_with_map(
+    lines: &[String],
+) -> Result<(Vec<u8>, SourceMap), Vec<Diagnostic>> {
+    match map_program(lines) {
+        (program, map, diagnostics) if diagnostics.is_empty() => Ok((program, map)),
+        (_, _, diagnostics) => Err(diagnostics),
+    }
+}
+
+pub(crate) fn parse_program_text(lines: &[String]) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    parse_program_with_map(lines).map(|(program, _)| program)
+}
+
+/// Diagnose the invalid words of the program text.
+pub(crate) fn diagnose_program(lines: &[String]) -> Vec<Diagnostic> {
+    map_program(lines).2
+}
+
+/// A word of a program text holding values and the addresses of its bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MappedWord {
+    pub(crate) line: usize,
+    /// The columns of the word, in characters.
+    pub(crate) columns: Range<usize>,
+    pub(crate) addresses: Range<usize>,
+}
+
+/// Maps the lines and columns of a program text to the addresses of their bytes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SourceMap {
+    /// The addresses of the bytes of every line, empty for lines without values.
+    pub(crate) lines: Vec<Range<usize>>,
+    /// The words holding values, ordered by address.
+    pub(crate) words: Vec<MappedWord>,
+}
+
+impl SourceMap {
+    /// Create the map of a program text, words that don't parse take no addresses.
+    pub(crate) fn new(lines: &[String]) -> Self {
+        map_program(lines).1
     }
 
-    let parse_lines = lines
+    /// Get the address of the first byte of the line, None if the line has no values.
+    pub(crate) fn address_of_line(&self, line: usize) -> Option<usize> {
+        self.lines
+            .get(line)
+            .filter(|range| !range.is_empty())
+            .map(|range| range.start)
+    }
+
+    /// Get the address of the word at the column of the line,
+    /// the first word of the line if the column lies before it.
+    pub(crate) fn address_at(&self, line: usize, column: usize) -> Option<usize> {
+        let mut words = self
+            .words
+            .iter()
+            .filter(|word| word.line == line)
+            .peekable();
+        let first = *words.peek()?;
+        let word = words
+            .take_while(|word| word.columns.start <= column)
+            .last()
+            .unwrap_or(first);
+        Some(word.addresses.start)
+    }
+
+    /// Get the line holding the byte at the address.
+    pub(crate) fn line_of_address(&self, addr: usize) -> Option<usize> {
+        self.word_of_address(addr).map(|word| word.line)
+    }
+
+    /// Get the word holding the byte at the address.
+    pub(crate) fn word_of_address(&self, addr: usize) -> Option<&MappedWord> {
+        self.words
+            .iter()
+            .find(|word| word.addresses.contains(&addr))
+    }
+}
+
+/// The physical address of the page table used by the MMU.
+pub(crate) const PAGE_TABLE_BASE: u8 = 0xF0;
+
+/// The number of entries in the TLB of the MMU.
+pub(crate) const TLB_SIZE: usize = 4;
+
+/// Attach an MMU to the CPU.
+/// If the page table region is still empty, an identity mapping is written into it.
+pub(crate) fn attach_mmu(cpu: &mut Cpu) {
+    let mmu = Mmu::new(PAGE_TABLE_BASE, TLB_SIZE);
+    let table = PAGE_TABLE_BASE as usize;
+    if cpu.memory[table..table + vole_rs::mmu::PAGE_COUNT]
         .iter()
-        .filter(|line| !line.trim().starts_with("//"))
-        .map(|line| {
-            let line = line.splitn(2, "//").collect::<Vec<&str>>()[0];
-            line.split_whitespace()
-                .map(parse_single_word)
-                .collect::<Vec<Result<Vec<u8>, ParseIntError>>>()
-        })
-        .collect::<Vec<Vec<Result<Vec<u8>, ParseIntError>>>>()
-        .concat();
-
-    if parse_lines.iter().any(|r| r.is_err()) {
-        Result::Err("only byte values in hex notation or line comments allowed")
+        .all(|b| *b == 0)
+    {
+        mmu.write_identity_table(&mut cpu.memory);
+    }
+    cpu.mmu = Some(mmu);
+}
+
+/// The address of the bank-select register.
+pub(crate) const BANK_SELECT_ADDR: u8 = 0x7F;
+
+/// The first address of the bank window.
+pub(crate) const BANK_WINDOW_START: u8 = 0x80;
+
+/// The number of bytes of the bank window.
+pub(crate) const BANK_WINDOW_LEN: usize = 0x80;
+
+/// The number of banks if the program doesn't need more.
+pub(crate) const MIN_BANKS: usize = 4;
+
+/// The largest program that fits into memory and the banks.
+pub(crate) const MAX_PROGRAM_LEN: usize = 256 + 255 * BANK_WINDOW_LEN;
+
+/// Check that the program fits into memory and the banks
+/// and leaves the bank-select register free if it is loaded with bank switching.
+pub(crate) fn check_program(program: &[u8], with_banks: bool) -> Result<(), &'static str> {
+    if program.len() > MAX_PROGRAM_LEN {
+        Err("program does not fit into memory and banks")
+    } else if (with_banks || program.len() > 256)
+        && program
+            .get(BANK_SELECT_ADDR as usize)
+            .is_some_and(|b| *b != 0)
+    {
+        Err("the byte at 0x7F is reserved for the bank-select register")
     } else {
-        let input = parse_lines
-            .into_iter()
-            .map(|r| r.expect("expected a parsed u8"))
-            .collect::<Vec<Vec<u8>>>()
-            .concat();
+        Ok(())
+    }
+}
 
-        Result::Ok(input)
+fn bank_switch(bank_count: usize) -> BankSwitch {
+    BankSwitch::new(
+        BANK_SELECT_ADDR,
+        BANK_WINDOW_START,
+        BANK_WINDOW_LEN,
+        bank_count,
+    )
+}
+
+/// Create a CPU with the program loaded.
+/// Bank switching is used if asked for or if the program doesn't fit into memory,
+/// the program has to pass [check_program].
+pub(crate) fn init_cpu(program: &[u8], with_banks: bool) -> Cpu {
+    if with_banks || program.len() > 256 {
+        let needed = program.len().saturating_sub(256).div_ceil(BANK_WINDOW_LEN) + 1;
+        Cpu::init_banked(program, bank_switch(needed.max(MIN_BANKS)))
+    } else {
+        Cpu::init(program)
+    }
+}
+
+/// The cache configurations to switch through.
+pub(crate) const CACHE_CONFIGS: [CacheConfig; 3] = [
+    CacheConfig::direct_mapped(8, 4, WritePolicy::WriteThrough),
+    CacheConfig::set_associative(4, 2, 4, WritePolicy::WriteBack, Replacement::Lru),
+    CacheConfig::set_associative(4, 2, 4, WritePolicy::WriteBack, Replacement::Fifo),
+];
+
+/// The numbers of cores to switch through, a single core is the plain CPU.
+pub(crate) const CORE_COUNTS: [usize; 3] = [2, 3, 4];
+
+/// The schedulers to switch through.
+pub(crate) const SCHEDULERS: [Scheduler; 3] = [
+    Scheduler::RoundRobin,
+    Scheduler::Random { seed: 0x5EED },
+    Scheduler::Manual,
+];
+
+/// The speeds of an animated run, the time between two ticks and the cycles done per tick.
+pub(crate) const ANIMATION_SPEEDS: [(Duration, u32); 8] = [
+    (Duration::from_millis(1000), 1),
+    (Duration::from_millis(500), 1),
+    (Duration::from_millis(200), 1),
+    (Duration::from_millis(100), 1),
+    (Duration::from_millis(50), 1),
+    (Duration::from_millis(50), 10),
+    (Duration::from_millis(50), 100),
+    (Duration::from_millis(50), 1000),
+];
+
+/// The time between two updates of the cycle counter while running on a worker thread.
+pub(crate) const PROGRESS_REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The speed an animated run starts with.
+pub(crate) const DEFAULT_ANIMATION_SPEED: usize = 2;
+
+/// The maximum number of steps when running several cores to completion.
+pub(crate) const MULTICORE_STEP_LIMIT: u32 = 100_000;
+
+/// Find the index of the first value that differs.
+fn first_difference(old: &[u8], new: &[u8]) -> Option<usize> {
+    old.iter()
+        .zip(new)
+        .enumerate()
+        .find_map(|(idx, (old, new))| if old != new { Some(idx) } else { None })
+}
+
+/// Describe why the CPU stopped after a failed cycle.
+fn failure_msg(cpu: &Cpu) -> &'static str {
+    if cpu.page_faulted() {
+        "page fault"
+    } else {
+        "illegal instruction"
+    }
+}
+
+/// Scroll the pane as little as possible to show the selected row.
+pub(crate) fn follow(selected: usize, scroll: usize, rows: usize) -> usize {
+    if selected < scroll {
+        selected
+    } else if rows > 0 && selected >= scroll + rows {
+        selected + 1 - rows
+    } else {
+        scroll
+    }
+}
+
+/// Scroll the memory list to show the next instruction if the program counter is followed.
+fn follow_program_counter(model: &mut Model) {
+    if model.follow_pc {
+        let cpu = model.shown_cpu();
+        let (pc, next) = (
+            cpu.program_counter,
+            (cpu.program_counter + 1) % cpu.memory.len(),
+        );
+        let rows = model.pane_rows.get();
+        model.memory_scroll = follow(pc, follow(next, model.memory_scroll, rows), rows);
+    }
+}
+
+/// True if the message leaves the CPU alone, so it can be handled while a worker runs it.
+fn allowed_while_running(msg: &Msg) -> bool {
+    matches!(
+        msg,
+        Msg::Exit
+            | Msg::CancelRun
+            | Msg::Tick
+            | Msg::ToggleHelp
+            | Msg::FocusNext
+            | Msg::FocusPrevious
+            | Msg::ScrollUp
+            | Msg::ScrollDown
+            | Msg::CursorLeft
+            | Msg::CursorRight
+            | Msg::ToggleMemoryGrid
+            | Msg::ToggleFollowPc
+            | Msg::NextNumberFormat
+            | Msg::Save
+            | Msg::SaveAs
+            | Msg::PromptKey { .. }
+            | Msg::KeyInput { .. }
+    )
+}
+
+/// Write the program text to the file and remember it as the file of the program.
+fn save(model: &mut Model, path: PathBuf) {
+    let mut text = model.program_textarea.lines().join("\n");
+    text.push('\n');
+    match std::fs::write(&path, text) {
+        Ok(()) => {
+            model.path = Some(path);
+            model.dirty = false;
+            model.error_msg = None;
+        }
+        Err(e) => model.error_msg = Some(format!("could not save {}: {}", path.display(), e)),
+    }
+}
+
+/// Put the machine of a finished run back into the model.
+/// Without a machine the model keeps the one from before the run.
+fn finish_run(model: &mut Model, (machine, outcome): (Option<Machine>, Outcome)) {
+    match machine {
+        Some(Machine::Single { cpu, pipeline }) => {
+            model.cpu = *cpu;
+            model.pipeline = pipeline;
+        }
+        Some(Machine::Cores(multicore)) => model.multicore = Some(*multicore),
+        None => (),
+    }
+    match outcome {
+        Outcome::Halted(true) | Outcome::Reached => (),
+        Outcome::Halted(false) if model.multicore.is_some() => {
+            model.error_msg = Some("a core stopped on an illegal instruction or page fault".into())
+        }
+        Outcome::Halted(false) => model.error_msg = Some(failure_msg(&model.cpu).into()),
+        Outcome::StepLimit => model.error_msg = Some("step limit reached".into()),
+        Outcome::Cancelled => model.error_msg = Some("run cancelled".into()),
+        Outcome::Panicked => {
+            model.error_msg = Some("the simulator crashed, the run was dropped".into())
+        }
+    }
+    follow_program_counter(model);
+}
+
+/// Replace the program text with the file and load it.
+fn open(model: &mut Model, path: PathBuf) {
+    match std::fs::read_to_string(&path) {
+        Ok(text) => {
+            model.program_textarea = program_textarea(text.lines().map(String::from).collect());
+            model.path = Some(path);
+            model.dirty = false;
+            model.browser = None;
+            update(model, Msg::Load);
+        }
+        Err(e) => model.error_msg = Some(format!("could not open {}: {}", path.display(), e)),
     }
 }
 
 /// Update the Model based on it's current state and the Msg
 pub(crate) fn update(model: &mut Model, msg: Msg) {
+    if model.worker.is_some() && !allowed_while_running(&msg) {
+        return;
+    }
     match msg {
         Msg::Exit => {
             if model.show_help {
                 model.show_help = false;
+            } else if model.dirty {
+                model.prompt = Some(Prompt::ConfirmQuit);
             } else {
+                if let Some(worker) = &model.worker {
+                    worker.cancel();
+                }
                 model.running = false;
             }
         }
         Msg::Load => {
-            let input = parse_program_text(model.program_textarea.lines());
+            let input = parse_program_with_map(model.program_textarea.lines());
             match input {
-                Ok(input) => {
+                Ok((input, source_map)) => {
+                    if let Err(msg) = check_program(&input, model.cpu.banks.is_some()) {
+                        model.error_msg = Some(msg.into());
+                        return;
+                    }
+                    model.source_map = source_map;
+                    model.diagnostics.clear();
                     model.error_msg = None;
-                    model.cpu = Cpu::init(&input)
+                    model.animation = None;
+                    model.edits.clear();
+                    let with_mmu = model.cpu.mmu.is_some();
+                    let cache_config = model.cpu.cache.as_ref().map(|c| c.config);
+                    let with_banks = model.cpu.banks.is_some();
+                    model.cpu = init_cpu(&input, with_banks);
+                    model.browsed_bank = None;
+                    if with_mmu {
+                        attach_mmu(&mut model.cpu);
+                    }
+                    model.cpu.cache = cache_config.map(Cache::new);
+                    if let Some(pipeline) = &model.pipeline {
+                        model.pipeline = Some(Pipeline::new(&model.cpu, pipeline.forwarding));
+                    }
+                    if let Some(multicore) = &model.multicore {
+                        let (count, scheduler) = (multicore.cores.len(), multicore.scheduler);
+                        model.multicore = Some(Multicore::new(&model.cpu, count, scheduler));
+                    }
+                    follow_program_counter(model);
+                }
+                Err(diagnostics) => {
+                    model.error_msg = Some(match diagnostics.len() {
+                        1 => diagnostics[0].to_string(),
+                        n => format!("{} (and {} more)", diagnostics[0], n - 1),
+                    });
+                    model.diagnostics = diagnostics;
                 }
-                Err(msg) => model.error_msg = Some(msg),
             }
         }
-        Msg::Cycle => {
-            if !model.cpu.halted {
-                let old_registers = model.cpu.registers;
-                let old_memory = model.cpu.memory;
-
-                let r = model.cpu.cycle();
-                if !r {
-                    model.error_msg = Some("illegal instruction");
+        Msg::Cycle if let Some(multicore) = &mut model.multicore => {
+            let old_memory = *multicore.memory();
+            let old_cores = multicore.cores.clone();
+            match multicore.step() {
+                Some((core, r)) => {
+                    if !r {
+                        model.error_msg = Some(failure_msg(&multicore.cores[core]).into());
+                    }
+                    model.modified_register = first_difference(
+                        &old_cores[core].registers,
+                        &multicore.cores[core].registers,
+                    );
+                    model.modified_memory = first_difference(&old_memory, multicore.memory());
                 }
+                None if multicore.halted() => model.error_msg = Some("all cores halted".into()),
+                None => model.error_msg = Some("the selected core halted".into()),
+            }
+            follow_program_counter(model);
+        }
+        Msg::Cycle if !model.cpu.halted => {
+            let old_registers = model.cpu.registers;
+            let old_memory = model.cpu.memory;
 
-                let new_registers = model.cpu.registers;
-                let new_memory = model.cpu.memory;
-                model.modified_register = old_registers
-                    .iter()
-                    .zip(new_registers)
-                    .enumerate()
-                    .find_map(
-                        |(idx, (old, new))| {
-                            if *old != new { Some(idx) } else { None }
-                        },
-                    );
-                model.modified_memory =
-                    old_memory
-                        .iter()
-                        .zip(new_memory)
-                        .enumerate()
-                        .find_map(
-                            |(idx, (old, new))| {
-                                if *old != new { Some(idx) } else { None }
-                            },
-                        );
-            }
-        }
-        Msg::Run => {
-            if !model.cpu.halted {
-                let r = model.cpu.run();
-                if !r {
-                    model.error_msg = Some("illegal instruction");
+            let r = match &mut model.pipeline {
+                Some(pipeline) => pipeline.clock(&mut model.cpu),
+                None => model.cpu.cycle(),
+            };
+            if !r {
+                model.error_msg = Some(failure_msg(&model.cpu).into());
+            }
+
+            model.modified_register = first_difference(&old_registers, &model.cpu.registers);
+            model.modified_memory = first_difference(&old_memory, &model.cpu.memory);
+            follow_program_counter(model);
+        }
+        Msg::Run | Msg::RunToCursor if !model.halted() => {
+            let stop_at = match msg {
+                Msg::RunToCursor => {
+                    let (line, column) = model.program_textarea.cursor();
+                    match model.source_map.address_at(line, column) {
+                        Some(addr) if addr < model.cpu.memory.len() => Some(addr),
+                        Some(_) => {
+                            model.error_msg =
+                                Some("can only run to the first 256 bytes of the program".into());
+                            return;
+                        }
+                        None => {
+                            model.error_msg = Some("no loaded values on the line".into());
+                            return;
+                        }
+                    }
                 }
+                _ => None,
+            };
+            model.animation = None;
+            let machine = match &model.multicore {
+                Some(multicore) => Machine::Cores(Box::new(multicore.clone())),
+                None => Machine::Single {
+                    cpu: Box::new(model.cpu.clone()),
+                    pipeline: model.pipeline.clone(),
+                },
+            };
+            model.worker = Some(Worker::spawn(machine, stop_at));
+        }
+        Msg::CancelRun => {
+            if let Some(worker) = &model.worker {
+                worker.cancel();
             }
         }
         Msg::FocusNext => match model.focus {
@@ -169,34 +720,345 @@ pub(crate) fn update(model: &mut Model, msg: Msg) {
             Focus::Memory => model.focus = Focus::Registers,
             Focus::Program => model.focus = Focus::Memory,
         },
-        Msg::ScrollUp => match model.focus {
-            Focus::Registers => model.registers_scroll = model.registers_scroll.saturating_sub(1),
-            Focus::Memory => model.memory_scroll = model.memory_scroll.saturating_sub(1),
-            _ => (),
+        Msg::ScrollUp | Msg::ScrollDown => {
+            let (selected, scroll, len, step) = match model.focus {
+                Focus::Registers => (
+                    &mut model.selected_register,
+                    &mut model.registers_scroll,
+                    model.cpu.registers.len(),
+                    1,
+                ),
+                Focus::Memory => (
+                    &mut model.selected_memory,
+                    &mut model.memory_scroll,
+                    model.cpu.memory.len(),
+                    if model.memory_grid { 16 } else { 1 },
+                ),
+                Focus::Program => return,
+            };
+            *selected = match msg {
+                Msg::ScrollUp => selected.checked_sub(step).unwrap_or(*selected),
+                _ if *selected + step < len => *selected + step,
+                _ => *selected,
+            };
+            *scroll = follow(*selected, *scroll, model.pane_rows.get());
+        }
+        Msg::CursorLeft | Msg::CursorRight if model.focus == Focus::Memory && model.memory_grid => {
+            model.selected_memory = match msg {
+                Msg::CursorLeft => model.selected_memory.saturating_sub(1),
+                _ => (model.selected_memory + 1).min(model.cpu.memory.len() - 1),
+            };
+            model.memory_scroll = follow(
+                model.selected_memory,
+                model.memory_scroll,
+                model.pane_rows.get(),
+            );
+        }
+        Msg::ToggleMemoryGrid => model.memory_grid = !model.memory_grid,
+        Msg::ToggleFollowPc => {
+            model.follow_pc = !model.follow_pc;
+            follow_program_counter(model);
+        }
+        Msg::EditValue => {
+            if let Some(target) = model.edit_target() {
+                model.prompt = Some(Prompt::EditValue {
+                    target,
+                    text: String::new(),
+                });
+            }
+        }
+        Msg::NextNumberFormat => match model.focus {
+            Focus::Registers => model.register_format = model.register_format.next(),
+            Focus::Memory => model.memory_format = model.memory_format.next(),
+            Focus::Program => (),
+        },
+        Msg::UndoEdit => match model.edits.pop() {
+            Some(edit) => model.set_value(edit.target, edit.old),
+            None => model.error_msg = Some("nothing to undo".into()),
+        },
+        Msg::KeyInput { key } if model.focus == Focus::Program => {
+            let edited = model.program_textarea.input(key);
+            if edited {
+                model.dirty = true;
+                model.diagnostics = diagnose_program(model.program_textarea.lines());
+            }
+        }
+        Msg::Save => match model.path.clone() {
+            Some(path) => save(model, path),
+            None => update(model, Msg::SaveAs),
         },
-        Msg::ScrollDown => match model.focus {
-            Focus::Registers if model.registers_scroll < model.cpu.registers.len() => {
-                model.registers_scroll = model.registers_scroll.saturating_add(1)
+        Msg::SaveAs => {
+            let path = model
+                .path
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string());
+            model.prompt = Some(Prompt::SaveAs { path });
+        }
+        Msg::PromptKey { code } => match (model.prompt.take(), code) {
+            (Some(Prompt::ConfirmQuit), KeyCode::Char('y')) => {
+                model.dirty = false;
+                update(model, Msg::Exit);
+            }
+            (Some(Prompt::ConfirmOpen { path }), KeyCode::Char('y')) => open(model, path),
+            (Some(Prompt::SaveAs { path }), KeyCode::Enter) if !path.is_empty() => {
+                save(model, path.into())
+            }
+            (Some(Prompt::SaveAs { mut path }), KeyCode::Char(c)) => {
+                path.push(c);
+                model.prompt = Some(Prompt::SaveAs { path });
+            }
+            (Some(Prompt::SaveAs { mut path }), KeyCode::Backspace) => {
+                path.pop();
+                model.prompt = Some(Prompt::SaveAs { path });
+            }
+            (Some(prompt @ Prompt::SaveAs { .. }), code) if code != KeyCode::Esc => {
+                model.prompt = Some(prompt)
+            }
+            (Some(Prompt::EditValue { target, text }), KeyCode::Enter) => {
+                match parse_value(&text) {
+                    Ok(value) => {
+                        let old = model.value(target);
+                        model.set_value(target, value);
+                        model.edits.push(Edit { target, old });
+                        model.error_msg = None;
+                    }
+                    Err(e) => model.error_msg = Some(e),
+                }
+            }
+            (Some(Prompt::EditValue { target, mut text }), KeyCode::Char(c)) => {
+                text.push(c);
+                model.prompt = Some(Prompt::EditValue { target, text });
+            }
+            (Some(Prompt::EditValue { target, mut text }), KeyCode::Backspace) => {
+                text.pop();
+                model.prompt = Some(Prompt::EditValue { target, text });
             }
-            Focus::Memory if model.memory_scroll < model.cpu.memory.len() => {
-                model.memory_scroll = model.memory_scroll.saturating_add(1)
+            (Some(prompt @ Prompt::EditValue { .. }), code) if code != KeyCode::Esc => {
+                model.prompt = Some(prompt)
             }
             _ => (),
         },
-        Msg::KeyInput { key } if model.focus == Focus::Program => {
-            model.program_textarea.input(key);
+        Msg::OpenBrowser => {
+            let dir = model
+                .path
+                .as_deref()
+                .and_then(Path::parent)
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            match FileBrowser::new(dir) {
+                Ok(browser) => model.browser = Some(browser),
+                Err(e) => {
+                    model.error_msg = Some(format!("could not browse {}: {}", dir.display(), e))
+                }
+            }
+        }
+        Msg::BrowserKey { code: KeyCode::Esc } => model.browser = None,
+        Msg::BrowserKey { code } => {
+            if let Some(browser) = &mut model.browser {
+                let selected = match code {
+                    KeyCode::Up => {
+                        browser.select_previous();
+                        Ok(None)
+                    }
+                    KeyCode::Down => {
+                        browser.select_next();
+                        Ok(None)
+                    }
+                    KeyCode::Enter | KeyCode::Right => browser.enter(),
+                    KeyCode::Backspace | KeyCode::Left => browser.leave().map(|()| None),
+                    _ => Ok(None),
+                };
+                match selected {
+                    Ok(Some(path)) if model.dirty => {
+                        model.prompt = Some(Prompt::ConfirmOpen { path })
+                    }
+                    Ok(Some(path)) => open(model, path),
+                    Ok(None) => (),
+                    Err(e) => model.error_msg = Some(e.to_string()),
+                }
+            }
         }
         Msg::ToggleHelp => {
             model.show_help = !model.show_help;
         }
+        Msg::ToggleMmu => {
+            let attach = model.cpu.mmu.is_none();
+            let cores = model.multicore.iter_mut().flat_map(|m| m.cores.iter_mut());
+            for cpu in std::iter::once(&mut model.cpu).chain(cores) {
+                if attach {
+                    attach_mmu(cpu);
+                } else {
+                    cpu.mmu = None;
+                }
+            }
+            if attach {
+                model.panel = Some(Panel::Mmu);
+            }
+        }
+        Msg::CycleCache => {
+            let next = match &model.cpu.cache {
+                None => Some(0),
+                Some(cache) => CACHE_CONFIGS
+                    .iter()
+                    .position(|c| *c == cache.config)
+                    .map(|idx| idx + 1)
+                    .filter(|idx| *idx < CACHE_CONFIGS.len()),
+            };
+            model.cpu.cache = next.map(|idx| Cache::new(CACHE_CONFIGS[idx]));
+            if model.cpu.cache.is_some() {
+                model.panel = Some(Panel::Cache);
+            }
+        }
+        Msg::NextPanel => {
+            let panels = model.available_panels();
+            if !panels.is_empty() {
+                let next = match model.shown_panel() {
+                    Some(shown) => panels
+                        .iter()
+                        .position(|p| *p == shown)
+                        .map_or(0, |idx| (idx + 1) % panels.len()),
+                    None => 0,
+                };
+                model.panel = Some(panels[next]);
+            }
+        }
+        Msg::TogglePipeline => {
+            if model.pipeline.is_some() {
+                model.pipeline = None;
+            } else {
+                model.pipeline = Some(Pipeline::new(&model.cpu, true));
+                model.panel = Some(Panel::Pipeline);
+            }
+        }
+        Msg::ToggleForwarding => {
+            if let Some(pipeline) = &mut model.pipeline {
+                pipeline.forwarding = !pipeline.forwarding;
+            }
+        }
+        Msg::CycleCores => {
+            let (next, scheduler) = match &model.multicore {
+                None => (Some(0), SCHEDULERS[0]),
+                Some(multicore) => (
+                    CORE_COUNTS
+                        .iter()
+                        .position(|c| *c == multicore.cores.len())
+                        .map(|idx| idx + 1)
+                        .filter(|idx| *idx < CORE_COUNTS.len()),
+                    multicore.scheduler,
+                ),
+            };
+            model.multicore =
+                next.map(|idx| Multicore::new(&model.cpu, CORE_COUNTS[idx], scheduler));
+            model.edits.clear();
+        }
+        Msg::CycleScheduler => {
+            if let Some(multicore) = &mut model.multicore {
+                let idx = SCHEDULERS
+                    .iter()
+                    .position(|s| *s == multicore.scheduler)
+                    .map_or(0, |idx| (idx + 1) % SCHEDULERS.len());
+                multicore.set_scheduler(SCHEDULERS[idx]);
+            }
+        }
+        Msg::ToggleBanks => {
+            if model.cpu.banks.is_some() {
+                model.cpu.select_bank(0);
+                model.cpu.banks = None;
+            } else if model.cpu.memory[BANK_SELECT_ADDR as usize] != 0 {
+                model.error_msg =
+                    Some("the byte at 0x7F is reserved for the bank-select register".into());
+                return;
+            } else {
+                model.cpu.banks = Some(bank_switch(MIN_BANKS));
+            }
+            model.browsed_bank = None;
+            model.edits.clear();
+        }
+        Msg::PreviousBank | Msg::NextBank => {
+            if let (Some(shown), Some(banks)) = (model.shown_bank(), &model.shown_cpu().banks) {
+                let count = banks.banks.len();
+                let next = match msg {
+                    Msg::PreviousBank => (shown + count - 1) % count,
+                    _ => (shown + 1) % count,
+                };
+                model.browsed_bank = Some(next);
+            }
+        }
+        Msg::ToggleAnimation => {
+            model.animation = match model.animation {
+                None if !model.halted() => Some(Animation {
+                    speed: DEFAULT_ANIMATION_SPEED,
+                    paused: false,
+                }),
+                _ => None,
+            };
+        }
+        Msg::SpeedUp | Msg::SlowDown => {
+            if let Some(animation) = &mut model.animation {
+                animation.speed = match msg {
+                    Msg::SpeedUp => (animation.speed + 1).min(ANIMATION_SPEEDS.len() - 1),
+                    _ => animation.speed.saturating_sub(1),
+                };
+            }
+        }
+        Msg::TogglePause => {
+            if let Some(animation) = &mut model.animation {
+                animation.paused = !animation.paused;
+            }
+        }
+        Msg::Tick => {
+            if model.worker.as_ref().is_some_and(Worker::is_finished)
+                && let Some(worker) = model.worker.take()
+            {
+                finish_run(model, worker.join());
+            }
+            if let Some(animation) = model.animation {
+                let (_, cycles) = ANIMATION_SPEEDS[animation.speed];
+                for _ in 0..cycles {
+                    if model.halted() {
+                        break;
+                    }
+                    update(model, Msg::Cycle);
+                }
+                if model.halted() {
+                    model.animation = None;
+                }
+            }
+        }
+        Msg::SelectCore { core } => {
+            if let Some(multicore) = &mut model.multicore
+                && core < multicore.cores.len()
+            {
+                multicore.selected = core;
+            }
+        }
         _ => (),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Msg, update};
-    use crate::{model::Model, update::parse_program_text};
+    use super::{
+        ANIMATION_SPEEDS, BANK_SELECT_ADDR, BANK_WINDOW_LEN, CACHE_CONFIGS,
+        DEFAULT_ANIMATION_SPEED, Msg, SourceMap, finish_run, on_key_event, update,
+    };
+    use crate::{
+        model::{Focus, Model, NumberFormat, Panel, Prompt, program_textarea},
+        update::{diagnose_program, parse_program_text, parse_program_with_map, parse_single_word},
+        worker::Outcome,
+    };
+    use crossterm::event::{Event, KeyCode, KeyEvent};
+    use std::time::Duration;
+    use vole_rs::multicore::Scheduler;
+
+    /// Run the program on the worker and wait for it like the event loop does.
+    fn run_to_completion(model: &mut Model) {
+        update(model, Msg::Run);
+        while model.worker.is_some() {
+            std::thread::sleep(Duration::from_millis(1));
+            update(model, Msg::Tick);
+        }
+    }
 
     #[test]
     fn test_exit_msg() {
@@ -205,6 +1067,548 @@ mod tests {
         assert!(!model.running)
     }
 
+    #[test]
+    fn test_toggle_mmu_msg() {
+        let mut model = Model::default();
+        update(&mut model, Msg::ToggleMmu);
+        assert!(model.cpu.mmu.is_some());
+        assert_eq!(model.cpu.memory[0xF1], 0x81);
+        run_to_completion(&mut model);
+        assert_eq!(model.cpu.memory[0x17], 0x34);
+        update(&mut model, Msg::ToggleMmu);
+        assert!(model.cpu.mmu.is_none());
+    }
+
+    #[test]
+    fn test_toggle_mmu_msg_with_cores() {
+        let mut model = Model::default();
+        update(&mut model, Msg::CycleCores);
+        update(&mut model, Msg::ToggleMmu);
+        let multicore = model.multicore.as_ref().unwrap();
+        assert!(multicore.cores.iter().all(|core| core.mmu.is_some()));
+        assert_eq!(multicore.memory()[0xF1], 0x81);
+        assert_eq!(model.shown_panel(), Some(Panel::Mmu));
+        update(&mut model, Msg::ToggleMmu);
+        let multicore = model.multicore.as_ref().unwrap();
+        assert!(multicore.cores.iter().all(|core| core.mmu.is_none()));
+        assert!(model.cpu.mmu.is_none());
+    }
+
+    #[test]
+    fn test_cycle_cache_msg() {
+        let mut model = Model::default();
+        for config in CACHE_CONFIGS {
+            update(&mut model, Msg::CycleCache);
+            assert_eq!(model.cpu.cache.as_ref().map(|c| c.config), Some(config));
+        }
+        run_to_completion(&mut model);
+        assert_eq!(model.cpu.cache.as_ref().unwrap().store_stats.misses, 1);
+        update(&mut model, Msg::CycleCache);
+        assert!(model.cpu.cache.is_none());
+    }
+
+    #[test]
+    fn test_next_panel_msg() {
+        let mut model = Model::default();
+        update(&mut model, Msg::ToggleMmu);
+        update(&mut model, Msg::CycleCache);
+        assert_eq!(model.shown_panel(), Some(Panel::Cache));
+        update(&mut model, Msg::NextPanel);
+        assert_eq!(model.shown_panel(), Some(Panel::Mmu));
+        update(&mut model, Msg::ToggleMmu);
+        assert_eq!(model.shown_panel(), Some(Panel::Cache));
+    }
+
+    #[test]
+    fn test_toggle_pipeline_msg() {
+        let mut model = Model::default();
+        update(&mut model, Msg::TogglePipeline);
+        assert_eq!(model.shown_panel(), Some(Panel::Pipeline));
+        update(&mut model, Msg::Cycle);
+        assert_eq!(model.pipeline.as_ref().unwrap().clock, 1);
+        assert_eq!(model.cpu.cycle, 0);
+        run_to_completion(&mut model);
+        assert_eq!(model.cpu.memory[0x17], 0x34);
+        assert_eq!(model.cpu.cycle, 3);
+        let pipeline = model.pipeline.as_ref().unwrap();
+        assert_eq!(pipeline.retired, 3);
+        assert!(pipeline.forwarding);
+        update(&mut model, Msg::ToggleForwarding);
+        assert!(!model.pipeline.as_ref().unwrap().forwarding);
+        update(&mut model, Msg::Load);
+        assert_eq!(model.pipeline.as_ref().unwrap().clock, 0);
+        update(&mut model, Msg::TogglePipeline);
+        assert!(model.pipeline.is_none());
+    }
+
+    #[test]
+    fn test_multicore_msgs() {
+        let mut model = Model::default();
+        update(&mut model, Msg::CycleCores);
+        assert_eq!(model.multicore.as_ref().unwrap().cores.len(), 2);
+        update(&mut model, Msg::Cycle);
+        update(&mut model, Msg::Cycle);
+        let multicore = model.multicore.as_ref().unwrap();
+        assert_eq!(multicore.cores[0].registers[0x04], 0x34);
+        assert_eq!(multicore.cores[1].registers[0x04], 0x34);
+        assert_eq!(model.modified_register, Some(0x04));
+        run_to_completion(&mut model);
+        let multicore = model.multicore.as_ref().unwrap();
+        assert!(multicore.halted());
+        assert_eq!(multicore.memory()[0x17], 0x34);
+        assert!(multicore.last_writer[0x17].is_some());
+
+        update(&mut model, Msg::CycleScheduler);
+        update(&mut model, Msg::CycleScheduler);
+        update(&mut model, Msg::Load);
+        update(&mut model, Msg::SelectCore { core: 1 });
+        update(&mut model, Msg::Cycle);
+        let multicore = model.multicore.as_ref().unwrap();
+        assert_eq!(multicore.scheduler, Scheduler::Manual);
+        assert_eq!(multicore.cores[0].program_counter, 0);
+        assert_eq!(multicore.cores[1].program_counter, 2);
+
+        update(&mut model, Msg::CycleCores);
+        update(&mut model, Msg::CycleCores);
+        assert_eq!(model.multicore.as_ref().unwrap().cores.len(), 4);
+        update(&mut model, Msg::CycleCores);
+        assert!(model.multicore.is_none());
+    }
+
+    #[test]
+    fn test_banks_msgs() {
+        let mut model = Model::default();
+        update(&mut model, Msg::ToggleBanks);
+        assert_eq!(model.cpu.current_bank(), Some(0));
+        update(&mut model, Msg::PreviousBank);
+        assert_eq!(model.shown_bank(), Some(3));
+        update(&mut model, Msg::NextBank);
+        update(&mut model, Msg::NextBank);
+        assert_eq!(model.shown_bank(), Some(1));
+        update(&mut model, Msg::Load);
+        assert_eq!(model.shown_bank(), Some(0));
+        update(&mut model, Msg::ToggleBanks);
+        assert!(model.cpu.banks.is_none());
+        assert_eq!(model.shown_bank(), None);
+
+        model.cpu.memory[BANK_SELECT_ADDR as usize] = 0xAB;
+        update(&mut model, Msg::ToggleBanks);
+        assert!(model.cpu.banks.is_none());
+        assert!(model.error_msg.is_some());
+        let mut lines = vec!["0x00".to_string(); 0x80];
+        lines[0x7F] = "0xAB".to_string();
+        model.program_textarea = program_textarea(lines);
+        update(&mut model, Msg::Load);
+        assert_eq!(model.cpu.memory[BANK_SELECT_ADDR as usize], 0xAB);
+        update(&mut model, Msg::ToggleBanks);
+        assert!(model.cpu.banks.is_none());
+        model.cpu.memory[BANK_SELECT_ADDR as usize] = 0x00;
+        update(&mut model, Msg::ToggleBanks);
+        update(&mut model, Msg::Load);
+        assert_eq!(
+            model.error_msg.as_deref(),
+            Some("the byte at 0x7F is reserved for the bank-select register")
+        );
+        assert_eq!(model.cpu.memory[BANK_SELECT_ADDR as usize], 0x00);
+    }
+
+    #[test]
+    fn test_animation_msgs() {
+        let mut model = Model::default();
+        update(&mut model, Msg::ToggleAnimation);
+        assert_eq!(model.animation.unwrap().speed, DEFAULT_ANIMATION_SPEED);
+        assert!(model.tick_interval().is_some());
+        update(&mut model, Msg::TogglePause);
+        assert!(model.tick_interval().is_none());
+        update(&mut model, Msg::TogglePause);
+        update(&mut model, Msg::SlowDown);
+        update(&mut model, Msg::SlowDown);
+        update(&mut model, Msg::SlowDown);
+        assert_eq!(model.animation.unwrap().speed, 0);
+        update(&mut model, Msg::Tick);
+        assert_eq!(model.cpu.cycle, 1);
+        assert_eq!(model.modified_register, Some(0x04));
+        for _ in 0..ANIMATION_SPEEDS.len() {
+            update(&mut model, Msg::SpeedUp);
+        }
+        assert_eq!(model.animation.unwrap().speed, ANIMATION_SPEEDS.len() - 1);
+        update(&mut model, Msg::Tick);
+        assert!(model.cpu.halted);
+        assert_eq!(model.cpu.cycle, 3);
+        assert!(model.animation.is_none());
+        update(&mut model, Msg::ToggleAnimation);
+        assert!(model.animation.is_none());
+    }
+
+    #[test]
+    fn test_animation_keys() {
+        let mut model = Model::default();
+        let key = |c| KeyEvent::from(KeyCode::Char(c));
+        model.focus = Focus::Registers;
+        assert!(matches!(
+            on_key_event(&mut model, key('g')),
+            Some(Msg::ToggleAnimation)
+        ));
+        update(&mut model, Msg::ToggleAnimation);
+        assert!(matches!(
+            on_key_event(&mut model, key(' ')),
+            Some(Msg::TogglePause)
+        ));
+        model.focus = Focus::Program;
+        for c in ['g', ' ', '+', '-'] {
+            assert!(matches!(
+                on_key_event(&mut model, key(c)),
+                Some(Msg::KeyInput { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_animation_of_endless_loop() {
+        let mut model = Model::init(vec![0xB0, 0x02, 0x00]);
+        update(&mut model, Msg::ToggleAnimation);
+        update(&mut model, Msg::SpeedUp);
+        update(&mut model, Msg::Tick);
+        assert_eq!(model.cpu.cycle, 1);
+        update(&mut model, Msg::Load);
+        assert!(model.animation.is_none());
+    }
+
+    #[test]
+    fn test_cancel_run_msg() {
+        let mut model = Model::init(vec![0xB0, 0x02, 0x00]);
+        update(&mut model, Msg::Run);
+        assert!(model.tick_interval().is_some());
+        update(&mut model, Msg::Cycle);
+        update(&mut model, Msg::Load);
+        assert!(model.worker.is_some());
+        while model.worker.as_ref().unwrap().cycles() == 0 {
+            std::thread::yield_now();
+        }
+        update(&mut model, Msg::CancelRun);
+        while model.worker.is_some() {
+            std::thread::sleep(Duration::from_millis(1));
+            update(&mut model, Msg::Tick);
+        }
+        assert_eq!(model.error_msg.as_deref(), Some("run cancelled"));
+        assert!(model.cpu.cycle > 0);
+        assert!(!model.cpu.halted);
+    }
+
+    #[test]
+    fn test_cancel_key() {
+        let mut model = Model::init(vec![0xB0, 0x02, 0x00]);
+        model.focus = Focus::Program;
+        let x = KeyEvent::from(KeyCode::Char('x'));
+        assert!(matches!(
+            on_key_event(&mut model, x),
+            Some(Msg::KeyInput { .. })
+        ));
+        update(&mut model, Msg::Run);
+        assert!(matches!(on_key_event(&mut model, x), Some(Msg::CancelRun)));
+        update(&mut model, Msg::CancelRun);
+        while model.worker.is_some() {
+            std::thread::sleep(Duration::from_millis(1));
+            update(&mut model, Msg::Tick);
+        }
+    }
+
+    #[test]
+    fn test_finish_panicked_run() {
+        let mut model = Model::init(vec![0x14, 0x02, 0x34, 0x17, 0xC0, 0x00]);
+        update(&mut model, Msg::Cycle);
+        finish_run(&mut model, (None, Outcome::Panicked));
+        assert_eq!(
+            model.error_msg.as_deref(),
+            Some("the simulator crashed, the run was dropped")
+        );
+        assert_eq!(model.cpu.cycle, 1);
+    }
+
+    #[test]
+    fn test_save_msgs() {
+        let path = std::env::temp_dir().join(format!("volerup-save-{}.vole", std::process::id()));
+        let mut model = Model::default();
+        let key = |c| Event::Key(KeyEvent::from(KeyCode::Char(c)));
+        update(&mut model, Msg::FocusNext);
+        update(&mut model, Msg::KeyInput { key: key('0') });
+        assert!(model.dirty);
+
+        update(&mut model, Msg::Save);
+        assert_eq!(model.prompt, Some(Prompt::SaveAs { path: "".into() }));
+        for c in path.display().to_string().chars().chain(['x']) {
+            update(
+                &mut model,
+                Msg::PromptKey {
+                    code: KeyCode::Char(c),
+                },
+            );
+        }
+        update(
+            &mut model,
+            Msg::PromptKey {
+                code: KeyCode::Backspace,
+            },
+        );
+        update(
+            &mut model,
+            Msg::PromptKey {
+                code: KeyCode::Enter,
+            },
+        );
+        assert_eq!(model.prompt, None);
+        assert!(!model.dirty);
+        assert_eq!(model.path.as_ref(), Some(&path));
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text, "00x1402\n0x3417\n0xC000\n");
+
+        update(&mut model, Msg::KeyInput { key: key('1') });
+        update(&mut model, Msg::Exit);
+        assert_eq!(model.prompt, Some(Prompt::ConfirmQuit));
+        update(
+            &mut model,
+            Msg::PromptKey {
+                code: KeyCode::Char('n'),
+            },
+        );
+        assert!(model.running);
+        update(&mut model, Msg::Save);
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .starts_with("010x1402")
+        );
+        update(&mut model, Msg::KeyInput { key: key('2') });
+        update(&mut model, Msg::Exit);
+        update(
+            &mut model,
+            Msg::PromptKey {
+                code: KeyCode::Char('y'),
+            },
+        );
+        assert!(!model.running);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_browser_msgs() {
+        let dir = std::env::temp_dir().join(format!("volerup-open-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("halt.vole"), "// halt\nC0 00\n").unwrap();
+        let mut model = Model {
+            path: Some(dir.join("unsaved.vole")),
+            ..Default::default()
+        };
+        update(&mut model, Msg::Cycle);
+        model.dirty = true;
+
+        update(&mut model, Msg::OpenBrowser);
+        assert!(
+            model
+                .browser
+                .as_ref()
+                .unwrap()
+                .dir
+                .ends_with(dir.file_name().unwrap())
+        );
+        update(
+            &mut model,
+            Msg::BrowserKey {
+                code: KeyCode::Down,
+            },
+        );
+        update(
+            &mut model,
+            Msg::BrowserKey {
+                code: KeyCode::Enter,
+            },
+        );
+        assert!(matches!(model.prompt, Some(Prompt::ConfirmOpen { .. })));
+        update(
+            &mut model,
+            Msg::PromptKey {
+                code: KeyCode::Char('y'),
+            },
+        );
+        assert!(model.browser.is_none());
+        assert!(!model.dirty);
+        assert_eq!(model.program_textarea.lines(), ["// halt", "C0 00"]);
+        assert!(model.path.as_ref().unwrap().ends_with("halt.vole"));
+        assert_eq!(model.cpu.program_counter, 0);
+        assert_eq!(model.cpu.memory[0], 0xC0);
+
+        update(&mut model, Msg::OpenBrowser);
+        update(&mut model, Msg::BrowserKey { code: KeyCode::Esc });
+        assert!(model.browser.is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn type_value(model: &mut Model, text: &str) {
+        update(model, Msg::EditValue);
+        for code in text.chars().map(KeyCode::Char).chain([KeyCode::Enter]) {
+            update(model, Msg::PromptKey { code });
+        }
+    }
+
+    #[test]
+    fn test_edit_msgs() {
+        let mut model = Model::default();
+        model.pane_rows.set(10);
+        update(&mut model, Msg::FocusPrevious);
+        assert_eq!(model.focus, Focus::Registers);
+        for _ in 0..4 {
+            update(&mut model, Msg::ScrollDown);
+        }
+        type_value(&mut model, "0x80");
+        assert_eq!(model.prompt, None);
+        assert_eq!(model.cpu.registers[4], 0x80);
+        assert_eq!(model.modified_register, Some(4));
+
+        update(&mut model, Msg::FocusNext);
+        for _ in 0..0x17 {
+            update(&mut model, Msg::ScrollDown);
+        }
+        assert_eq!(model.selected_memory, 0x17);
+        assert_eq!(model.memory_scroll, 0x17 + 1 - 10);
+        type_value(&mut model, "-1.5");
+        assert_eq!(model.cpu.memory[0x17], 0xDC);
+        assert_eq!(model.modified_memory, Some(0x17));
+        type_value(&mut model, "300");
+        assert!(model.error_msg.is_some());
+        assert_eq!(model.cpu.memory[0x17], 0xDC);
+        assert_eq!(model.edits.len(), 2);
+
+        update(&mut model, Msg::ScrollUp);
+        update(&mut model, Msg::UndoEdit);
+        assert_eq!(model.cpu.memory[0x17], 0x00);
+        assert_eq!(model.selected_memory, 0x17);
+        update(&mut model, Msg::UndoEdit);
+        assert_eq!(model.cpu.registers[4], 0x00);
+        update(&mut model, Msg::UndoEdit);
+        assert_eq!(model.error_msg.as_deref(), Some("nothing to undo"));
+
+        update(&mut model, Msg::CycleCores);
+        update(&mut model, Msg::SelectCore { core: 1 });
+        update(&mut model, Msg::FocusPrevious);
+        type_value(&mut model, "7");
+        let multicore = model.multicore.as_ref().unwrap();
+        assert_eq!(multicore.cores[1].registers[4], 7);
+        assert_eq!(multicore.cores[0].registers[4], 0);
+    }
+
+    #[test]
+    fn test_number_format_msgs() {
+        let mut model = Model::default();
+        update(&mut model, Msg::NextNumberFormat);
+        assert_eq!(model.memory_format, NumberFormat::Unsigned);
+        assert_eq!(model.register_format, NumberFormat::Hex);
+        update(&mut model, Msg::FocusPrevious);
+        for _ in 0..5 {
+            update(&mut model, Msg::NextNumberFormat);
+        }
+        assert_eq!(model.register_format, NumberFormat::Float);
+        update(&mut model, Msg::NextNumberFormat);
+        assert_eq!(model.register_format, NumberFormat::Hex);
+
+        let formatted = [
+            NumberFormat::Hex,
+            NumberFormat::Unsigned,
+            NumberFormat::Signed,
+            NumberFormat::Binary,
+            NumberFormat::Ascii,
+            NumberFormat::Float,
+        ]
+        .map(|format| format.format(0xDC));
+        assert_eq!(
+            formatted,
+            ["0xDC", "220", "-36", "0b11011100", "\\xDC", "-1.5"]
+        );
+        assert_eq!(NumberFormat::Ascii.format(b'A'), "'A'");
+    }
+
+    #[test]
+    fn test_memory_grid_msgs() {
+        let mut model = Model::default();
+        update(&mut model, Msg::CursorRight);
+        assert_eq!(model.selected_memory, 0);
+        update(&mut model, Msg::ToggleMemoryGrid);
+        assert!(model.memory_grid);
+        update(&mut model, Msg::ScrollDown);
+        update(&mut model, Msg::CursorRight);
+        assert_eq!(model.selected_memory, 0x11);
+        update(&mut model, Msg::CursorLeft);
+        update(&mut model, Msg::CursorLeft);
+        update(&mut model, Msg::ScrollUp);
+        assert_eq!(model.selected_memory, 0x0F);
+        for _ in 0..16 {
+            update(&mut model, Msg::ScrollDown);
+        }
+        assert_eq!(model.selected_memory, 0xFF);
+        update(&mut model, Msg::CursorRight);
+        assert_eq!(model.selected_memory, 0xFF);
+        update(&mut model, Msg::ToggleMemoryGrid);
+        update(&mut model, Msg::ScrollUp);
+        assert_eq!(model.selected_memory, 0xFE);
+    }
+
+    #[test]
+    fn test_follow_pc_msgs() {
+        let mut program = vec![0x00; 0x40];
+        program.extend([0xB0, 0x00]);
+        let mut model = Model::init(program);
+        model.pane_rows.set(16);
+        model.cpu.program_counter = 0x40;
+        update(&mut model, Msg::Cycle);
+        assert_eq!(model.cpu.program_counter, 0x00);
+        assert_eq!(model.memory_scroll, 0);
+        model.cpu.program_counter = 0x40;
+        update(&mut model, Msg::ToggleFollowPc);
+        assert!(!model.follow_pc);
+        update(&mut model, Msg::ToggleFollowPc);
+        assert_eq!(model.memory_scroll, 0x41 + 1 - 16);
+        update(&mut model, Msg::ToggleFollowPc);
+        update(&mut model, Msg::Cycle);
+        assert_eq!(model.memory_scroll, 0x41 + 1 - 16);
+    }
+
+    #[test]
+    fn test_run_to_cursor_msg() {
+        let mut model = Model::init_from_source("0x2001\n0x2101\n0x5201 0x3217\n0xC000").unwrap();
+        let run_to = |model: &mut Model, row, col| {
+            model
+                .program_textarea
+                .move_cursor(tui_textarea::CursorMove::Jump(row, col));
+            update(model, Msg::RunToCursor);
+            while model.worker.is_some() {
+                std::thread::sleep(Duration::from_millis(1));
+                update(model, Msg::Tick);
+            }
+        };
+        run_to(&mut model, 2, 3);
+        assert_eq!(model.error_msg, None);
+        assert_eq!(model.cpu.program_counter, 0x04);
+        assert_eq!(
+            model
+                .source_map
+                .line_of_address(model.next_instruction_offset()),
+            Some(2)
+        );
+        run_to(&mut model, 2, 7);
+        assert_eq!(model.cpu.program_counter, 0x06);
+        assert_eq!(model.cpu.registers[2], 0x02);
+        assert!(!model.cpu.halted);
+        run_to(&mut model, 0, 0);
+        assert!(model.cpu.halted);
+        assert_eq!(model.cpu.memory[0x17], 0x02);
+    }
+
+    #[test]
+    fn test_large_program_uses_banks() {
+        let lines = vec!["0x00".to_string(); 256 + 5 * BANK_WINDOW_LEN];
+        let model = Model::init_from_source(&lines.join("\n")).unwrap();
+        assert_eq!(model.cpu.banks.as_ref().unwrap().banks.len(), 6);
+    }
+
     #[test]
     fn test_parse() {
         let lines: &[String] = &[
@@ -284,4 +1688,115 @@ mod tests {
         let r = parse_program_text(lines);
         assert!(r.is_err());
     }
+
+    #[test]
+    fn test_parse_diagnostics() {
+        let lines: &[String] = &[
+            "0x12 0x123 // three digits".to_string(),
+            "".to_string(),
+            "  zz 0x 0x12345 0xC000".to_string(),
+        ];
+        let diagnostics = parse_program_text(lines).unwrap_err();
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.line, d.columns.clone(), d.token.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (0, 5..10, "0x123"),
+                (2, 2..4, "zz"),
+                (2, 5..7, "0x"),
+                (2, 8..15, "0x12345"),
+            ]
+        );
+        assert_eq!(
+            diagnostics[1].to_string(),
+            "line 3, column 3: `zz` is not a number in hex notation"
+        );
+        assert_eq!(diagnostics[2].reason, "has no digits");
+        assert_eq!(diagnostics[3].reason, "has more than four digits");
+        assert!(diagnostics[0].reason.starts_with("is ambiguous"));
+        assert_eq!(diagnose_program(&lines[2..]).len(), 3);
+        assert_eq!(
+            parse_single_word("+F"),
+            Err("is not a number in hex notation")
+        );
+    }
+
+    #[test]
+    fn test_diagnostics_msgs() {
+        let mut model = Model::default();
+        let key = |c| Event::Key(KeyEvent::from(KeyCode::Char(c)));
+        update(&mut model, Msg::FocusNext);
+        update(&mut model, Msg::KeyInput { key: key('g') });
+        assert_eq!(model.diagnostics.len(), 1);
+        assert_eq!(model.diagnostics[0].token, "g0x1402");
+        update(&mut model, Msg::Load);
+        assert_eq!(
+            model.error_msg.as_deref(),
+            Some("line 1, column 1: `g0x1402` is not a number in hex notation")
+        );
+        update(&mut model, Msg::KeyInput { key: key(' ') });
+        update(&mut model, Msg::KeyInput { key: key('1') });
+        assert_eq!(model.diagnostics.len(), 2);
+        update(&mut model, Msg::Load);
+        assert!(
+            model
+                .error_msg
+                .as_ref()
+                .is_some_and(|msg| msg.ends_with("(and 1 more)"))
+        );
+
+        let backspace = Event::Key(KeyEvent::from(KeyCode::Backspace));
+        for _ in 0..3 {
+            update(
+                &mut model,
+                Msg::KeyInput {
+                    key: backspace.clone(),
+                },
+            );
+        }
+        assert!(model.diagnostics.is_empty());
+        update(&mut model, Msg::Load);
+        assert_eq!(model.error_msg, None);
+        assert_eq!(model.cpu.memory[0], 0x14);
+    }
+
+    #[test]
+    fn test_source_map() {
+        let lines: &[String] = &[
+            "// some comment".to_string(),
+            "0x1402 0x3417".to_string(),
+            "".to_string(),
+            "C0 00 // halt".to_string(),
+        ];
+        let map = SourceMap::new(lines);
+        assert_eq!(map.lines, vec![0..0, 0..4, 4..4, 4..6]);
+        assert_eq!(map.address_of_line(1), Some(0));
+        assert_eq!(map.address_of_line(2), None);
+        assert_eq!(map.address_of_line(3), Some(4));
+        assert_eq!(map.line_of_address(3), Some(1));
+        assert_eq!(map.line_of_address(5), Some(3));
+        assert_eq!(map.line_of_address(6), None);
+        assert_eq!(map.words[1].columns, 7..13);
+        assert_eq!(map.words[1].addresses, 2..4);
+        assert_eq!(map.address_at(1, 0), Some(0));
+        assert_eq!(map.address_at(1, 6), Some(0));
+        assert_eq!(map.address_at(1, 7), Some(2));
+        assert_eq!(map.address_at(3, 20), Some(5));
+        assert_eq!(map.address_at(2, 0), None);
+    }
+
+    #[test]
+    fn test_parse_with_map() {
+        let lines = ["  0x1402  // load".to_string(), "zz 0xC000".to_string()];
+        let (program, map) = parse_program_with_map(&lines[..1]).unwrap();
+        assert_eq!(program, vec![0x14, 0x02]);
+        assert_eq!(map.words[0].columns, 2..8);
+        assert!(parse_program_with_map(&lines).is_err());
+        let map = SourceMap::new(&lines);
+        assert_eq!(map.lines, vec![0..2, 2..4]);
+        assert_eq!(map.words[1].columns, 3..9);
+    }
 }
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use ratatui::{
     Frame,
     layout::{Constraint, Direction, Flex, Layout, Margin, Rect},
@@ -6,17 +8,32 @@ use ratatui::{
     widgets::{Block, Borders, Clear, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState},
 };
 
-use crate::model::{Focus, Model};
+use vole_rs::{
+    cache::{Cache, Replacement, WritePolicy},
+    mmu::{Mmu, PAGE_COUNT, PTE_FRAME, PTE_VALID},
+    multicore::{Multicore, Scheduler},
+    pipeline::{Pipeline, PipelineEvent},
+    vole::{Access, Cpu},
+};
+
+use crate::browser::FileBrowser;
+use crate::model::{EditTarget, Focus, Model, NumberFormat, Panel, Prompt};
+use crate::update::{ANIMATION_SPEEDS, Diagnostic, follow};
 
 fn default_style() -> Style {
     Style::default().fg(Color::Green)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn render_list(
     values: &[u8],
     title: &str,
+    format: NumberFormat,
     line_to_highlight: Option<usize>,
+    line_colors: &[Option<Color>],
+    backgrounds: &[Option<Color>],
     focused: bool,
+    selected: usize,
     vertical_scroll: usize,
     rect: Rect,
     frame: &mut Frame,
@@ -29,9 +46,17 @@ fn render_list(
         .enumerate()
         .map(|(idx, value)| {
             let s = if len < 100 {
-                format!("{:2} (0x{:02X}): 0x{:02X} ({:3})", idx, idx, value, value)
+                format!("{:2} (0x{:02X}): {}", idx, idx, format.format(*value))
             } else {
-                format!("{:3} (0x{:02X}): 0x{:02X} ({:3})", idx, idx, value, value)
+                format!("{:3} (0x{:02X}): {}", idx, idx, format.format(*value))
+            };
+            let style = match line_colors.get(idx) {
+                Some(Some(color)) => style.fg(*color),
+                _ => style,
+            };
+            let style = match backgrounds.get(idx) {
+                Some(Some(color)) => style.bg(*color),
+                _ => style,
             };
             let style = if let Some(idx_to_highlight) = line_to_highlight
                 && idx_to_highlight == idx
@@ -42,6 +67,11 @@ fn render_list(
             } else {
                 style
             };
+            let style = if focused && idx == selected {
+                style.add_modifier(Modifier::REVERSED)
+            } else {
+                style
+            };
             Line::from(s).style(style)
         })
         .collect::<Vec<Line>>();
@@ -63,7 +93,12 @@ fn render_list(
         .block(
             Block::bordered()
                 .style(style)
-                .title(format!(" {}{} ", title, if focused { "*" } else { "" }))
+                .title(format!(
+                    " {} ({}){} ",
+                    title,
+                    format.name(),
+                    if focused { "*" } else { "" }
+                ))
                 .title_style(block_style),
         );
 
@@ -78,6 +113,632 @@ fn render_list(
     );
 }
 
+/// Get the background colors of the shown memory cells,
+/// blue for the next instruction and magenta for the cell it loads from, stores to or jumps to.
+/// Addresses are translated by the MMU, nothing is marked in an unmapped page
+/// or in `hidden`, the cells of a bank that is shown but not mapped.
+fn instruction_backgrounds(
+    cpu: &Cpu,
+    memory: &[u8],
+    hidden: Option<Range<usize>>,
+) -> Vec<Option<Color>> {
+    let mut colors = vec![None; memory.len()];
+    let shown = |addr: Option<u8>| {
+        addr.map(usize::from)
+            .filter(|addr| !hidden.as_ref().is_some_and(|h| h.contains(addr)))
+    };
+    let pc = (cpu.program_counter % memory.len()) as u8;
+    let (Some(first), Some(second)) = (
+        shown(cpu.physical_addr(pc)),
+        shown(cpu.physical_addr(pc.wrapping_add(1))),
+    ) else {
+        return colors;
+    };
+    let instruction = u16::from_be_bytes([memory[first], memory[second]]);
+    let operand = Cpu::decode_instruction(instruction).and_then(|o| o.memory_address());
+    if let Some(addr) = shown(operand.and_then(|addr| cpu.physical_addr(addr))) {
+        colors[addr] = Some(Color::Magenta);
+    }
+    colors[first] = Some(Color::Blue);
+    colors[second] = Some(Color::Blue);
+    colors
+}
+
+/// Render the program text with the line numbers and the addresses of the lines in the gutter
+/// and the line of the next instruction on blue.
+fn render_program(model: &Model, rect: Rect, frame: &mut Frame) {
+    let textarea = &model.program_textarea;
+    let lines = textarea.lines();
+    let (cursor_row, cursor_col) = textarea.cursor();
+    let rows = rect.height as usize;
+    let top = follow(cursor_row, model.program_scroll.get(), rows);
+    model.program_scroll.set(top);
+
+    let source_map = &model.source_map;
+    let next_line = source_map.line_of_address(model.next_instruction_offset());
+    let selection = textarea.selection_range();
+    let number_width = lines.len().to_string().len();
+    let addr_width = if source_map.lines.last().is_some_and(|r| r.end > 256) {
+        4
+    } else {
+        2
+    };
+
+    let text = lines
+        .iter()
+        .enumerate()
+        .skip(top)
+        .take(rows)
+        .map(|(row, line)| {
+            let addr = source_map
+                .address_of_line(row)
+                .map_or(String::new(), |addr| {
+                    format!("{:0w$X}", addr, w = addr_width)
+                });
+            let gutter = format!(
+                "{:>nw$} {:>aw$} ",
+                row + 1,
+                addr,
+                nw = number_width,
+                aw = addr_width
+            );
+            let mut spans = vec![Span::styled(gutter, default_style())];
+
+            let mut chars = line
+                .chars()
+                .map(|c| if c == '\t' { ' ' } else { c })
+                .collect::<Vec<char>>();
+            if row == cursor_row {
+                // room for the cursor at the end of the line
+                chars.push(' ');
+            }
+            let mut segment = String::new();
+            let mut segment_style = Style::default();
+            for (col, c) in chars.into_iter().enumerate() {
+                let mut char_style = Style::default();
+                if model
+                    .diagnostics
+                    .iter()
+                    .any(|d| d.line == row && d.columns.contains(&col))
+                {
+                    char_style = char_style.fg(Color::Red).add_modifier(Modifier::UNDERLINED);
+                }
+                if let Some((start, end)) = selection
+                    && start <= (row, col)
+                    && (row, col) < end
+                {
+                    char_style = char_style.bg(Color::LightBlue);
+                }
+                if (row, col) == (cursor_row, cursor_col) {
+                    char_style = char_style.add_modifier(Modifier::REVERSED);
+                }
+                if char_style != segment_style && !segment.is_empty() {
+                    spans.push(Span::styled(std::mem::take(&mut segment), segment_style));
+                }
+                segment_style = char_style;
+                segment.push(c);
+            }
+            spans.push(Span::styled(segment, segment_style));
+
+            let mut line_style = Style::default();
+            if row == cursor_row {
+                line_style = line_style.add_modifier(Modifier::UNDERLINED);
+            }
+            if Some(row) == next_line {
+                line_style = line_style.bg(Color::Blue);
+            }
+            Line::from(spans).style(line_style)
+        })
+        .collect::<Vec<Line>>();
+
+    frame.render_widget(Paragraph::new(text).style(default_style()), rect);
+}
+
+/// The most diagnostics listed below the program at once.
+const MAX_DIAGNOSTICS_SHOWN: usize = 5;
+
+/// Render the list of the invalid words of the program text.
+fn render_diagnostics(diagnostics: &[Diagnostic], rect: Rect, frame: &mut Frame) {
+    let lines = diagnostics
+        .iter()
+        .take(MAX_DIAGNOSTICS_SHOWN)
+        .map(|d| Line::from(d.to_string()))
+        .collect::<Vec<Line>>();
+    let title = format!(" Diagnostics ({}) ", diagnostics.len());
+    let paragraph = Paragraph::new(lines)
+        .style(default_style().fg(Color::Red))
+        .block(Block::default().borders(Borders::ALL).title(title));
+    frame.render_widget(paragraph, rect);
+}
+
+/// The width of the memory pane showing the memory as a grid.
+const GRID_WIDTH: u16 = 71;
+
+/// Render the memory as a grid of 16 by 16 hex values with an ASCII column
+/// and the value of the selected cell in all number formats below.
+#[allow(clippy::too_many_arguments)]
+fn render_grid(
+    values: &[u8],
+    title: &str,
+    cell_to_highlight: Option<usize>,
+    cell_colors: &[Option<Color>],
+    backgrounds: &[Option<Color>],
+    focused: bool,
+    selected: usize,
+    rect: Rect,
+    frame: &mut Frame,
+) {
+    let style: Style = default_style();
+
+    let mut header = vec![Span::raw("  ")];
+    for col in 0..16 {
+        header.push(Span::raw(format!(" {:02X}", col)));
+    }
+    let mut lines = vec![Line::from(header)];
+    for (row, row_values) in values.chunks(16).enumerate() {
+        let mut spans = vec![Span::raw(format!("{:02X}", row * 16))];
+        let mut ascii = String::new();
+        for (col, value) in row_values.iter().copied().enumerate() {
+            let addr = row * 16 + col;
+            let mut cell_style = match cell_colors.get(addr) {
+                Some(Some(color)) => style.fg(*color),
+                _ => style,
+            };
+            if cell_to_highlight == Some(addr) {
+                cell_style = cell_style
+                    .add_modifier(Modifier::BOLD)
+                    .add_modifier(Modifier::UNDERLINED);
+            }
+            if let Some(Some(color)) = backgrounds.get(addr) {
+                cell_style = cell_style.bg(*color);
+            }
+            if focused && addr == selected {
+                cell_style = cell_style.add_modifier(Modifier::REVERSED);
+            }
+            spans.push(Span::raw(" "));
+            spans.push(Span::styled(format!("{:02X}", value), cell_style));
+            ascii.push(if value.is_ascii_graphic() || value == b' ' {
+                value as char
+            } else {
+                '.'
+            });
+        }
+        spans.push(Span::raw(format!("  {}", ascii)));
+        lines.push(Line::from(spans));
+    }
+    if let Some(value) = values.get(selected) {
+        lines.push(Line::from(""));
+        lines.push(Line::from(format!(
+            "0x{:02X}: 0x{:02X}, {}, signed {}, {}, {}, float {}",
+            selected,
+            value,
+            NumberFormat::Unsigned.format(*value),
+            NumberFormat::Signed.format(*value),
+            NumberFormat::Binary.format(*value),
+            NumberFormat::Ascii.format(*value),
+            NumberFormat::Float.format(*value),
+        )));
+    }
+
+    let block_style = if focused {
+        Style::default().add_modifier(Modifier::BOLD)
+    } else {
+        Style::default()
+    };
+    let paragraph = Paragraph::new(lines).block(
+        Block::bordered()
+            .style(style)
+            .title(format!(
+                " {} (grid){} ",
+                title,
+                if focused { "*" } else { "" }
+            ))
+            .title_style(block_style),
+    );
+    frame.render_widget(paragraph, rect);
+}
+
+/// The colors marking the cores.
+const CORE_COLORS: [Color; 4] = [Color::Cyan, Color::Yellow, Color::Magenta, Color::LightBlue];
+
+fn core_color(core: usize) -> Color {
+    CORE_COLORS[core % CORE_COLORS.len()]
+}
+
+fn scheduler_name(scheduler: Scheduler) -> &'static str {
+    match scheduler {
+        Scheduler::RoundRobin => "round-robin",
+        Scheduler::Random { .. } => "random",
+        Scheduler::Manual => "manual",
+    }
+}
+
+/// Render the registers and program counters of all cores side by side
+#[allow(clippy::too_many_arguments)]
+fn render_cores(
+    multicore: &Multicore,
+    format: NumberFormat,
+    line_to_highlight: Option<usize>,
+    focused: bool,
+    selected: usize,
+    vertical_scroll: usize,
+    rect: Rect,
+    frame: &mut Frame,
+) {
+    let style: Style = default_style();
+    let core_span = |core: usize, text: String| {
+        let style = style.fg(core_color(core));
+        let style = if multicore.last_stepped == Some(core) {
+            style.add_modifier(Modifier::BOLD)
+        } else {
+            style
+        };
+        Span::styled(text, style)
+    };
+
+    let mut header = vec![Span::raw("  ")];
+    for core in 0..multicore.cores.len() {
+        header.push(core_span(
+            core,
+            format!(" {:>w$}", format!("C{}", core), w = format.width()),
+        ));
+    }
+    let mut lines = vec![Line::from(header)];
+    for reg in 0..16 {
+        let name_style = if focused && reg == selected {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::default()
+        };
+        let mut spans = vec![Span::styled(format!("R{:X}", reg), name_style)];
+        for (core, cpu) in multicore.cores.iter().enumerate() {
+            let text = format!(
+                " {:>w$}",
+                format.format(cpu.registers[reg]),
+                w = format.width()
+            );
+            if multicore.last_stepped == Some(core) && line_to_highlight == Some(reg) {
+                spans.push(Span::styled(
+                    text,
+                    style
+                        .fg(core_color(core))
+                        .add_modifier(Modifier::BOLD)
+                        .add_modifier(Modifier::UNDERLINED),
+                ));
+            } else {
+                spans.push(core_span(core, text));
+            }
+        }
+        lines.push(Line::from(spans));
+    }
+    let mut pcs = vec![Span::raw("PC")];
+    for (core, cpu) in multicore.cores.iter().enumerate() {
+        pcs.push(core_span(core, format!(" {:02X}", cpu.program_counter)));
+    }
+    lines.push(Line::from(pcs));
+    let mut states = vec![Span::raw("  ")];
+    for (core, cpu) in multicore.cores.iter().enumerate() {
+        states.push(core_span(
+            core,
+            if cpu.halted { " H " } else { " R " }.to_string(),
+        ));
+    }
+    lines.push(Line::from(states));
+
+    let block_style = if focused {
+        Style::default().add_modifier(Modifier::BOLD)
+    } else {
+        Style::default()
+    };
+    let paragraph = Paragraph::new(lines)
+        .scroll((vertical_scroll as u16, 0))
+        .block(
+            Block::bordered()
+                .style(style)
+                .title(format!(
+                    " Cores ({}){} ",
+                    format.name(),
+                    if focused { "*" } else { "" }
+                ))
+                .title_style(block_style),
+        );
+    frame.render_widget(paragraph, rect);
+}
+
+fn access_name(access: Access) -> &'static str {
+    match access {
+        Access::Fetch => "fetch",
+        Access::Load => "load",
+        Access::Store => "store",
+    }
+}
+
+/// Render the page table, the last translation and the TLB of the MMU
+fn render_mmu(mmu: &Mmu, cpu: &Cpu, rect: Rect, frame: &mut Frame) {
+    let style: Style = default_style();
+    let highlighted_page = mmu.last_translation.map(|t| t.page);
+
+    let entry_span = |page: u8| {
+        let entry = cpu.memory[mmu.entry_addr(page) as usize];
+        let s = if entry & PTE_VALID != 0 {
+            format!("{:X} -> {:X}", page, entry & PTE_FRAME)
+        } else {
+            format!("{:X} -> -", page)
+        };
+        let style = if highlighted_page == Some(page) {
+            style
+                .add_modifier(Modifier::BOLD)
+                .add_modifier(Modifier::UNDERLINED)
+        } else {
+            style
+        };
+        Span::styled(format!("{:<10}", s), style)
+    };
+
+    let mut lines = vec![Line::from(format!(
+        "Page Table at 0x{:02X}:",
+        mmu.page_table_base
+    ))];
+    let half = PAGE_COUNT as u8 / 2;
+    for page in 0..half {
+        lines.push(Line::from(vec![
+            Span::raw(" "),
+            entry_span(page),
+            entry_span(page + half),
+        ]));
+    }
+
+    lines.push(Line::from(""));
+    lines.push(Line::from("Translation:"));
+    match (mmu.fault, mmu.last_translation) {
+        (Some(fault), _) => lines.push(Line::from(Span::styled(
+            format!(
+                " PAGE FAULT {} 0x{:02X} (page {:X})",
+                access_name(fault.access),
+                fault.virtual_addr,
+                fault.page
+            ),
+            Style::default().fg(Color::Red),
+        ))),
+        (None, Some(t)) => lines.push(Line::from(format!(
+            " {} 0x{:02X} -> 0x{:02X} ({})",
+            access_name(t.access),
+            t.virtual_addr,
+            t.physical_addr,
+            if t.tlb_hit { "TLB hit" } else { "TLB miss" }
+        ))),
+        (None, None) => lines.push(Line::from(" -")),
+    }
+
+    lines.push(Line::from(""));
+    lines.push(Line::from(format!(
+        "TLB: {} hits, {} misses",
+        mmu.tlb.hits, mmu.tlb.misses
+    )));
+    for entry in &mmu.tlb.entries {
+        lines.push(Line::from(format!(
+            " page {:X} -> frame {:X}",
+            entry.page, entry.frame
+        )));
+    }
+
+    let paragraph = Paragraph::new(lines)
+        .style(style)
+        .block(Block::default().borders(Borders::ALL).title(" MMU "));
+    frame.render_widget(paragraph, rect);
+}
+
+/// Render the cache lines, highlighting the ones accessed in the last cycle, and the statistics
+fn render_cache(cache: &Cache, cpu: &Cpu, rect: Rect, frame: &mut Frame) {
+    let style: Style = default_style();
+    let config = cache.config;
+
+    let geometry = if config.ways == 1 {
+        format!("direct-mapped, {} lines", config.sets)
+    } else {
+        format!("{}-way, {} sets", config.ways, config.sets)
+    };
+    let write_policy = match config.write_policy {
+        WritePolicy::WriteThrough => "WT",
+        WritePolicy::WriteBack => "WB",
+    };
+    let replacement = match config.replacement {
+        Replacement::Lru => "LRU",
+        Replacement::Fifo => "FIFO",
+    };
+    let mut lines = vec![
+        Line::from(format!(
+            "{}, {} B, {}, {}",
+            geometry, config.line_size, write_policy, replacement
+        )),
+        Line::from("set way V D addr  data"),
+    ];
+
+    for set in 0..config.sets {
+        for (way, line) in cache.set(set).iter().enumerate() {
+            let text = if line.valid {
+                let addr = cache.line_addr(set, line);
+                let data = (addr..addr + config.line_size)
+                    .map(|a| format!("{:02X}", cpu.memory.get(a).copied().unwrap_or(0)))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                format!(
+                    "{:3} {:3} 1 {} 0x{:02X}  {}",
+                    set,
+                    way,
+                    if line.dirty { 1 } else { 0 },
+                    addr,
+                    data
+                )
+            } else {
+                format!("{:3} {:3} 0 0 -", set, way)
+            };
+            let accessed = cache
+                .recent
+                .iter()
+                .filter(|a| a.set == set && a.way == Some(way))
+                .map(|a| a.hit)
+                .next_back();
+            let style = match accessed {
+                Some(true) => style.add_modifier(Modifier::BOLD),
+                Some(false) => style.fg(Color::Red).add_modifier(Modifier::BOLD),
+                None => style,
+            };
+            lines.push(Line::from(text).style(style));
+        }
+    }
+
+    lines.push(Line::from(""));
+    lines.push(Line::from("       hits misses evictions"));
+    for access in [Access::Fetch, Access::Load, Access::Store] {
+        let stats = cache.stats(access);
+        lines.push(Line::from(format!(
+            "{:5} {:5} {:6} {:9}",
+            access_name(access),
+            stats.hits,
+            stats.misses,
+            stats.evictions
+        )));
+    }
+    lines.push(Line::from(format!(
+        "writes to memory: {}",
+        cache.memory_writes
+    )));
+
+    lines.push(Line::from(""));
+    lines.push(Line::from("Last cycle:"));
+    for a in &cache.recent {
+        let (outcome, outcome_style) = if a.hit {
+            ("hit", style.add_modifier(Modifier::BOLD))
+        } else {
+            ("miss", style.fg(Color::Red).add_modifier(Modifier::BOLD))
+        };
+        lines.push(Line::from(vec![
+            Span::raw(format!(" {:5} 0x{:02X} ", access_name(a.access), a.addr)),
+            Span::styled(outcome, outcome_style),
+            Span::raw(if a.evicted { " (eviction)" } else { "" }),
+        ]));
+    }
+
+    let paragraph = Paragraph::new(lines)
+        .style(style)
+        .block(Block::default().borders(Borders::ALL).title(" Cache "));
+    frame.render_widget(paragraph, rect);
+}
+
+/// Render the pipeline diagram of the last clocks and the resulting CPI
+fn render_pipeline(pipeline: &Pipeline, cpu: &Cpu, rect: Rect, frame: &mut Frame) {
+    let style: Style = default_style();
+    let flushed_style = style.fg(Color::Red);
+
+    let mut lines = vec![
+        Line::from(format!(
+            "forwarding {}",
+            if pipeline.forwarding { "on" } else { "off" }
+        )),
+        Line::from(" clk IF ID EX ME WB"),
+    ];
+
+    let footer = vec![
+        Line::from(""),
+        Line::from(format!(
+            "clocks: {}, instructions: {}",
+            pipeline.clock, pipeline.retired
+        )),
+        Line::from(format!(
+            "CPI: {:.2}, unpipelined: {} cycles",
+            pipeline.cpi(),
+            cpu.cycle
+        )),
+        Line::from(format!(
+            "stalls: {}, flushed: {}, fwd: {}",
+            pipeline.stalls, pipeline.flushed, pipeline.forwards
+        )),
+    ];
+
+    let rows = (rect.height as usize).saturating_sub(2 + lines.len() + footer.len());
+    let start = pipeline.history.len().saturating_sub(rows);
+    for record in &pipeline.history[start..] {
+        let flushed = record
+            .events
+            .iter()
+            .any(|e| matches!(e, PipelineEvent::Flush { .. }));
+        let mut spans = vec![Span::raw(format!("{:4}", record.clock))];
+        for (idx, slot) in record.stages.iter().enumerate() {
+            let text = match slot {
+                Some(instr) => format!(" {:02X}", instr.addr),
+                None => " --".to_string(),
+            };
+            if flushed && idx < 2 && slot.is_some() {
+                spans.push(Span::styled(text, flushed_style));
+            } else {
+                spans.push(Span::raw(text));
+            }
+        }
+        for event in &record.events {
+            spans.push(Span::raw(match event {
+                PipelineEvent::Stall { reg } => format!(" stall R{:X}", reg),
+                PipelineEvent::Forward { reg } => format!(" fwd R{:X}", reg),
+                PipelineEvent::Flush { count } => format!(" flush {}", count),
+            }));
+        }
+        lines.push(Line::from(spans));
+    }
+    lines.extend(footer);
+
+    let paragraph = Paragraph::new(lines)
+        .style(style)
+        .block(Block::default().borders(Borders::ALL).title(" Pipeline "));
+    frame.render_widget(paragraph, rect);
+}
+
+/// Render the file browser with the entries of the directory and the preview of the selected file
+fn render_browser(browser: &FileBrowser, rect: Rect, frame: &mut Frame) {
+    let style: Style = default_style();
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" Open {} ", browser.dir.display()))
+        .title_bottom(" Enter: open, Backspace: parent directory, Esc: close ")
+        .style(style);
+    let inner = block.inner(rect);
+    frame.render_widget(Clear, rect);
+    frame.render_widget(block, rect);
+
+    let [entries_rect, preview_rect] =
+        Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(inner);
+    let rows = entries_rect.height as usize;
+    let start = (browser.selected + 1).saturating_sub(rows);
+    let entries = browser
+        .entries
+        .iter()
+        .enumerate()
+        .skip(start)
+        .map(|(idx, entry)| {
+            let name = if entry.is_dir {
+                format!("{}/", entry.name)
+            } else {
+                entry.name.clone()
+            };
+            if idx == browser.selected {
+                Line::from(name).style(style.add_modifier(Modifier::REVERSED))
+            } else {
+                Line::from(name)
+            }
+        })
+        .collect::<Vec<Line>>();
+    frame.render_widget(Paragraph::new(entries), entries_rect);
+
+    let preview = browser
+        .preview
+        .iter()
+        .map(|line| Line::from(line.as_str()))
+        .collect::<Vec<Line>>();
+    frame.render_widget(
+        Paragraph::new(preview).block(Block::default().borders(Borders::LEFT)),
+        preview_rect,
+    );
+}
+
 /// Render the TUI from the model
 pub(crate) fn view(model: &Model, frame: &mut Frame) {
     let style: Style = default_style();
@@ -107,7 +768,7 @@ pub(crate) fn view(model: &Model, frame: &mut Frame) {
             [
                 Constraint::Length(34),
                 Constraint::Length(24),
-                Constraint::Length(25),
+                Constraint::Length(if model.memory_grid { GRID_WIDTH } else { 25 }),
                 Constraint::Min(1),
             ]
             .as_ref(),
@@ -138,15 +799,36 @@ pub(crate) fn view(model: &Model, frame: &mut Frame) {
     let cycle_rect = left_chunks[1];
     let pc_rect = left_chunks[2];
     let instr_reg_rect = left_chunks[3];
+    let panel_rect = left_chunks[4];
     let regs_rect = main_chunks[1];
     let mem_rect = main_chunks[2];
-    let prog_rect = main_chunks[3];
+    let (prog_rect, diagnostics_rect) = if model.diagnostics.is_empty() {
+        (main_chunks[3], None)
+    } else {
+        let shown = model.diagnostics.len().min(MAX_DIAGNOSTICS_SHOWN) as u16;
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(shown + 2)].as_ref())
+            .split(main_chunks[3]);
+        (chunks[0], Some(chunks[1]))
+    };
+
+    model
+        .pane_rows
+        .set(regs_rect.height.saturating_sub(2) as usize);
 
+    let cpu = model.shown_cpu();
     let cpu_state_paragraph = {
-        let cpu_state = if model.cpu.halted {
-            "HALTED"
-        } else {
-            "RUNNING"
+        let cpu_state = if cpu.halted { "HALTED" } else { "RUNNING" };
+        let cpu_state = match &model.multicore {
+            Some(multicore) => format!(
+                "{} (core {}/{}, {})",
+                cpu_state,
+                multicore.selected,
+                multicore.cores.len(),
+                scheduler_name(multicore.scheduler)
+            ),
+            None => cpu_state.to_string(),
         };
         Paragraph::new(cpu_state)
             .style(style)
@@ -154,12 +836,31 @@ pub(crate) fn view(model: &Model, frame: &mut Frame) {
     };
     frame.render_widget(cpu_state_paragraph, cpu_state_rect);
 
-    let cycle_paragraph = Paragraph::new(model.cpu.cycle.to_string())
+    let cycle = match &model.multicore {
+        Some(multicore) => format!("{} (steps: {})", cpu.cycle, multicore.steps),
+        None => cpu.cycle.to_string(),
+    };
+    let cycle = match (&model.worker, model.animation) {
+        (Some(worker), _) => {
+            let done = match &model.multicore {
+                Some(multicore) => format!("steps: {}", multicore.steps as u64 + worker.cycles()),
+                None => (cpu.cycle as u64 + worker.cycles()).to_string(),
+            };
+            format!("{} (running, x: cancel)", done)
+        }
+        (None, Some(animation)) if animation.paused => format!("{} (paused)", cycle),
+        (None, Some(animation)) => {
+            let (interval, cycles) = ANIMATION_SPEEDS[animation.speed];
+            format!("{} ({} every {} ms)", cycle, cycles, interval.as_millis())
+        }
+        (None, None) => cycle,
+    };
+    let cycle_paragraph = Paragraph::new(cycle)
         .style(style)
         .block(Block::default().borders(Borders::ALL).title(" Cycle "));
     frame.render_widget(cycle_paragraph, cycle_rect);
 
-    let pc_paragraph = Paragraph::new(model.cpu.program_counter.to_string())
+    let pc_paragraph = Paragraph::new(cpu.program_counter.to_string())
         .style(style)
         .block(
             Block::default()
@@ -169,12 +870,12 @@ pub(crate) fn view(model: &Model, frame: &mut Frame) {
     frame.render_widget(pc_paragraph, pc_rect);
 
     let instr_reg_paragraph = {
-        let opcode = if let Some(opcode) = model.cpu.decode() {
+        let opcode = if let Some(opcode) = cpu.decode() {
             format!("({})", opcode)
         } else {
             "".to_string()
         };
-        let instr = format!("0x{:02X} {}", model.cpu.instruction_register, opcode);
+        let instr = format!("0x{:02X} {}", cpu.instruction_register, opcode);
         Paragraph::new(instr).style(style).block(
             Block::default()
                 .borders(Borders::ALL)
@@ -183,32 +884,157 @@ pub(crate) fn view(model: &Model, frame: &mut Frame) {
     };
     frame.render_widget(instr_reg_paragraph, instr_reg_rect);
 
-    render_list(
-        &model.cpu.registers,
-        "Registers",
-        model.modified_register,
-        model.focus == Focus::Registers,
-        model.registers_scroll,
-        regs_rect,
-        frame,
-    );
+    match model.shown_panel() {
+        Some(Panel::Mmu) if let Some(mmu) = &cpu.mmu => render_mmu(mmu, cpu, panel_rect, frame),
+        Some(Panel::Cache) if let Some(cache) = &cpu.cache => {
+            render_cache(cache, cpu, panel_rect, frame)
+        }
+        Some(Panel::Pipeline) if let Some(pipeline) = &model.pipeline => {
+            render_pipeline(pipeline, cpu, panel_rect, frame)
+        }
+        _ => (),
+    }
 
-    render_list(
-        &model.cpu.memory,
-        "Main Memory",
-        model.modified_memory,
-        model.focus == Focus::Memory,
-        model.memory_scroll,
-        mem_rect,
-        frame,
-    );
+    match &model.multicore {
+        Some(multicore) => {
+            render_cores(
+                multicore,
+                model.register_format,
+                model.modified_register,
+                model.focus == Focus::Registers,
+                model.selected_register,
+                model.registers_scroll,
+                regs_rect,
+                frame,
+            );
+            let writer_colors = multicore
+                .last_writer
+                .iter()
+                .map(|w| w.map(core_color))
+                .collect::<Vec<Option<Color>>>();
+            let backgrounds = instruction_backgrounds(cpu, multicore.memory(), None);
+            if model.memory_grid {
+                render_grid(
+                    multicore.memory(),
+                    "Main Memory",
+                    model.modified_memory,
+                    &writer_colors,
+                    &backgrounds,
+                    model.focus == Focus::Memory,
+                    model.selected_memory,
+                    mem_rect,
+                    frame,
+                );
+            } else {
+                render_list(
+                    multicore.memory(),
+                    "Main Memory",
+                    model.memory_format,
+                    model.modified_memory,
+                    &writer_colors,
+                    &backgrounds,
+                    model.focus == Focus::Memory,
+                    model.selected_memory,
+                    model.memory_scroll,
+                    mem_rect,
+                    frame,
+                );
+            }
+        }
+        None => {
+            render_list(
+                &model.cpu.registers,
+                "Registers",
+                model.register_format,
+                model.modified_register,
+                &[],
+                &[],
+                model.focus == Focus::Registers,
+                model.selected_register,
+                model.registers_scroll,
+                regs_rect,
+                frame,
+            );
+
+            let (memory, title, window_colors, hidden) =
+                match (&model.cpu.banks, model.shown_bank()) {
+                    (Some(banks), Some(shown)) => {
+                        let mut memory = model.cpu.memory;
+                        let window = banks.window();
+                        if let Some(bank) = model.cpu.bank(shown) {
+                            memory[window.clone()].copy_from_slice(bank);
+                        }
+                        let color = if shown == banks.current {
+                            None
+                        } else {
+                            Some(Color::DarkGray)
+                        };
+                        let colors = (0..memory.len())
+                            .map(|addr| if window.contains(&addr) { color } else { None })
+                            .collect::<Vec<Option<Color>>>();
+                        let title = format!("Memory bank {}/{}", shown, banks.banks.len());
+                        let hidden = (shown != banks.current).then_some(window);
+                        (memory, title, colors, hidden)
+                    }
+                    _ => (model.cpu.memory, "Main Memory".to_string(), vec![], None),
+                };
+            let backgrounds = instruction_backgrounds(&model.cpu, &memory, hidden);
+            if model.memory_grid {
+                render_grid(
+                    &memory,
+                    &title,
+                    model.modified_memory,
+                    &window_colors,
+                    &backgrounds,
+                    model.focus == Focus::Memory,
+                    model.selected_memory,
+                    mem_rect,
+                    frame,
+                );
+            } else {
+                render_list(
+                    &memory,
+                    &title,
+                    model.memory_format,
+                    model.modified_memory,
+                    &window_colors,
+                    &backgrounds,
+                    model.focus == Focus::Memory,
+                    model.selected_memory,
+                    model.memory_scroll,
+                    mem_rect,
+                    frame,
+                );
+            }
+        }
+    }
+
+    if model.follow_pc && !model.memory_grid {
+        let text = " follows PC ";
+        let follow_rect = Rect {
+            x: mem_rect.x + 1,
+            y: mem_rect.bottom().saturating_sub(1),
+            width: (text.len() as u16).min(mem_rect.width.saturating_sub(2)),
+            height: 1,
+        };
+        frame.render_widget(Paragraph::new(text).style(style), follow_rect);
+    }
 
     let editor_block = {
-        let (title, block_style) = if model.focus == Focus::Program {
-            (" Program* ", style.add_modifier(Modifier::BOLD))
+        let (focus_mark, block_style) = if model.focus == Focus::Program {
+            ("*", style.add_modifier(Modifier::BOLD))
         } else {
-            (" Program ", style)
+            ("", style)
         };
+        let file_name = model
+            .path
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map_or(String::new(), |name| {
+                format!(" - {}", name.to_string_lossy())
+            });
+        let dirty_mark = if model.dirty { " [+]" } else { "" };
+        let title = format!(" Program{}{}{} ", focus_mark, file_name, dirty_mark);
 
         Block::default()
             .borders(Borders::ALL)
@@ -221,11 +1047,43 @@ pub(crate) fn view(model: &Model, frame: &mut Frame) {
         .margin(1)
         .constraints([Constraint::Percentage(100)].as_ref())
         .split(prog_rect);
-    frame.render_widget(&model.program_textarea, editor_rect[0]);
+    render_program(model, editor_rect[0], frame);
+    if let Some(rect) = diagnostics_rect {
+        render_diagnostics(&model.diagnostics, rect, frame);
+    }
 
-    if let Some(msg) = model.error_msg {
+    if let Some(prompt) = &model.prompt {
+        let text = match prompt {
+            Prompt::SaveAs { path } => format!("Save as: {}_", path),
+            Prompt::ConfirmQuit => "Unsaved changes, quit anyway? (y/n)".to_string(),
+            Prompt::ConfirmOpen { path } => format!(
+                "Unsaved changes, open {} anyway? (y/n)",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            ),
+            Prompt::EditValue { target, text } => {
+                let name = match target {
+                    EditTarget::Register { core, reg } if model.multicore.is_some() => {
+                        format!("R{:X} of core {}", reg, core)
+                    }
+                    EditTarget::Register { reg, .. } => format!("R{:X}", reg),
+                    EditTarget::Memory {
+                        addr,
+                        bank: Some(bank),
+                    } => format!("memory cell 0x{:02X} of bank {}", addr, bank),
+                    EditTarget::Memory { addr, .. } => format!("memory cell 0x{:02X}", addr),
+                };
+                format!(
+                    "New value of {} (0x hex, 0b binary, decimal or float like 1.5): {}_",
+                    name, text
+                )
+            }
+        };
+        let prompt_rect = center_horizontal(footer_chunks[0], text.len() as u16);
+        let prompt_paragraph = Paragraph::new(text).style(Style::default().fg(Color::Yellow));
+        frame.render_widget(prompt_paragraph, prompt_rect);
+    } else if let Some(msg) = &model.error_msg {
         let style = Style::default().fg(Color::Red);
-        let error_msg_paragraph = Paragraph::new(msg).style(style);
+        let error_msg_paragraph = Paragraph::new(msg.as_str()).style(style);
         let error_msg_rect = center_horizontal(footer_chunks[0], msg.len() as u16);
         frame.render_widget(error_msg_paragraph, error_msg_rect);
     }
@@ -247,6 +1105,10 @@ pub(crate) fn view(model: &Model, frame: &mut Frame) {
     };
     frame.render_widget(help_message, help_msg_rect);
 
+    if let Some(browser) = &model.browser {
+        render_browser(browser, help_screen_chunk, frame);
+    }
+
     if model.show_help {
         let instructions_help = vec![
             Line::from("Vole Instructions:"),
@@ -266,6 +1128,65 @@ pub(crate) fn view(model: &Model, frame: &mut Frame) {
                 "0xBRXY - JUMP to instruction at memory cell XY if register R equals register 0",
             ),
             Line::from("0xC000 - HALT the execution"),
+            Line::from(
+                "0xDRXY - LOAD memory cell XY into register R and set it to 0x01 (cores only)",
+            ),
+            Line::from(""),
+            Line::from("Devices:"),
+            Line::from("m - attach or detach the MMU"),
+            Line::from("c - switch to the next cache configuration or detach the cache"),
+            Line::from("v - show the panel of the next attached device"),
+            Line::from("i - switch between single-cycle and pipelined execution"),
+            Line::from("f - switch forwarding in the pipeline on or off"),
+            Line::from("n - switch to the next number of cores sharing the memory"),
+            Line::from("s - switch to the next scheduler for the cores"),
+            Line::from("0-9 - select the core to show and to step with the manual scheduler"),
+            Line::from("b - attach or detach bank switching, [ / ] - browse the memory banks"),
+            Line::from(""),
+            Line::from("Animated run:"),
+            Line::from("g - start or stop the animated run, the CPU steps on a timer"),
+            Line::from("+ / - - do more or fewer cycles per second, Space - pause or resume"),
+            Line::from("P runs to completion in the background, x - cancel the run"),
+            Line::from(
+                "Ctrl+T - run till the word at the cursor of the editor is the next instruction",
+            ),
+            Line::from(""),
+            Line::from("Editing registers and memory:"),
+            Line::from("Enter - type a new value for the selected register or memory cell"),
+            Line::from("as 0x hex, 0b binary, decimal from -128 to 255 or a float like 1.5"),
+            Line::from("u - undo the last edit"),
+            Line::from(""),
+            Line::from("Number display:"),
+            Line::from(
+                "d - show the focused pane as hex, unsigned, signed, binary, ASCII or float",
+            ),
+            Line::from("h - show the memory as a list or as a grid of 16 by 16 cells"),
+            Line::from("← / → / ↑ / ↓ move the cursor through the cells of the grid"),
+            Line::from(
+                "The next instruction is shown on blue, the cell it loads, stores or jumps to on magenta",
+            ),
+            Line::from(
+                "a - switch scrolling the memory list along with the program counter on or off",
+            ),
+            Line::from(format!(
+                "Registers: {} - {}",
+                model.register_format.name(),
+                model.register_format.description()
+            )),
+            Line::from(format!(
+                "Memory: {} - {}",
+                model.memory_format.name(),
+                model.memory_format.description()
+            )),
+            Line::from(""),
+            Line::from("Files:"),
+            Line::from("Ctrl+S - save the program, Alt+S - save the program under another name"),
+            Line::from("Ctrl+O - browse the directories and open a program file"),
+            Line::from(""),
+            Line::from("MMU:"),
+            Line::from("A virtual address is split into a 4 bit page number and a 4 bit offset."),
+            Line::from("The page table at 0xF0 holds one byte per page: 0x80 marks it valid,"),
+            Line::from("the low nibble is the frame. Unmapped pages raise a page fault."),
         ];
         let help_paragraph = Paragraph::new(instructions_help)
             .style(style)
@@ -274,3 +1195,60 @@ pub(crate) fn view(model: &Model, frame: &mut Frame) {
         frame.render_widget(help_paragraph, help_screen_chunk);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instruction_backgrounds_at_end_of_memory() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0xFE] = 0x20;
+        cpu.memory[0xFF] = 0x05;
+        cpu.program_counter = 0xFE;
+        let backgrounds = instruction_backgrounds(&cpu, &cpu.memory, None);
+        assert_eq!(backgrounds[0xFE], Some(Color::Blue));
+        assert_eq!(backgrounds[0xFF], Some(Color::Blue));
+
+        assert!(cpu.cycle());
+        assert_eq!(cpu.program_counter, 0x00);
+        let backgrounds = instruction_backgrounds(&cpu, &cpu.memory, None);
+        assert_eq!(backgrounds[0x00], Some(Color::Blue));
+        assert_eq!(backgrounds[0xFE], None);
+
+        cpu.program_counter = 0x100;
+        let backgrounds = instruction_backgrounds(&cpu, &cpu.memory, None);
+        assert_eq!(backgrounds[0x00], Some(Color::Blue));
+        assert_eq!(backgrounds[0x01], Some(Color::Blue));
+    }
+
+    #[test]
+    fn test_instruction_backgrounds_with_mmu_and_banks() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0xF0] = PTE_VALID | 0x02;
+        cpu.memory[0xF1] = PTE_VALID | 0x03;
+        cpu.memory[0x20] = 0x14;
+        cpu.memory[0x21] = 0x10;
+        cpu.mmu = Some(Mmu::new(0xF0, 4));
+        let backgrounds = instruction_backgrounds(&cpu, &cpu.memory, None);
+        assert_eq!(backgrounds[0x20], Some(Color::Blue));
+        assert_eq!(backgrounds[0x21], Some(Color::Blue));
+        assert_eq!(backgrounds[0x30], Some(Color::Magenta));
+        assert_eq!(backgrounds[0x00], None);
+        assert_eq!(cpu.mmu.as_ref().unwrap().tlb.misses, 0);
+        cpu.program_counter = 0x40;
+        let backgrounds = instruction_backgrounds(&cpu, &cpu.memory, None);
+        assert!(backgrounds.iter().all(Option::is_none));
+
+        cpu.mmu = None;
+        cpu.program_counter = 0x00;
+        cpu.memory[0x00] = 0x14;
+        cpu.memory[0x01] = 0x90;
+        let backgrounds = instruction_backgrounds(&cpu, &cpu.memory, Some(0x80..0x100));
+        assert_eq!(backgrounds[0x00], Some(Color::Blue));
+        assert_eq!(backgrounds[0x90], None);
+        cpu.program_counter = 0x80;
+        let backgrounds = instruction_backgrounds(&cpu, &cpu.memory, Some(0x80..0x100));
+        assert!(backgrounds.iter().all(Option::is_none));
+    }
+}
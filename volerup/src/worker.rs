@@ -0,0 +1,204 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+
+use vole_rs::{multicore::Multicore, pipeline::Pipeline, vole::Cpu};
+
+use crate::update::MULTICORE_STEP_LIMIT;
+
+/// The number of cycles between two checks for cancellation and progress updates.
+const PROGRESS_INTERVAL: u64 = 4096;
+
+/// The machine a worker runs, handed back when the run ends.
+#[derive(Debug)]
+pub(crate) enum Machine {
+    Single {
+        cpu: Box<Cpu>,
+        pipeline: Option<Pipeline>,
+    },
+    Cores(Box<Multicore>),
+}
+
+/// How a run on a worker ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// The machine halted, false if on an illegal instruction or page fault.
+    Halted(bool),
+    /// The cores reached the step limit.
+    StepLimit,
+    /// The program counter reached the address to stop at.
+    Reached,
+    /// The run was cancelled.
+    Cancelled,
+    /// The simulator panicked, the machine is lost.
+    Panicked,
+}
+
+/// Runs a machine to completion on a background thread.
+#[derive(Debug)]
+pub(crate) struct Worker {
+    cancel: Arc<AtomicBool>,
+    cycles: Arc<AtomicU64>,
+    handle: JoinHandle<(Machine, Outcome)>,
+}
+
+impl Worker {
+    /// Start running the machine, till the program counter reaches `stop_at` if given.
+    /// With several cores the program counter of the selected core is checked.
+    pub(crate) fn spawn(machine: Machine, stop_at: Option<usize>) -> Self {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let cycles = Arc::new(AtomicU64::new(0));
+        let handle = {
+            let (cancel, cycles) = (Arc::clone(&cancel), Arc::clone(&cycles));
+            thread::spawn(move || run(machine, stop_at, &cancel, &cycles))
+        };
+        Worker {
+            cancel,
+            cycles,
+            handle,
+        }
+    }
+
+    /// The number of cycles done so far, updated every few thousand cycles.
+    pub(crate) fn cycles(&self) -> u64 {
+        self.cycles.load(Ordering::Relaxed)
+    }
+
+    /// Ask the worker to stop, it ends with [Outcome::Cancelled].
+    pub(crate) fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Wait for the run to end and get the machine back, there is none if the worker panicked.
+    pub(crate) fn join(self) -> (Option<Machine>, Outcome) {
+        match self.handle.join() {
+            Ok((machine, outcome)) => (Some(machine), outcome),
+            Err(_) => (None, Outcome::Panicked),
+        }
+    }
+}
+
+fn run(
+    mut machine: Machine,
+    stop_at: Option<usize>,
+    cancel: &AtomicBool,
+    cycles: &AtomicU64,
+) -> (Machine, Outcome) {
+    let mut done: u64 = 0;
+    let mut ok = true;
+    let outcome = loop {
+        let pc = match &machine {
+            Machine::Single { cpu, .. } => cpu.program_counter,
+            Machine::Cores(multicore) => multicore.cores[multicore.selected].program_counter,
+        };
+        if done > 0 && stop_at == Some(pc) {
+            break Outcome::Reached;
+        }
+        match &mut machine {
+            Machine::Single { cpu, .. } if cpu.halted => break Outcome::Halted(ok),
+            Machine::Single { cpu, pipeline } => {
+                ok = match pipeline {
+                    Some(pipeline) => pipeline.clock(cpu),
+                    None => cpu.cycle(),
+                };
+            }
+            Machine::Cores(_) if done == MULTICORE_STEP_LIMIT as u64 => break Outcome::StepLimit,
+            Machine::Cores(multicore) => match multicore.step() {
+                Some((_, r)) => ok &= r,
+                None => break Outcome::Halted(ok),
+            },
+        }
+        done += 1;
+        if done.is_multiple_of(PROGRESS_INTERVAL) {
+            cycles.store(done, Ordering::Relaxed);
+            if cancel.load(Ordering::Relaxed) {
+                break Outcome::Cancelled;
+            }
+        }
+    };
+    cycles.store(done, Ordering::Relaxed);
+    (machine, outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vole_rs::multicore::Scheduler;
+
+    #[test]
+    fn test_run_to_halt() {
+        let cpu = Cpu::init(&[0x14, 0x02, 0x34, 0x17, 0xC0, 0x00]);
+        let worker = Worker::spawn(
+            Machine::Single {
+                cpu: Box::new(cpu),
+                pipeline: None,
+            },
+            None,
+        );
+        let (machine, outcome) = worker.join();
+        let machine = machine.unwrap();
+        assert_eq!(outcome, Outcome::Halted(true));
+        let Machine::Single { cpu, .. } = machine else {
+            panic!("expected a single CPU");
+        };
+        assert_eq!(cpu.memory[0x17], 0x34);
+    }
+
+    #[test]
+    fn test_run_till_address() {
+        let cpu = Cpu::init(&[0x20, 0x01, 0x21, 0x01, 0xB0, 0x00, 0xC0, 0x00]);
+        let worker = Worker::spawn(
+            Machine::Single {
+                cpu: Box::new(cpu),
+                pipeline: None,
+            },
+            Some(0x02),
+        );
+        let (machine, outcome) = worker.join();
+        let machine = machine.unwrap();
+        assert_eq!(outcome, Outcome::Reached);
+        let Machine::Single { cpu, .. } = machine else {
+            panic!("expected a single CPU");
+        };
+        assert_eq!(cpu.cycle, 1);
+    }
+
+    #[test]
+    fn test_cancel_endless_loop() {
+        let cpu = Cpu::init(&[0xB0, 0x02, 0x00]);
+        let worker = Worker::spawn(
+            Machine::Single {
+                cpu: Box::new(cpu),
+                pipeline: None,
+            },
+            None,
+        );
+        while worker.cycles() == 0 {
+            thread::yield_now();
+        }
+        worker.cancel();
+        let cycles = worker.cycles();
+        let (machine, outcome) = worker.join();
+        let machine = machine.unwrap();
+        assert_eq!(outcome, Outcome::Cancelled);
+        let Machine::Single { cpu, .. } = machine else {
+            panic!("expected a single CPU");
+        };
+        assert!(cpu.cycle as u64 >= cycles);
+        assert!(!cpu.halted);
+    }
+
+    #[test]
+    fn test_panicking_run() {
+        let mut multicore = Multicore::new(&Cpu::new(), 2, Scheduler::RoundRobin);
+        multicore.selected = 2;
+        let worker = Worker::spawn(Machine::Cores(Box::new(multicore)), None);
+        let (machine, outcome) = worker.join();
+        assert!(machine.is_none());
+        assert_eq!(outcome, Outcome::Panicked);
+    }
+}